@@ -30,6 +30,8 @@ tinydancer verify --slot "$slot_number"
 
 tinydancer verify --slot "$slot_number" --sample-qty 20
 
+tinydancer verify --from "$((slot_number - 5))" --to "$slot_number" --output json || echo "some slots failed verification"
+
 echo "Continue? (y/n)"
 read cont
 if [ "$cont" != "y" ]; then
@@ -0,0 +1,784 @@
+//! Client config - `~/.config/tinydancer/config.json`
+//!
+//! The file holds named profiles (default, devnet, mainnet, ...), commands run
+//! against the profile picked by `--profile`, `TINYDANCER_PROFILE` or the
+//! file's `activeProfile`, in that order. `--config` or `TINYDANCER_CONFIG`
+//! point at another file.
+//!
+//! Every field of the profile can be overridden through a `TINYDANCER_<FIELD>`
+//! environment variable, e.g. `TINYDANCER_SAMPLE_QTY=20`, lists are comma
+//! separated. The resolved profile is validated before it is used.
+//!
+//! Files written before profiles existed are read as the default profile and
+//! rewritten in the new layout on the next `config set`.
+use crate::{
+    rpc_wrapper::{
+        sampling::{UnsampledSlotPolicy, DEFAULT_SAMPLE_WAIT_MS},
+        DEFAULT_CLEAN_INTERVAL_MS, DEFAULT_FANOUT_SIZE, DEFAULT_TX_BATCH_INTERVAL_MS,
+        DEFAULT_TX_BATCH_SIZE,
+    },
+    sampler::{
+        archive::ArchiveDurationUnit,
+        pipeline::{OverflowPolicy, PipelineConfig},
+        providers::DEFAULT_PROVIDER_OVERLAP,
+        shred_source::ShredSourceKind,
+        slot_updates::SamplingMode,
+        strategy::{DEFAULT_TARGET_CONFIDENCE, DEFAULT_WITHHOLDING_FRACTION},
+    },
+    supervisor::RestartPolicy,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_PROFILE: &str = "default";
+const ENV_PREFIX: &str = "TINYDANCER_";
+const CONFIG_PATH_ENV: &str = "TINYDANCER_CONFIG";
+const PROFILE_ENV: &str = "TINYDANCER_PROFILE";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("no config at {path}, initialise one first using: tinydancer config set")]
+    NotFound { path: PathBuf },
+    #[error("couldn't access config {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid config {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("no profile named {profile} in the config, profiles: {available}")]
+    UnknownProfile { profile: String, available: String },
+    #[error("invalid {field}: {reason}")]
+    Invalid { field: &'static str, reason: String },
+    #[error("invalid {var}: {reason}")]
+    Env { var: String, reason: String },
+    #[error("HOME is not set, pass the config path with --config")]
+    NoHome,
+}
+
+/// A profile, every tunable of the client, the flags of a command override it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ConfigSchema {
+    pub log_path: String,
+    /// Mainnet, Devnet, Localnet or an rpc url
+    pub cluster: String,
+    /// extra rpc providers shreds are sampled from
+    pub rpc_endpoints: Vec<String>,
+    /// trusted rpc the leader schedule is fetched from, without one it is cross-checked across the rpc providers
+    pub leader_schedule_endpoint: Option<String>,
+    /// minimum amount of shreds sampled per slot
+    pub sample_qty: usize,
+    /// probability with which a leader withholding shreds should be caught
+    pub target_confidence: f64,
+    /// fraction of a slot's shreds a malicious leader is assumed to withhold
+    pub withholding_fraction: f64,
+    /// commitment slots are sampled at
+    pub sampling_mode: SamplingMode,
+    /// where shreds are pulled from, the first is sampled and the rest cross-check it
+    pub shred_sources: Vec<ShredSourceKind>,
+    /// providers each sampled shred is requested from
+    pub provider_overlap: usize,
+    /// slots waiting to be sampled before the overflow policy kicks in
+    pub slot_queue_capacity: usize,
+    /// slots being sampled or verified at once
+    pub max_slots_in_flight: usize,
+    pub overflow_policy: OverflowPolicy,
+    /// slots sampled concurrently
+    pub sampling_workers: usize,
+    /// attempts at sampling a slot before giving up on it
+    pub max_sample_attempts: u8,
+    /// restarts in a row before a failing service takes the client down
+    pub max_restarts: u32,
+    /// rocks db path shreds and verdicts are archived in
+    pub archive_path: Option<String>,
+    pub shred_archive_duration: u64,
+    /// verdicts are small, they can be kept around longer than the shreds
+    pub verdict_archive_duration: u64,
+    pub archive_duration_unit: ArchiveDurationUnit,
+    /// rpc the transaction bridge forwards to, defaults to the cluster
+    pub rpc_addr: Option<String>,
+    /// websocket the transaction bridge follows blocks on, defaults to the cluster's
+    pub ws_addr: Option<String>,
+    /// address the lite rpc http server listens on
+    pub lite_rpc_http_addr: String,
+    /// address the lite rpc websocket server listens on
+    pub lite_rpc_ws_addr: String,
+    /// transactions forwarded per batch
+    pub tx_batch_size: usize,
+    /// leaders ahead transactions are sent to
+    pub fanout_size: u64,
+    pub tx_batch_interval_ms: u64,
+    /// how often confirmed transactions are dropped from the bridge
+    pub clean_interval_ms: u64,
+    /// keypair file the transaction bridge signs with, a fresh keypair if unset
+    pub identity_path: Option<String>,
+    /// what rpc responses for slots without a verdict get
+    pub unsampled_slot_policy: UnsampledSlotPolicy,
+    /// longest an rpc response waits for the sampler with the wait policy
+    pub sample_wait_ms: u64,
+    /// only these unknown rpc methods are forwarded upstream, all of them if empty
+    pub proxy_allow: Vec<String>,
+    /// rpc methods never forwarded upstream, `*` for all of them
+    pub proxy_deny: Vec<String>,
+    /// mark forwarded responses as unverified
+    pub proxy_annotate: bool,
+}
+
+impl Default for ConfigSchema {
+    fn default() -> Self {
+        Self {
+            log_path: String::from("/tmp/client.log"),
+            cluster: String::from("Localnet"),
+            rpc_endpoints: vec![],
+            leader_schedule_endpoint: None,
+            sample_qty: 10,
+            target_confidence: DEFAULT_TARGET_CONFIDENCE,
+            withholding_fraction: DEFAULT_WITHHOLDING_FRACTION,
+            sampling_mode: SamplingMode::default(),
+            shred_sources: vec![ShredSourceKind::Rpc],
+            provider_overlap: DEFAULT_PROVIDER_OVERLAP,
+            slot_queue_capacity: PipelineConfig::default().slot_queue_capacity,
+            max_slots_in_flight: PipelineConfig::default().max_slots_in_flight,
+            overflow_policy: PipelineConfig::default().overflow_policy,
+            sampling_workers: PipelineConfig::default().sampling_workers,
+            max_sample_attempts: PipelineConfig::default().max_sample_attempts,
+            max_restarts: RestartPolicy::default().max_restarts,
+            archive_path: None,
+            shred_archive_duration: 10000000,
+            verdict_archive_duration: 10000000,
+            archive_duration_unit: ArchiveDurationUnit::Slots,
+            rpc_addr: None,
+            ws_addr: None,
+            lite_rpc_http_addr: String::from("[::]:8890"),
+            lite_rpc_ws_addr: String::from("[::]:8891"),
+            tx_batch_size: DEFAULT_TX_BATCH_SIZE,
+            fanout_size: DEFAULT_FANOUT_SIZE,
+            tx_batch_interval_ms: DEFAULT_TX_BATCH_INTERVAL_MS,
+            clean_interval_ms: DEFAULT_CLEAN_INTERVAL_MS,
+            identity_path: None,
+            unsampled_slot_policy: UnsampledSlotPolicy::default(),
+            sample_wait_ms: DEFAULT_SAMPLE_WAIT_MS,
+            proxy_allow: vec![],
+            proxy_deny: vec![],
+            proxy_annotate: false,
+        }
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    url::Url::parse(url)
+        .map(|url| matches!(url.scheme(), "http" | "https"))
+        .unwrap_or(false)
+}
+
+fn is_ws_url(url: &str) -> bool {
+    url::Url::parse(url)
+        .map(|url| matches!(url.scheme(), "ws" | "wss"))
+        .unwrap_or(false)
+}
+
+impl ConfigSchema {
+    /// Defaults for a new profile, named after a cluster it starts out on that cluster
+    pub fn for_profile(name: &str) -> Self {
+        let cluster = match name.to_lowercase().as_str() {
+            "mainnet" => "Mainnet",
+            "devnet" => "Devnet",
+            "localnet" => "Localnet",
+            _ => return Self::default(),
+        };
+        Self {
+            cluster: String::from(cluster),
+            ..Self::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |field, reason: &str| {
+            Err(ConfigError::Invalid {
+                field,
+                reason: reason.to_string(),
+            })
+        };
+
+        if self.log_path.is_empty() {
+            return invalid("logPath", "must not be empty");
+        }
+        if !matches!(self.cluster.as_str(), "Mainnet" | "Devnet" | "Localnet")
+            && !is_http_url(&self.cluster)
+        {
+            return invalid(
+                "cluster",
+                "must be Mainnet, Devnet, Localnet or an http(s) url",
+            );
+        }
+        if let Some(endpoint) = self.rpc_endpoints.iter().find(|e| !is_http_url(e)) {
+            return invalid(
+                "rpcEndpoints",
+                &format!("{} is not an http(s) url", endpoint),
+            );
+        }
+        if matches!(&self.leader_schedule_endpoint, Some(endpoint) if !is_http_url(endpoint)) {
+            return invalid("leaderScheduleEndpoint", "must be an http(s) url");
+        }
+        if self.sample_qty == 0 {
+            return invalid("sampleQty", "must be at least 1");
+        }
+        if !(self.target_confidence > 0.0 && self.target_confidence < 1.0) {
+            return invalid("targetConfidence", "must be between 0 and 1");
+        }
+        if !(self.withholding_fraction > 0.0 && self.withholding_fraction <= 1.0) {
+            return invalid("withholdingFraction", "must be above 0 and at most 1");
+        }
+        if self.shred_sources.is_empty() {
+            return invalid("shredSources", "must name at least one source");
+        }
+        if self.provider_overlap == 0 {
+            return invalid("providerOverlap", "must be at least 1");
+        }
+        if self.slot_queue_capacity == 0 {
+            return invalid("slotQueueCapacity", "must be at least 1");
+        }
+        if self.max_slots_in_flight == 0 {
+            return invalid("maxSlotsInFlight", "must be at least 1");
+        }
+        if self.sampling_workers == 0 {
+            return invalid("samplingWorkers", "must be at least 1");
+        }
+        if self.max_sample_attempts == 0 {
+            return invalid("maxSampleAttempts", "must be at least 1");
+        }
+        if matches!(&self.archive_path, Some(path) if path.is_empty()) {
+            return invalid("archivePath", "must not be empty");
+        }
+        if self.shred_archive_duration == 0 {
+            return invalid("shredArchiveDuration", "must be at least 1");
+        }
+        if self.verdict_archive_duration == 0 {
+            return invalid("verdictArchiveDuration", "must be at least 1");
+        }
+        if matches!(&self.rpc_addr, Some(addr) if !is_http_url(addr)) {
+            return invalid("rpcAddr", "must be an http(s) url");
+        }
+        if matches!(&self.ws_addr, Some(addr) if !is_ws_url(addr)) {
+            return invalid("wsAddr", "must be a ws(s) url");
+        }
+        if self.lite_rpc_http_addr.parse::<SocketAddr>().is_err() {
+            return invalid("liteRpcHttpAddr", "must be a socket address like [::]:8890");
+        }
+        if self.lite_rpc_ws_addr.parse::<SocketAddr>().is_err() {
+            return invalid("liteRpcWsAddr", "must be a socket address like [::]:8891");
+        }
+        if self.tx_batch_size == 0 {
+            return invalid("txBatchSize", "must be at least 1");
+        }
+        if self.fanout_size == 0 {
+            return invalid("fanoutSize", "must be at least 1");
+        }
+        if self.clean_interval_ms == 0 {
+            return invalid("cleanIntervalMs", "must be at least 1");
+        }
+        if matches!(&self.identity_path, Some(path) if path.is_empty()) {
+            return invalid("identityPath", "must not be empty");
+        }
+        if self.unsampled_slot_policy == UnsampledSlotPolicy::Wait && self.sample_wait_ms == 0 {
+            return invalid("sampleWaitMs", "must be at least 1 with the wait policy");
+        }
+        if let Some(method) = self
+            .proxy_allow
+            .iter()
+            .find(|m| self.proxy_deny.contains(m))
+        {
+            return invalid("proxyAllow", &format!("{} is in proxyDeny too", method));
+        }
+        Ok(())
+    }
+}
+
+/// Fields `tinydancer config set` can change, unset ones are left alone
+#[derive(Debug, Default, clap::Args)]
+pub struct ConfigUpdate {
+    #[clap(long, required = false)]
+    pub log_path: Option<String>,
+    /// The cluster you want to run the client on (Mainnet, Localnet, Devnet, <custom-url>)
+    #[clap(long, short, required = false)]
+    pub cluster: Option<String>,
+    /// Extra rpc providers shreds are sampled from, replaces the configured ones
+    #[clap(long = "rpc-endpoint", required = false)]
+    pub rpc_endpoints: Vec<String>,
+    #[clap(long, required = false)]
+    pub leader_schedule_endpoint: Option<String>,
+    #[clap(long, required = false)]
+    pub sample_qty: Option<usize>,
+    #[clap(long, required = false)]
+    pub target_confidence: Option<f64>,
+    #[clap(long, required = false)]
+    pub withholding_fraction: Option<f64>,
+    #[clap(long, value_enum, required = false)]
+    pub sampling_mode: Option<SamplingMode>,
+    /// Where shreds are pulled from, replaces the configured ones
+    #[clap(long = "shred-source", value_enum, required = false)]
+    pub shred_sources: Vec<ShredSourceKind>,
+    #[clap(long, required = false)]
+    pub provider_overlap: Option<usize>,
+    #[clap(long, required = false)]
+    pub slot_queue_capacity: Option<usize>,
+    #[clap(long, required = false)]
+    pub max_slots_in_flight: Option<usize>,
+    #[clap(long, value_enum, required = false)]
+    pub overflow_policy: Option<OverflowPolicy>,
+    #[clap(long, required = false)]
+    pub sampling_workers: Option<usize>,
+    #[clap(long, required = false)]
+    pub max_sample_attempts: Option<u8>,
+    #[clap(long, required = false)]
+    pub max_restarts: Option<u32>,
+    #[clap(long, required = false)]
+    pub archive_path: Option<String>,
+    #[clap(long, required = false)]
+    pub shred_archive_duration: Option<u64>,
+    #[clap(long, required = false)]
+    pub verdict_archive_duration: Option<u64>,
+    #[clap(long, value_enum, required = false)]
+    pub archive_duration_unit: Option<ArchiveDurationUnit>,
+    #[clap(long, required = false)]
+    pub rpc_addr: Option<String>,
+    #[clap(long, required = false)]
+    pub ws_addr: Option<String>,
+    #[clap(long, required = false)]
+    pub lite_rpc_http_addr: Option<String>,
+    #[clap(long, required = false)]
+    pub lite_rpc_ws_addr: Option<String>,
+    #[clap(long, required = false)]
+    pub tx_batch_size: Option<usize>,
+    #[clap(long, required = false)]
+    pub fanout_size: Option<u64>,
+    #[clap(long, required = false)]
+    pub tx_batch_interval_ms: Option<u64>,
+    #[clap(long, required = false)]
+    pub clean_interval_ms: Option<u64>,
+    #[clap(long, required = false)]
+    pub identity_path: Option<String>,
+    #[clap(long, value_enum, required = false)]
+    pub unsampled_slot_policy: Option<UnsampledSlotPolicy>,
+    #[clap(long, required = false)]
+    pub sample_wait_ms: Option<u64>,
+    /// Unknown rpc methods forwarded upstream, replaces the configured ones
+    #[clap(long, required = false)]
+    pub proxy_allow: Vec<String>,
+    /// Rpc methods never forwarded upstream, replaces the configured ones
+    #[clap(long, required = false)]
+    pub proxy_deny: Vec<String>,
+    #[clap(long, required = false)]
+    pub proxy_annotate: Option<bool>,
+}
+
+impl ConfigUpdate {
+    pub fn apply(self, config: &mut ConfigSchema) {
+        macro_rules! set {
+            ($($field:ident),*) => {
+                $(if let Some(value) = self.$field {
+                    config.$field = value;
+                })*
+            };
+        }
+        set!(
+            log_path,
+            cluster,
+            sample_qty,
+            target_confidence,
+            withholding_fraction,
+            sampling_mode,
+            provider_overlap,
+            slot_queue_capacity,
+            max_slots_in_flight,
+            overflow_policy,
+            sampling_workers,
+            max_sample_attempts,
+            max_restarts,
+            shred_archive_duration,
+            verdict_archive_duration,
+            archive_duration_unit,
+            lite_rpc_http_addr,
+            lite_rpc_ws_addr,
+            tx_batch_size,
+            fanout_size,
+            tx_batch_interval_ms,
+            clean_interval_ms,
+            unsampled_slot_policy,
+            sample_wait_ms,
+            proxy_annotate
+        );
+        if !self.rpc_endpoints.is_empty() {
+            config.rpc_endpoints = self.rpc_endpoints;
+        }
+        if !self.shred_sources.is_empty() {
+            config.shred_sources = self.shred_sources;
+        }
+        if !self.proxy_allow.is_empty() {
+            config.proxy_allow = self.proxy_allow;
+        }
+        if !self.proxy_deny.is_empty() {
+            config.proxy_deny = self.proxy_deny;
+        }
+        if self.leader_schedule_endpoint.is_some() {
+            config.leader_schedule_endpoint = self.leader_schedule_endpoint;
+        }
+        if self.archive_path.is_some() {
+            config.archive_path = self.archive_path;
+        }
+        if self.rpc_addr.is_some() {
+            config.rpc_addr = self.rpc_addr;
+        }
+        if self.ws_addr.is_some() {
+            config.ws_addr = self.ws_addr;
+        }
+        if self.identity_path.is_some() {
+            config.identity_path = self.identity_path;
+        }
+    }
+}
+
+fn default_profile_name() -> String {
+    String::from(DEFAULT_PROFILE)
+}
+
+/// The config file as stored on disk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigFile {
+    #[serde(default = "default_profile_name")]
+    pub active_profile: String,
+    pub profiles: BTreeMap<String, ConfigSchema>,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        Self {
+            active_profile: default_profile_name(),
+            profiles: BTreeMap::from([(default_profile_name(), ConfigSchema::default())]),
+        }
+    }
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(ConfigError::NotFound {
+                    path: path.to_path_buf(),
+                })
+            }
+            Err(source) => {
+                return Err(ConfigError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+        let parse_error = |source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        let value: Value = serde_json::from_str(&contents).map_err(parse_error)?;
+        if value.get("profiles").is_some() {
+            serde_json::from_value(value).map_err(parse_error)
+        } else {
+            // a config from before profiles, it becomes the default profile
+            let legacy: ConfigSchema = serde_json::from_value(value).map_err(parse_error)?;
+            Ok(Self {
+                active_profile: default_profile_name(),
+                profiles: BTreeMap::from([(default_profile_name(), legacy)]),
+            })
+        }
+    }
+
+    /// Writes the config to a temporary file next to `path` and moves it over,
+    /// so a crash mid write never leaves a truncated config behind
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let io_error = |source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+
+        let contents = serde_json::to_string_pretty(self).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let tmp_path = path.with_extension("json.tmp");
+        let mut tmp_file = fs::File::create(&tmp_path).map_err(io_error)?;
+        tmp_file
+            .write_all(contents.as_bytes())
+            .and_then(|_| tmp_file.sync_all())
+            .map_err(io_error)?;
+        fs::rename(&tmp_path, path).map_err(io_error)
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&ConfigSchema, ConfigError> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| ConfigError::UnknownProfile {
+                profile: name.to_string(),
+                available: self.profiles.keys().cloned().collect::<Vec<_>>().join(", "),
+            })
+    }
+}
+
+/// Config file in use, `--config`, `TINYDANCER_CONFIG` or `~/.config/tinydancer/config.json`
+pub fn config_path(path: Option<PathBuf>) -> Result<PathBuf, ConfigError> {
+    if let Some(path) = path.or_else(|| std::env::var_os(CONFIG_PATH_ENV).map(PathBuf::from)) {
+        return Ok(path);
+    }
+    let home = std::env::var_os("HOME").ok_or(ConfigError::NoHome)?;
+    Ok(PathBuf::from(home).join(".config/tinydancer/config.json"))
+}
+
+/// Profile in use, `--profile`, `TINYDANCER_PROFILE` or the file's active profile
+pub fn profile_name(profile: Option<&str>, file: &ConfigFile) -> String {
+    profile
+        .map(String::from)
+        .or_else(|| std::env::var(PROFILE_ENV).ok())
+        .unwrap_or_else(|| file.active_profile.clone())
+}
+
+/// The profile to run with, environment overrides applied and validated
+pub fn load_config(path: &Path, profile: Option<&str>) -> Result<ConfigSchema, ConfigError> {
+    let file = ConfigFile::load(path)?;
+    let config = file.profile(&profile_name(profile, &file))?.clone();
+    let config = apply_env_overrides(config, |var| std::env::var(var).ok())?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// `sampleQty` -> `TINYDANCER_SAMPLE_QTY`
+fn env_var_name(field: &str) -> String {
+    let mut var = String::from(ENV_PREFIX);
+    for c in field.chars() {
+        if c.is_uppercase() {
+            var.push('_');
+        }
+        var.push(c.to_ascii_uppercase());
+    }
+    var
+}
+
+fn apply_env_overrides(
+    config: ConfigSchema,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<ConfigSchema, ConfigError> {
+    let Value::Object(mut fields) = serde_json::to_value(&config).expect("config serializes")
+    else {
+        unreachable!("config serializes to an object")
+    };
+
+    let names: Vec<String> = fields.keys().cloned().collect();
+    for name in names {
+        let var = env_var_name(&name);
+        let Some(raw) = lookup(&var) else {
+            continue;
+        };
+        let value = match &fields[&name] {
+            Value::Array(_) => Value::Array(
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| Value::String(item.to_string()))
+                    .collect(),
+            ),
+            Value::Number(_) | Value::Bool(_) => {
+                serde_json::from_str(&raw).unwrap_or(Value::String(raw))
+            }
+            _ => Value::String(raw),
+        };
+        fields.insert(name, value);
+
+        // checked one variable at a time so the error names the culprit
+        if let Err(e) = serde_json::from_value::<ConfigSchema>(Value::Object(fields.clone())) {
+            return Err(ConfigError::Env {
+                var,
+                reason: e.to_string(),
+            });
+        }
+    }
+    Ok(serde_json::from_value(Value::Object(fields)).expect("overrides were checked"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn env_overrides_replace_profile_fields() {
+        let vars = HashMap::from([
+            ("TINYDANCER_SAMPLE_QTY", "20"),
+            ("TINYDANCER_CLUSTER", "Devnet"),
+            (
+                "TINYDANCER_RPC_ENDPOINTS",
+                "https://a.example.com, https://b.example.com",
+            ),
+            ("TINYDANCER_ARCHIVE_PATH", "/tmp/archive"),
+        ]);
+        let config = apply_env_overrides(ConfigSchema::default(), |var| {
+            vars.get(var).map(|value| value.to_string())
+        })
+        .unwrap();
+
+        assert_eq!(config.sample_qty, 20);
+        assert_eq!(config.cluster, "Devnet");
+        assert_eq!(
+            config.rpc_endpoints,
+            vec!["https://a.example.com", "https://b.example.com"]
+        );
+        assert_eq!(config.archive_path.as_deref(), Some("/tmp/archive"));
+    }
+
+    #[test]
+    fn env_overrides_reach_the_sampling_tunables() {
+        let vars = HashMap::from([
+            ("TINYDANCER_TARGET_CONFIDENCE", "0.9"),
+            ("TINYDANCER_SAMPLING_MODE", "confirmedAndFinalized"),
+            ("TINYDANCER_SHRED_SOURCES", "rpc,gossip"),
+            ("TINYDANCER_OVERFLOW_POLICY", "coalesce"),
+            ("TINYDANCER_SAMPLING_WORKERS", "8"),
+            ("TINYDANCER_MAX_SAMPLE_ATTEMPTS", "3"),
+        ]);
+        let config = apply_env_overrides(ConfigSchema::default(), |var| {
+            vars.get(var).map(|value| value.to_string())
+        })
+        .unwrap();
+
+        assert_eq!(config.target_confidence, 0.9);
+        assert_eq!(config.sampling_mode, SamplingMode::ConfirmedAndFinalized);
+        assert_eq!(
+            config.shred_sources,
+            vec![ShredSourceKind::Rpc, ShredSourceKind::Gossip]
+        );
+        assert_eq!(config.overflow_policy, OverflowPolicy::Coalesce);
+        assert_eq!(config.sampling_workers, 8);
+        assert_eq!(config.max_sample_attempts, 3);
+    }
+
+    #[test]
+    fn bad_env_override_names_the_variable() {
+        let err = apply_env_overrides(ConfigSchema::default(), |var| {
+            (var == "TINYDANCER_SAMPLE_QTY").then(|| String::from("lots"))
+        })
+        .unwrap_err();
+        assert!(matches!(err, ConfigError::Env { var, .. } if var == "TINYDANCER_SAMPLE_QTY"));
+    }
+
+    #[test]
+    fn validation_rejects_bad_fields() {
+        let config = ConfigSchema {
+            cluster: String::from("not a cluster"),
+            ..ConfigSchema::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid {
+                field: "cluster",
+                ..
+            })
+        ));
+        let config = ConfigSchema {
+            ws_addr: Some(String::from("http://localhost:8900")),
+            ..ConfigSchema::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid {
+                field: "wsAddr",
+                ..
+            })
+        ));
+        let config = ConfigSchema {
+            proxy_allow: vec![String::from("getBalance")],
+            proxy_deny: vec![String::from("getBalance")],
+            ..ConfigSchema::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid {
+                field: "proxyAllow",
+                ..
+            })
+        ));
+        assert!(ConfigSchema::for_profile("mainnet").validate().is_ok());
+    }
+
+    #[test]
+    fn merged_flags_are_validated() {
+        let mut config = ConfigSchema::default();
+        ConfigUpdate {
+            target_confidence: Some(1.0),
+            ..ConfigUpdate::default()
+        }
+        .apply(&mut config);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid {
+                field: "targetConfidence",
+                ..
+            })
+        ));
+
+        let mut config = ConfigSchema::default();
+        ConfigUpdate {
+            sampling_workers: Some(0),
+            ..ConfigUpdate::default()
+        }
+        .apply(&mut config);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid {
+                field: "samplingWorkers",
+                ..
+            })
+        ));
+
+        let mut config = ConfigSchema::default();
+        ConfigUpdate {
+            ws_addr: Some(String::from("localhost:8900")),
+            ..ConfigUpdate::default()
+        }
+        .apply(&mut config);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid {
+                field: "wsAddr",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn legacy_config_becomes_the_default_profile() {
+        let path =
+            std::env::temp_dir().join(format!("tinydancer-config-{}.json", std::process::id()));
+        fs::write(&path, r#"{ "logPath": "/tmp/x.log", "cluster": "Devnet" }"#).unwrap();
+
+        let file = ConfigFile::load(&path).unwrap();
+        assert_eq!(file.active_profile, DEFAULT_PROFILE);
+        assert_eq!(file.profile(DEFAULT_PROFILE).unwrap().cluster, "Devnet");
+
+        file.save(&path).unwrap();
+        assert_eq!(ConfigFile::load(&path).unwrap(), file);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -11,13 +11,6 @@ macro_rules! convert_to_websocket {
     };
 }
 #[macro_export]
-macro_rules! block_on {
-    ($func:expr,$error:expr) => {
-        let rt = Runtime::new().unwrap();
-        rt.handle().block_on($func).expect($error);
-    };
-}
-#[macro_export]
 macro_rules! try_coerce_shred {
     ($response:expr) => {{
         let shred = if let Some(response) = $response.clone() {
@@ -32,23 +25,3 @@ macro_rules! try_coerce_shred {
         shred
     }};
 }
-
-#[macro_export]
-macro_rules! send_rpc_call {
-    ($url:expr, $body:expr) => {{
-        use reqwest::header::{ACCEPT, CONTENT_TYPE};
-        let req_client = reqwest::Client::new();
-        let res = req_client
-            .post($url)
-            .body($body)
-            .header(CONTENT_TYPE, "application/json")
-            .header(ACCEPT, "application/json")
-            .send()
-            .await
-            .expect("error")
-            .text()
-            .await
-            .expect("error");
-        res
-    }};
-}
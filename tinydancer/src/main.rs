@@ -30,25 +30,51 @@
 #![feature(mutex_unlock)]
 mod tinydancer;
 use crossterm::style::Stylize;
-use reqwest::header::{ACCEPT, CONTENT_TYPE};
-use sampler::{pull_and_verify_shreds, ArchiveConfig};
+use rpc_wrapper::BridgeConfig;
+use sampler::{
+    archive::{
+        archive_stats, convert_json_values, get_shred, scan_archive, ArchiveDurationUnit,
+        ARCHIVE_CFS,
+    },
+    leader_schedule::LeaderScheduleCache,
+    pipeline::{OverflowPolicy, PipelineConfig},
+    providers::ProviderReputation,
+    rpc_client::JsonRpcClient,
+    shred_source::{build_shred_sources, RpcProviders, ShredSourceKind},
+    slot_updates::SamplingMode,
+    strategy::SamplingStrategy,
+    verdicts::{get_verdicts, MAX_VERDICT_RANGE, VERDICT_CF},
+    ArchiveConfig, Verdict, SHRED_CF,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use solana_ledger::shred::ShredType;
+use solana_sdk::commitment_config::CommitmentLevel;
 use spinoff::{spinners, Color, Spinner};
 use std::{
     f32::consts::E,
     fs::{self, File, OpenOptions},
-    io,
+    io::{self, Write},
+    net::SocketAddr,
     path::{Path, PathBuf},
+    sync::Arc,
     thread::sleep,
     time::Duration,
 };
+use supervisor::RestartPolicy;
 use tinydancer::{endpoint, Cluster, TinyDancer, TinyDancerConfig};
+use verify::{SlotVerifier, VerifyOutput};
+mod config;
 mod macros;
 use colored::Colorize;
+use config::{
+    config_path, profile_name, ConfigError, ConfigFile, ConfigSchema, ConfigUpdate, DEFAULT_PROFILE,
+};
 mod rpc_wrapper;
 mod sampler;
+mod supervisor;
 mod ui;
+mod verify;
 
 use anyhow::{anyhow, Result};
 use clap::{ArgGroup, Parser, Subcommand, *};
@@ -58,6 +84,14 @@ use tracing_subscriber;
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    /// Config file to use, defaults to $TINYDANCER_CONFIG or ~/.config/tinydancer/config.json
+    #[clap(long, global = true, required = false)]
+    config: Option<PathBuf>,
+
+    /// Config profile to use, defaults to $TINYDANCER_PROFILE or the active profile
+    #[clap(long, global = true, required = false)]
+    profile: Option<String>,
+
     /// Subcommands to run
     #[clap(subcommand)]
     command: Commands,
@@ -75,25 +109,184 @@ pub enum Commands {
         #[clap(long, short, default_value_t = false)]
         tui_monitor: bool,
 
-        /// Amount of shreds you want to sample per slot
-        #[clap(long, short, default_value_t = 10)]
-        sample_qty: usize,
-        /// Rocks db path for storing shreds
+        /// Minimum amount of shreds you want to sample per slot, defaults to the config's sampleQty
+        #[clap(long, short, required = false)]
+        sample_qty: Option<usize>,
+
+        /// Probability with which a leader withholding shreds should be caught, defaults to the config's targetConfidence
+        #[clap(long, required = false)]
+        target_confidence: Option<f64>,
+
+        /// Fraction of a slot's shreds a malicious leader is assumed to withhold, defaults to the config's withholdingFraction
+        #[clap(long, required = false)]
+        withholding_fraction: Option<f64>,
+        /// Rocks db path for storing shreds, defaults to the config's archivePath
         #[clap(required = false)]
         archive_path: Option<String>,
 
-        /// Duration after which shreds will be purged
-        #[clap(required = false, default_value_t = 10000000)]
-        shred_archive_duration: u64,
+        /// Duration after which shreds will be purged, defaults to the config's shredArchiveDuration
+        #[clap(required = false)]
+        shred_archive_duration: Option<u64>,
+
+        /// Duration after which verdicts will be purged, defaults to the config's verdictArchiveDuration
+        #[clap(long, required = false)]
+        verdict_archive_duration: Option<u64>,
+
+        /// Unit the archive duration is measured in, defaults to the config's archiveDurationUnit
+        #[clap(long, value_enum, required = false)]
+        archive_duration_unit: Option<ArchiveDurationUnit>,
+
+        /// Commitment slots are sampled at, confirmed-and-finalized samples every slot twice
+        #[clap(long, value_enum, required = false)]
+        sampling_mode: Option<SamplingMode>,
+
+        /// Extra rpc providers to sample from, on top of the cluster and the configured ones
+        #[clap(long = "rpc-endpoint", required = false)]
+        rpc_endpoints: Vec<String>,
+
+        /// How many providers each sampled shred is requested from
+        #[clap(long, required = false)]
+        provider_overlap: Option<usize>,
+
+        /// Most slots being sampled or verified at once
+        #[clap(long, required = false)]
+        max_slots_in_flight: Option<usize>,
+
+        /// Most slots waiting to be sampled before the overflow policy kicks in
+        #[clap(long, required = false)]
+        slot_queue_capacity: Option<usize>,
+
+        /// What to do with new slots once the slot queue is full
+        #[clap(long, value_enum, required = false)]
+        overflow_policy: Option<OverflowPolicy>,
+
+        /// Slots sampled concurrently
+        #[clap(long, required = false)]
+        sampling_workers: Option<usize>,
+
+        /// Attempts at sampling a slot before giving up on it
+        #[clap(long, required = false)]
+        max_sample_attempts: Option<u8>,
+
+        /// Where to pull shreds from, the first source is sampled and the rest are cross-checked against it
+        #[clap(long = "shred-source", value_enum, required = false)]
+        shred_sources: Vec<ShredSourceKind>,
+
+        /// Gossip entrypoint (host:port) used by the gossip shred source
+        #[clap(long, required = false)]
+        gossip_entrypoint: Option<String>,
+
+        /// Trusted rpc the leader schedule is fetched from, without one it is cross-checked across the rpc providers
+        #[clap(long, required = false)]
+        leader_schedule_endpoint: Option<String>,
+
+        /// Restarts in a row before a failing service takes the whole client down
+        #[clap(long, required = false)]
+        max_restarts: Option<u32>,
+
+        #[clap(flatten)]
+        bridge: rpc_wrapper::cli::Args,
     },
-    /// Verify the samples for a single slot
+    /// Verify the samples for a single slot, a range of slots or new slots as they arrive
     Verify {
-        #[clap(long, required = false, default_value = "0")]
-        slot: usize,
+        #[clap(long, required = false, conflicts_with_all = &["from", "to"])]
+        slot: Option<u64>,
+
+        /// First slot of the range, defaults to the current slot with `--follow`
+        #[clap(long, required = false)]
+        from: Option<u64>,
+
+        /// Last slot of the range, defaults to `from`, or never stop with `--follow`
+        #[clap(long, required = false)]
+        to: Option<u64>,
+
+        /// Keep verifying new slots as the cluster produces them
+        #[clap(long, default_value_t = false)]
+        follow: bool,
+
+        /// How each verified slot is printed, json and csv print one record per slot
+        #[clap(long, value_enum, default_value = "text")]
+        output: VerifyOutput,
+
+        /// Minimum amount of shreds to sample, defaults to the config's sampleQty
+        #[clap(long, required = false)]
+        sample_qty: Option<usize>,
+
+        /// Probability with which a leader withholding shreds should be caught, defaults to the config's targetConfidence
+        #[clap(long, required = false)]
+        target_confidence: Option<f64>,
+
+        /// Fraction of a slot's shreds a malicious leader is assumed to withhold, defaults to the config's withholdingFraction
+        #[clap(long, required = false)]
+        withholding_fraction: Option<f64>,
+
+        /// Where to pull shreds from, the first source is sampled and the rest are cross-checked against it
+        #[clap(long = "shred-source", value_enum, required = false)]
+        shred_sources: Vec<ShredSourceKind>,
+
+        /// Rocks db path, needed by the archive shred source
+        #[clap(long, required = false)]
+        archive_path: Option<String>,
+
+        /// Gossip entrypoint (host:port) used by the gossip shred source
+        #[clap(long, required = false)]
+        gossip_entrypoint: Option<String>,
+
+        /// Trusted rpc the leader schedule is fetched from, without one it is cross-checked across the rpc providers
+        #[clap(long, required = false)]
+        leader_schedule_endpoint: Option<String>,
 
-        #[clap(long, required = false, default_value = "10")]
-        sample_qty: usize,
+        /// Also check every sampled FEC set can be reconstructed through erasure recovery
+        #[clap(long, default_value_t = false)]
+        deep: bool,
+
+        /// Commitment the shreds are requested at (processed, confirmed or finalized)
+        #[clap(long, default_value = "confirmed")]
+        commitment: CommitmentLevel,
+
+        /// Extra rpc providers to sample from, on top of the cluster and the configured ones
+        #[clap(long = "rpc-endpoint", required = false)]
+        rpc_endpoints: Vec<String>,
+
+        /// How many providers each sampled shred is requested from
+        #[clap(long, required = false)]
+        provider_overlap: Option<usize>,
+    },
+    /// Show the reputation of the rpc providers a running client samples from
+    Providers {
+        /// Rpc address of the running client
+        #[clap(long, default_value = "http://0.0.0.0:8890")]
+        client_url: String,
+
+        /// Print the reputations as JSON
+        #[clap(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Show the recorded sampling verdicts for a range of slots
+    Verdicts {
+        /// Rocks db path of the client, defaults to the config's archivePath
+        #[clap(long, required = false)]
+        archive_path: Option<String>,
+
+        /// First slot of the range
+        #[clap(long)]
+        from: u64,
+
+        /// Last slot of the range, defaults to `from`
+        #[clap(long, required = false)]
+        to: Option<u64>,
+
+        /// Confidence a valid slot has to reach to count as sampled, defaults to the config's targetConfidence
+        #[clap(long, required = false)]
+        target_confidence: Option<f64>,
+
+        /// Print the verdicts as JSON
+        #[clap(long, default_value_t = false)]
+        json: bool,
     },
+    /// Inspect the shred archive
+    #[clap(subcommand)]
+    Archive(ArchiveSubcommands),
     /// Stream the client logs to your terminal
     Logs {
         #[clap(long, required = false, default_value = "/tmp/client.log")]
@@ -108,26 +301,80 @@ pub enum Commands {
 
 #[derive(Debug, Subcommand)]
 pub enum ConfigSubcommands {
-    Set {
-        #[clap(long, required = false, default_value = "/tmp/client.log")]
-        log_path: String,
-        /// The cluster you want to run the client on (Mainnet, Localnet,Devnet, <custom-url>)
-        #[clap(long, short, required = false, default_value = "Localnet")]
-        cluster: String,
-    },
+    /// Change fields of a profile, creating it if it doesn't exist yet
+    Set(ConfigUpdate),
+    /// Print the profile in use, environment overrides applied
     Get,
+    /// Make a profile the active one
+    Use { profile: String },
+    /// List the profiles in the config
+    Profiles,
 }
 
-pub fn get_config_file() -> Result<ConfigSchema> {
-    let home_path = std::env::var("HOME")?;
-    let path = home_path + "/.config/tinydancer/config.json";
-    let config_str = std::fs::read_to_string(path)?;
-    Ok(serde_json::from_str::<ConfigSchema>(&config_str)?)
+#[derive(Debug, Subcommand)]
+pub enum ArchiveSubcommands {
+    /// List the archived shreds of a slot range
+    Ls {
+        /// Rocks db path of the client, defaults to the config's archivePath
+        #[clap(long, required = false)]
+        archive_path: Option<String>,
+        #[clap(long)]
+        from: u64,
+        /// Last slot of the range, defaults to `from`
+        #[clap(long, required = false)]
+        to: Option<u64>,
+    },
+    /// Print a single archived shred
+    Get {
+        /// Rocks db path of the client, defaults to the config's archivePath
+        #[clap(long, required = false)]
+        archive_path: Option<String>,
+        #[clap(long)]
+        slot: u64,
+        #[clap(long)]
+        index: u32,
+        /// Look up a coding shred instead of a data shred
+        #[clap(long, default_value_t = false)]
+        coding: bool,
+    },
+    /// Export the archived shreds of a slot range as JSON lines
+    Export {
+        /// Rocks db path of the client, defaults to the config's archivePath
+        #[clap(long, required = false)]
+        archive_path: Option<String>,
+        #[clap(long)]
+        from: u64,
+        /// Last slot of the range, defaults to `from`
+        #[clap(long, required = false)]
+        to: Option<u64>,
+        /// File to write to, defaults to stdout
+        #[clap(long, required = false)]
+        output: Option<String>,
+    },
+    /// Count the archived shreds of a slot range
+    Stats {
+        /// Rocks db path of the client, defaults to the config's archivePath
+        #[clap(long, required = false)]
+        archive_path: Option<String>,
+        #[clap(long, default_value_t = 0)]
+        from: u64,
+        #[clap(long, default_value_t = u64::MAX)]
+        to: u64,
+    },
+    /// Rewrite shreds stored as JSON in the binary format, the client must be stopped
+    Convert {
+        /// Rocks db path of the client, defaults to the config's archivePath
+        #[clap(long, required = false)]
+        archive_path: Option<String>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    let config_path = config_path(args.config)?;
+    let profile = args.profile;
+    let load_config = || config::load_config(&config_path, profile.as_deref());
 
     match args.command {
         Commands::Logs { log_path } => {
@@ -141,163 +388,411 @@ async fn main() -> Result<()> {
         Commands::Start {
             enable_ui_service,
             sample_qty,
+            target_confidence,
+            withholding_fraction,
             archive_path,
             shred_archive_duration,
+            verdict_archive_duration,
+            archive_duration_unit,
+            sampling_mode,
+            rpc_endpoints,
+            provider_overlap,
+            max_slots_in_flight,
+            slot_queue_capacity,
+            overflow_policy,
+            sampling_workers,
+            max_sample_attempts,
             tui_monitor,
+            shred_sources,
+            gossip_entrypoint,
+            leader_schedule_endpoint,
+            max_restarts,
+            bridge,
         } => {
-            let config_file =
-                get_config_file().map_err(|_| anyhow!("tinydancer config not set"))?;
+            let mut config_file = load_config()?;
+            ConfigUpdate {
+                leader_schedule_endpoint,
+                sample_qty,
+                target_confidence,
+                withholding_fraction,
+                sampling_mode,
+                shred_sources,
+                provider_overlap,
+                slot_queue_capacity,
+                max_slots_in_flight,
+                overflow_policy,
+                sampling_workers,
+                max_sample_attempts,
+                max_restarts,
+                archive_path,
+                shred_archive_duration,
+                verdict_archive_duration,
+                archive_duration_unit,
+                ..ConfigUpdate::default()
+            }
+            .apply(&mut config_file);
+            config_file.rpc_endpoints.extend(rpc_endpoints);
+            bridge.apply(&mut config_file);
+            // the flags are only checked once merged into the profile
+            config_file.validate()?;
+
+            let bridge = BridgeConfig::from(&config_file);
             let config = TinyDancerConfig {
                 enable_ui_service,
                 rpc_endpoint: get_cluster(config_file.cluster),
-                sample_qty,
+                sample_qty: config_file.sample_qty,
+                target_confidence: config_file.target_confidence,
+                withholding_fraction: config_file.withholding_fraction,
                 tui_monitor,
                 log_path: config_file.log_path,
-                archive_config: {
-                    archive_path
-                        .map(|path| {
-                            Ok(ArchiveConfig {
-                                shred_archive_duration,
-                                archive_path: path,
-                            })
-                        })
-                        .unwrap_or(Err(anyhow!("shred path not provided...")))?
+                archive_config: config_file
+                    .archive_path
+                    .map(|path| ArchiveConfig {
+                        shred_archive_duration: config_file.shred_archive_duration,
+                        verdict_archive_duration: config_file.verdict_archive_duration,
+                        duration_unit: config_file.archive_duration_unit,
+                        archive_path: path,
+                    })
+                    .ok_or_else(|| anyhow!("shred path not provided..."))?,
+                shred_sources: config_file.shred_sources,
+                gossip_entrypoint: parse_gossip_entrypoint(gossip_entrypoint)?,
+                leader_schedule_endpoint: config_file.leader_schedule_endpoint,
+                sampling_mode: config_file.sampling_mode,
+                rpc_endpoints: config_file.rpc_endpoints,
+                provider_overlap: config_file.provider_overlap,
+                pipeline: PipelineConfig {
+                    slot_queue_capacity: config_file.slot_queue_capacity,
+                    max_slots_in_flight: config_file.max_slots_in_flight,
+                    overflow_policy: config_file.overflow_policy,
+                    sampling_workers: config_file.sampling_workers,
+                    max_sample_attempts: config_file.max_sample_attempts,
+                    ..PipelineConfig::default()
+                },
+                bridge,
+                restart_policy: RestartPolicy {
+                    max_restarts: config_file.max_restarts,
+                    ..RestartPolicy::default()
                 },
             };
 
-            TinyDancer::start(config).await.unwrap();
+            TinyDancer::start(config).await?;
         }
 
-        Commands::Slot => {
-            let config_file =
-                get_config_file().map_err(|_| anyhow!("tinydancer config not set"))?;
-            let slot_res = {
-                let req_client = reqwest::Client::new();
-                let res = req_client
-                    .post(get_endpoint(config_file.cluster))
-                    .body(
-                        serde_json::json!({"jsonrpc":"2.0","id":1, "method":"getSlot"}).to_string(),
-                    )
-                    .header(CONTENT_TYPE, "application/json")
-                    .header(ACCEPT, "application/json")
-                    .send()
-                    .await;
-
-                res
-            };
-
-            match slot_res {
-                Ok(get_slot_response) => {
-                    let slot_text = get_slot_response.text().await.map_err(|e| {
-                        anyhow!("Failed to get slot due to error: {}", e.to_string())
-                    })?;
+        Commands::Verdicts {
+            archive_path,
+            from,
+            to,
+            target_confidence,
+            json,
+        } => {
+            let mut config_file = load_config()?;
+            ConfigUpdate {
+                target_confidence,
+                archive_path,
+                ..ConfigUpdate::default()
+            }
+            .apply(&mut config_file);
+            config_file.validate()?;
 
-                    let slot = serde_json::from_str::<GetSlotResponse>(&slot_text.as_str());
+            let archive_path = config_file
+                .archive_path
+                .ok_or_else(|| anyhow!("archive path not provided..."))?;
+            let db = open_archive_read_only(archive_path)?;
+            let verdicts = get_verdicts(&db, from, to.unwrap_or(from))?;
 
-                    match slot {
-                        Ok(slot) => {
-                            println!("Slot: {}", slot.result.to_string().green(),);
-                        }
-                        Err(e) => {
-                            println!("Failed to get slot due to error: {}", e.to_string().red());
+            if json {
+                println!("{}", serde_json::to_string_pretty(&verdicts)?);
+            } else if verdicts.is_empty() {
+                println!(
+                    "No verdicts recorded between slot {} and {}",
+                    from,
+                    to.unwrap_or(from)
+                );
+            } else {
+                for verdict in verdicts {
+                    let status = match verdict.verdict {
+                        Verdict::Valid if !verdict.passed(config_file.target_confidence) => {
+                            "LowConfidence".to_string().yellow()
                         }
-                    }
-                }
-                Err(e) => {
-                    println!("Failed to get slot,due to error: {}", e.to_string().red());
+                        Verdict::Valid => "Valid".to_string().green(),
+                        Verdict::Invalid => "Invalid".to_string().red(),
+                        Verdict::WrongLeader => "WrongLeader".to_string().red(),
+                        Verdict::Unrecoverable => "Unrecoverable".to_string().red(),
+                    };
+                    println!(
+                        "Slot {} {} valid {} invalid {} confidence {:.4} leader {}",
+                        verdict.slot.to_string().yellow(),
+                        status,
+                        verdict.valid,
+                        verdict.invalid,
+                        verdict.confidence,
+                        verdict.leader
+                    );
                 }
             }
         }
-        Commands::Config(sub_config) => match sub_config {
-            ConfigSubcommands::Get => {
-                let home_path = std::env::var("HOME").unwrap();
-                let is_existing = home_path.clone() + "/.config/tinydancer/config.json";
-                let path = Path::new(&is_existing);
-                if path.exists() {
-                    std::process::Command::new("cat")
-                        .arg(home_path + "/.config/tinydancer/config.json")
-                        .spawn()
-                        .expect("Config not set");
-                } else {
+
+        Commands::Archive(sub_archive) => match sub_archive {
+            ArchiveSubcommands::Ls {
+                archive_path,
+                from,
+                to,
+            } => {
+                let db =
+                    open_archive_read_only(archive_path_or_config(archive_path, &load_config)?)?;
+                for entry in scan_archive(&db, from, to.unwrap_or(from))? {
                     println!(
-                        "{} {}",
-                        "Initialise a config first using:".to_string().yellow(),
-                        "tinydancer set config".to_string().green()
+                        "{} {:?} {} {} bytes",
+                        entry.slot.to_string().yellow(),
+                        entry.shred_type,
+                        entry.index,
+                        entry.value.len()
                     );
                 }
             }
-            ConfigSubcommands::Set { log_path, cluster } => {
-                // println!("{:?}", fs::create_dir_all("~/.config/tinydancer"));
-
-                let home_path = std::env::var("HOME").unwrap();
-                let tinydancer_dir = home_path + "/.config/tinydancer";
-
-                let path = Path::new(&tinydancer_dir);
-                if !path.exists() {
-                    std::process::Command::new("mkdir")
-                        .arg(&tinydancer_dir)
-                        .stdout(std::process::Stdio::null())
-                        .spawn()
-                        .expect("couldnt make dir");
+            ArchiveSubcommands::Get {
+                archive_path,
+                slot,
+                index,
+                coding,
+            } => {
+                let db =
+                    open_archive_read_only(archive_path_or_config(archive_path, &load_config)?)?;
+                let shred_type = match coding {
+                    true => ShredType::Code,
+                    false => ShredType::Data,
+                };
+                match get_shred(&db, slot, shred_type, index)? {
+                    Some(shred) => println!("{}", serde_json::to_string_pretty(&shred)?),
+                    None => println!(
+                        "{:?} shred {} of slot {} is not archived",
+                        shred_type,
+                        index,
+                        slot.to_string().yellow()
+                    ),
                 }
-                sleep(Duration::from_secs(1));
-
-                let config_path = tinydancer_dir + "/config.json";
-                let path = Path::new(&config_path);
-                if !path.exists() {
-                    std::process::Command::new("touch")
-                        .arg(&config_path)
-                        .stdout(std::process::Stdio::null())
-                        .spawn()
-                        .expect("couldnt make file");
+            }
+            ArchiveSubcommands::Export {
+                archive_path,
+                from,
+                to,
+                output,
+            } => {
+                let db =
+                    open_archive_read_only(archive_path_or_config(archive_path, &load_config)?)?;
+                let mut writer: Box<dyn io::Write> = match output {
+                    Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+                    None => Box::new(io::stdout().lock()),
+                };
+                for entry in scan_archive(&db, from, to.unwrap_or(from))? {
+                    writeln!(writer, "{}", serde_json::to_string(&entry.shred()?)?)?;
                 }
-                sleep(Duration::from_secs(1));
-
-                let config_file = get_config_file();
-                match config_file {
-                    Ok(mut config_file) => {
-                        // overwrite
-                        config_file.log_path = log_path;
-                        config_file.cluster = cluster;
-                        std::fs::write(config_path, serde_json::to_string_pretty(&config_file)?)?;
-                    }
-                    Err(_) => {
-                        // initialize
-                        std::fs::write(
-                            config_path,
-                            serde_json::to_string_pretty(&serde_json::json!({
-                                "cluster":"Localnet",
-                                "logPath":"/tmp/client.log"
-                            }))?,
-                        )?;
-                    }
+                writer.flush()?;
+            }
+            ArchiveSubcommands::Stats {
+                archive_path,
+                from,
+                to,
+            } => {
+                let db =
+                    open_archive_read_only(archive_path_or_config(archive_path, &load_config)?)?;
+                let stats = archive_stats(&db, from, to)?;
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            }
+            ArchiveSubcommands::Convert { archive_path } => {
+                let mut opts = rocksdb::Options::default();
+                opts.create_missing_column_families(true);
+                let db = rocksdb::DB::open_cf(
+                    &opts,
+                    archive_path_or_config(archive_path, &load_config)?,
+                    ARCHIVE_CFS,
+                )?;
+                let converted = convert_json_values(&db)?;
+                println!("Converted {} shreds", converted.to_string().green());
+            }
+        },
+
+        Commands::Slot => {
+            let config_file = load_config()?;
+            match JsonRpcClient::new(get_endpoint(config_file.cluster))
+                .call::<u64>("getSlot", serde_json::json!([]))
+                .await
+            {
+                Ok(slot) => println!("Slot: {}", slot.to_string().green()),
+                Err(e) => println!("Failed to get slot due to error: {}", e.to_string().red()),
+            }
+        }
+        Commands::Config(sub_config) => match sub_config {
+            ConfigSubcommands::Get => {
+                let config_file = load_config()?;
+                println!("{}", serde_json::to_string_pretty(&config_file)?);
+            }
+            ConfigSubcommands::Set(update) => {
+                let mut file = match ConfigFile::load(&config_path) {
+                    Ok(file) => file,
+                    Err(ConfigError::NotFound { .. }) => ConfigFile {
+                        active_profile: profile.clone().unwrap_or_else(|| DEFAULT_PROFILE.into()),
+                        profiles: Default::default(),
+                    },
+                    Err(e) => return Err(e.into()),
+                };
+                let name = profile_name(profile.as_deref(), &file);
+                let config_file = file
+                    .profiles
+                    .entry(name.clone())
+                    .or_insert_with(|| ConfigSchema::for_profile(&name));
+                update.apply(config_file);
+                config_file.validate()?;
+                file.save(&config_path)?;
+                println!(
+                    "Saved profile {} to {}",
+                    name.green(),
+                    config_path.display()
+                );
+            }
+            ConfigSubcommands::Use { profile } => {
+                let mut file = ConfigFile::load(&config_path)?;
+                file.profile(&profile)?;
+                file.active_profile = profile;
+                file.save(&config_path)?;
+            }
+            ConfigSubcommands::Profiles => {
+                let file = ConfigFile::load(&config_path)?;
+                let active = profile_name(profile.as_deref(), &file);
+                for (name, config_file) in &file.profiles {
+                    let marker = if *name == active { "*" } else { " " };
+                    println!(
+                        "{} {} {}",
+                        marker,
+                        name.clone().yellow(),
+                        config_file.cluster
+                    );
                 }
             }
         },
-        Commands::Verify { slot, sample_qty } => {
-            let _spinner = Spinner::new(
-                spinners::Dots,
-                format!("Verifying Shreds for Slot {}", slot),
-                Color::Green,
+        Commands::Verify {
+            slot,
+            from,
+            to,
+            follow,
+            output,
+            sample_qty,
+            target_confidence,
+            withholding_fraction,
+            shred_sources,
+            archive_path,
+            gossip_entrypoint,
+            leader_schedule_endpoint,
+            deep,
+            commitment,
+            rpc_endpoints,
+            provider_overlap,
+        } => {
+            let mut config_file = load_config()?;
+            ConfigUpdate {
+                leader_schedule_endpoint,
+                sample_qty,
+                target_confidence,
+                withholding_fraction,
+                shred_sources,
+                provider_overlap,
+                ..ConfigUpdate::default()
+            }
+            .apply(&mut config_file);
+            config_file.rpc_endpoints.extend(rpc_endpoints);
+            config_file.validate()?;
+
+            let archive = archive_path
+                .map(|path| open_archive_read_only(path).map(Arc::new))
+                .transpose()?;
+            let rpc_url = get_endpoint(config_file.cluster);
+            let rpc_providers = RpcProviders::new(
+                rpc_url.clone(),
+                config_file.rpc_endpoints,
+                config_file.provider_overlap,
             );
+            let provider_reputations = rpc_providers.reputations.clone();
+            let leader_schedule = LeaderScheduleCache::new(
+                config_file.leader_schedule_endpoint,
+                &rpc_providers.endpoints,
+            );
+            let sources = build_shred_sources(
+                &config_file.shred_sources,
+                rpc_providers,
+                archive,
+                parse_gossip_entrypoint(gossip_entrypoint)?,
+            )?;
 
-            let config_file =
-                get_config_file().map_err(|_| anyhow!("tinydancer config not set"))?;
-            let is_verified =
-                pull_and_verify_shreds(slot, get_endpoint(config_file.cluster), sample_qty).await;
+            let strategy = SamplingStrategy::new(
+                config_file.sample_qty,
+                config_file.target_confidence,
+                config_file.withholding_fraction,
+            );
 
-            if is_verified {
-                println!(
-                    "\nSlot {} is {} ✓",
-                    slot.to_string().yellow(),
-                    "Valid".to_string().green()
-                );
+            let mut verifier = SlotVerifier {
+                rpc_client: JsonRpcClient::new(rpc_url),
+                sources,
+                strategy,
+                leader_schedule,
+                commitment,
+                deep,
+                output,
+                checked: 0,
+                failed: 0,
+            };
+
+            let from = from.or(slot);
+            if follow {
+                verifier.follow(from, to).await?;
             } else {
-                println!(
-                    "\nSlot {} is not {} ❌",
-                    slot.to_string().yellow(),
-                    "Valid".to_string().red()
-                );
+                let from = from.ok_or_else(|| {
+                    anyhow!("a slot to verify is required, pass --slot, --from or --follow")
+                })?;
+                let to = to.unwrap_or(from);
+                if to < from || to - from >= MAX_VERDICT_RANGE {
+                    return Err(anyhow!(
+                        "invalid slot range {}..={}, it has to span at most {} slots",
+                        from,
+                        to,
+                        MAX_VERDICT_RANGE
+                    ));
+                }
+
+                let _spinner = (output == VerifyOutput::Text).then(|| {
+                    Spinner::new(
+                        spinners::Dots,
+                        format!("Verifying Shreds for Slot {}", from),
+                        Color::Green,
+                    )
+                });
+                verifier.verify_range(from, to).await?;
+            }
+
+            let reputations = provider_reputations.snapshot();
+            if output == VerifyOutput::Text && reputations.len() > 1 {
+                println!();
+                print_provider_reputations(&reputations);
+            }
+            if verifier.failed > 0 {
+                return Err(anyhow!(
+                    "{} of {} slots failed verification",
+                    verifier.failed,
+                    verifier.checked
+                ));
+            }
+        }
+        Commands::Providers { client_url, json } => {
+            let reputations = JsonRpcClient::new(client_url)
+                .call::<Vec<ProviderReputation>>("getProviderReputation", serde_json::json!([]))
+                .await
+                .map_err(|e| anyhow!("Failed to get provider reputation due to error: {}", e))?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&reputations)?);
+            } else if reputations.is_empty() {
+                println!("No rpc provider has been sampled yet");
+            } else {
+                print_provider_reputations(&reputations);
             }
         }
     }
@@ -305,6 +800,51 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// The running client holds the lock on the archive so it's opened read only,
+/// which only needs the column families that are read
+fn open_archive_read_only(archive_path: String) -> Result<rocksdb::DB> {
+    Ok(rocksdb::DB::open_cf_for_read_only(
+        &rocksdb::Options::default(),
+        archive_path,
+        vec![SHRED_CF, VERDICT_CF],
+        false,
+    )?)
+}
+
+/// An archive path passed on the command line wins over the one of the profile,
+/// the config is only loaded when it's missing
+fn archive_path_or_config(
+    archive_path: Option<String>,
+    load_config: impl FnOnce() -> Result<ConfigSchema, ConfigError>,
+) -> Result<String> {
+    match archive_path {
+        Some(path) => Ok(path),
+        None => load_config()?
+            .archive_path
+            .ok_or_else(|| anyhow!("archive path not provided...")),
+    }
+}
+
+fn print_provider_reputations(reputations: &[ProviderReputation]) {
+    for reputation in reputations {
+        let score = format!("{:.4}", reputation.score);
+        let score = match reputation.mismatched + reputation.wrong_leader {
+            0 => score.green(),
+            _ => score.red(),
+        };
+        println!(
+            "{} score {} served {} withheld {} mismatched {} wrong leader {} failed requests {}",
+            reputation.provider.clone().yellow(),
+            score,
+            reputation.served,
+            reputation.withheld,
+            reputation.mismatched,
+            reputation.wrong_leader,
+            reputation.failed_requests
+        );
+    }
+}
+
 pub fn get_cluster(cluster: String) -> Cluster {
     match cluster.as_str() {
         "Mainnet" => Cluster::Mainnet,
@@ -313,6 +853,15 @@ pub fn get_cluster(cluster: String) -> Cluster {
         _ => Cluster::Custom(cluster),
     }
 }
+pub fn parse_gossip_entrypoint(entrypoint: Option<String>) -> Result<Option<SocketAddr>> {
+    entrypoint
+        .map(|entrypoint| {
+            solana_net_utils::parse_host_port(&entrypoint)
+                .map_err(|e| anyhow!("invalid gossip entrypoint {}: {}", entrypoint, e))
+        })
+        .transpose()
+}
+
 pub fn get_endpoint(cluster: String) -> String {
     match cluster.as_str() {
         "Mainnet" => "https://api.mainnet-beta.solana.com".to_owned(),
@@ -321,18 +870,3 @@ pub fn get_endpoint(cluster: String) -> String {
         _ => cluster.to_owned(),
     }
 }
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ConfigSchema {
-    pub log_path: String,
-    pub cluster: String,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct GetSlotResponse {
-    pub jsonrpc: String,
-    pub result: i64,
-    pub id: i64,
-}
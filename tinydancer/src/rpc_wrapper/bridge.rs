@@ -1,16 +1,22 @@
 use crate::{
-    get_endpoint,
     rpc_wrapper::{
         block_store::{BlockInformation, BlockStore},
         configs::{IsBlockHashValidConfig, SendTransactionConfig},
         encoding::BinaryEncoding,
+        proxy::{ProxyConfig, ProxyLayer},
         rpc::LiteRpcServer,
+        sampling::{BridgeSampling, SampleNotification, SamplingReport},
         tpu_manager::TpuManager,
         workers::{BlockListener, Cleaner, TxSender, WireTransaction},
     },
-    sampler::{get_serialized, pull_and_verify_shreds, SHRED_CF},
+    sampler::{
+        get_serialized,
+        providers::{ProviderReputation, ProviderReputations},
+        slot_updates::SlotStream,
+        verdicts::{get_verdicts, SlotVerdict},
+        SHRED_CF,
+    },
     tinydancer::Cluster,
-    ConfigSchema,
 };
 use colored::Colorize;
 use hyper::Method;
@@ -31,21 +37,37 @@ use anyhow::bail;
 use solana_ledger::shred::{Shred, ShredType, Slot};
 use tiny_logger::logs::{info, warn};
 
-use jsonrpsee::{server::ServerBuilder, types::SubscriptionResult, SubscriptionSink};
+use jsonrpsee::{
+    server::{ServerBuilder, ServerHandle},
+    types::SubscriptionResult,
+    RpcModule, SubscriptionSink,
+};
 use prometheus::{core::GenericGauge, opts, register_int_counter, register_int_gauge, IntCounter};
 use solana_rpc_client::{nonblocking::rpc_client::RpcClient, rpc_client::SerializableTransaction};
 use solana_rpc_client_api::{
-    config::{RpcContextConfig, RpcRequestAirdropConfig, RpcSignatureStatusConfig},
+    config::{
+        RpcBlockConfig, RpcContextConfig, RpcEncodingConfigWrapper, RpcRequestAirdropConfig,
+        RpcSignatureStatusConfig, RpcTransactionConfig,
+    },
     response::{Response as RpcResponse, RpcBlockhash, RpcResponseContext, RpcVersionInfo},
 };
 use solana_sdk::{
-    blake3::hashv, commitment_config::CommitmentConfig, hash::Hash, pubkey::Pubkey,
-    signature::Keypair, transaction::VersionedTransaction,
+    blake3::hashv,
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    transaction::VersionedTransaction,
+};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, TransactionStatus, UiConfirmedBlock,
 };
-use solana_transaction_status::TransactionStatus;
 use tokio::{
     net::ToSocketAddrs,
-    sync::mpsc::{self, UnboundedSender},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc::{self, UnboundedSender},
+    },
     task::JoinHandle,
 };
 use tower_http::cors::{Any, CorsLayer};
@@ -61,10 +83,26 @@ lazy_static::lazy_static! {
     register_int_counter!(opts!("literpc_rpc_get_signature_statuses", "RPC call to get signature statuses")).unwrap();
     static ref RPC_GET_VERSION: IntCounter =
     register_int_counter!(opts!("literpc_rpc_get_version", "RPC call to version")).unwrap();
+    static ref RPC_GET_SLOT: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_slot", "RPC call to get slot")).unwrap();
+    static ref RPC_GET_BLOCK_HEIGHT: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_block_height", "RPC call to get block height")).unwrap();
+    static ref RPC_GET_BLOCK: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_block", "RPC call to get block")).unwrap();
+    static ref RPC_GET_TRANSACTION: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_transaction", "RPC call to get transaction")).unwrap();
+    static ref RPC_GET_SLOT_VERDICTS: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_slot_verdicts", "RPC call to get slot verdicts")).unwrap();
+    static ref RPC_GET_PROVIDER_REPUTATION: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_get_provider_reputation", "RPC call to get provider reputation")).unwrap();
     static ref RPC_REQUEST_AIRDROP: IntCounter =
     register_int_counter!(opts!("literpc_rpc_airdrop", "RPC call to request airdrop")).unwrap();
     static ref RPC_SIGNATURE_SUBSCRIBE: IntCounter =
     register_int_counter!(opts!("literpc_rpc_signature_subscribe", "RPC call to subscribe to signature")).unwrap();
+    static ref RPC_SLOT_SUBSCRIBE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_slot_subscribe", "RPC call to subscribe to slots")).unwrap();
+    static ref RPC_SAMPLE_SUBSCRIBE: IntCounter =
+    register_int_counter!(opts!("literpc_rpc_sample_subscribe", "RPC call to subscribe to slot samples")).unwrap();
     pub static ref TXS_IN_CHANNEL: GenericGauge<prometheus::core::AtomicI64> = register_int_gauge!(opts!("literpc_txs_in_channel", "Transactions in channel")).unwrap();
 }
 
@@ -73,6 +111,11 @@ pub struct LiteBridge {
     pub rpc_client: Arc<RpcClient>,
     pub tpu_manager: Arc<TpuManager>,
     pub db_instance: Arc<rocksdb::DB>,
+    /// verdicts responses are filled from
+    pub sampling: Arc<BridgeSampling>,
+    /// slots the sample service sees
+    pub slots: SlotStream,
+    pub provider_reputations: Arc<ProviderReputations>,
     // None if LiteBridge is not executed
     pub tx_send_channel: Option<UnboundedSender<(String, WireTransaction, u64)>>,
     pub tx_sender: TxSender,
@@ -87,6 +130,9 @@ impl LiteBridge {
         fanout_slots: u64,
         identity: Keypair,
         db_instance: Arc<rocksdb::DB>,
+        slots: SlotStream,
+        sampling: BridgeSampling,
+        provider_reputations: Arc<ProviderReputations>,
     ) -> anyhow::Result<Self> {
         let rpc_client = Arc::new(RpcClient::new(rpc_url.clone()));
 
@@ -102,6 +148,9 @@ impl LiteBridge {
 
         Ok(Self {
             db_instance,
+            sampling: Arc::new(sampling),
+            slots,
+            provider_reputations,
             rpc_client,
             tpu_manager,
             tx_send_channel: None,
@@ -120,87 +169,161 @@ impl LiteBridge {
         tx_batch_size: usize,
         tx_send_interval: Duration,
         clean_interval: Duration,
-    ) -> anyhow::Result<Vec<JoinHandle<anyhow::Result<()>>>> {
+        proxy: ProxyConfig,
+    ) -> anyhow::Result<BridgeServices> {
         let (tx_send, tx_recv) = mpsc::unbounded_channel();
         self.tx_send_channel = Some(tx_send);
 
-        let tx_sender = self
-            .tx_sender
-            .clone()
-            .execute(tx_recv, tx_batch_size, tx_send_interval);
-
-        let finalized_block_listener = self
-            .block_listner
-            .clone()
-            .listen(CommitmentConfig::finalized());
-
-        let confirmed_block_listener = self
-            .block_listner
-            .clone()
-            .listen(CommitmentConfig::confirmed());
-
-        let cleaner = Cleaner::new(
-            self.tx_sender.clone(),
-            self.block_listner.clone(),
-            self.block_store.clone(),
-            self.tpu_manager.clone(),
+        // dropped on an early return, which stops whatever already started
+        let mut services = BridgeServices::default();
+
+        services.tasks.push(self.tx_sender.clone().execute(
+            tx_recv,
+            tx_batch_size,
+            tx_send_interval,
+        ));
+
+        services.tasks.push(
+            self.block_listner
+                .clone()
+                .listen(CommitmentConfig::finalized()),
+        );
+
+        services.tasks.push(
+            self.block_listner
+                .clone()
+                .listen(CommitmentConfig::confirmed()),
+        );
+
+        services.tasks.push(
+            Cleaner::new(
+                self.tx_sender.clone(),
+                self.block_listner.clone(),
+                self.block_store.clone(),
+                self.tpu_manager.clone(),
+            )
+            .start(clean_interval),
+        );
+
+        let upstream = self.rpc_client.url();
+        start_servers(
+            &mut services,
+            self.into_rpc(),
+            http_addr,
+            ws_addr,
+            upstream,
+            proxy,
         )
-        .start(clean_interval);
-
-        let rpc = self.into_rpc();
-
-        let (ws_server, http_server) = {
-            let ws_server_handle = ServerBuilder::default()
-                .ws_only()
-                .build(ws_addr.clone())
-                .await?
-                .start(rpc.clone())?;
-            let cors = CorsLayer::new()
-                .allow_methods([Method::POST, Method::GET])
-                .allow_origin(Any)
-                .allow_headers([
-                    header::CONTENT_TYPE,
-                    header::ACCESS_CONTROL_ALLOW_HEADERS,
-                    header::ACCESS_CONTROL_ALLOW_ORIGIN,
-                    header::ACCESS_CONTROL_ALLOW_METHODS,
-                ]);
-            let middleware = tower::ServiceBuilder::new().layer(cors);
-            let http_server_handle = ServerBuilder::default()
-                .http_only()
-                .set_middleware(middleware)
-                .set_host_filtering(jsonrpsee::server::AllowHosts::Any)
-                .build(http_addr.clone())
-                .await?
-                .start(rpc)?;
-
-            let ws_server = tokio::spawn(async move {
-                info!("Websocket Server started at {ws_addr:?}");
-                ws_server_handle.stopped().await;
-                bail!("Websocket server stopped");
-            });
-
-            let http_server = tokio::spawn(async move {
-                info!("HTTP Server started at {http_addr:?}");
-                http_server_handle.stopped().await;
-                bail!("HTTP server stopped");
-            });
-
-            (ws_server, http_server)
-        };
-
-        let services = vec![
-            ws_server,
-            http_server,
-            tx_sender,
-            finalized_block_listener,
-            confirmed_block_listener,
-            cleaner,
-        ];
+        .await?;
 
         Ok(services)
     }
 }
 
+/// Starts the websocket and http servers answering `rpc`
+async fn start_servers<Ctx, T>(
+    services: &mut BridgeServices,
+    rpc: RpcModule<Ctx>,
+    http_addr: T,
+    ws_addr: T,
+    upstream: String,
+    proxy: ProxyConfig,
+) -> anyhow::Result<()>
+where
+    Ctx: Send + Sync + 'static,
+    T: ToSocketAddrs + std::fmt::Debug + 'static + Send + Clone,
+{
+    let known_methods = rpc.method_names().map(String::from).collect();
+
+    let ws_server_handle = ServerBuilder::default()
+        .ws_only()
+        .build(ws_addr.clone())
+        .await?
+        .start(rpc.clone())?;
+    services.servers.push(ws_server_handle.clone());
+
+    let cors = CorsLayer::new()
+        .allow_methods([Method::POST, Method::GET])
+        .allow_origin(Any)
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+        ]);
+    // unknown methods are answered by the upstream rpc, with cors headers too
+    let middleware = tower::ServiceBuilder::new()
+        .layer(cors)
+        .layer(ProxyLayer::new(upstream, known_methods, proxy));
+    let http_server_handle = ServerBuilder::default()
+        .http_only()
+        .set_middleware(middleware)
+        .set_host_filtering(jsonrpsee::server::AllowHosts::Any)
+        .build(http_addr.clone())
+        .await?
+        .start(rpc)?;
+    services.servers.push(http_server_handle.clone());
+
+    services.tasks.push(tokio::spawn(async move {
+        info!("Websocket Server started at {ws_addr:?}");
+        ws_server_handle.stopped().await;
+        bail!("Websocket server stopped");
+    }));
+
+    services.tasks.push(tokio::spawn(async move {
+        info!("HTTP Server started at {http_addr:?}");
+        http_server_handle.stopped().await;
+        bail!("HTTP server stopped");
+    }));
+
+    Ok(())
+}
+
+/// Everything `start_services` started, stopped once dropped so a restarted bridge
+/// can bind its ports again
+#[derive(Default)]
+pub struct BridgeServices {
+    tasks: Vec<JoinHandle<anyhow::Result<()>>>,
+    servers: Vec<ServerHandle>,
+}
+
+impl BridgeServices {
+    /// Resolves once any of the services stops, none of them should
+    pub async fn stopped(&mut self) -> anyhow::Result<()> {
+        if self.tasks.is_empty() {
+            return Ok(());
+        }
+        let (stopped, _, _) = futures::future::select_all(self.tasks.iter_mut()).await;
+        stopped?
+    }
+
+    /// Stops every service, and waits for the servers to let go of their ports
+    pub async fn stop(mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        for server in std::mem::take(&mut self.servers) {
+            // already stopped is fine
+            let _ = server.stop();
+            server.stopped().await;
+        }
+        for task in std::mem::take(&mut self.tasks) {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for BridgeServices {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        for server in &self.servers {
+            let _ = server.stop();
+        }
+    }
+}
+
 #[jsonrpsee::core::async_trait]
 impl LiteRpcServer for LiteBridge {
     async fn send_transaction(
@@ -266,31 +389,13 @@ impl LiteRpcServer for LiteBridge {
         ) = self.block_store.get_latest_block(commitment_config).await;
 
         info!("glb {blockhash} {slot} {block_height}");
-        let mut rpc_url = String::from("http://0.0.0.0:8899");
-        let home_path = std::env::var("HOME").unwrap();
-        let is_existing = home_path.clone() + "/.config/tinydancer/config.json";
-        let path = Path::new(&is_existing);
-        if path.exists() {
-            let file = fs::File::open(home_path.clone() + "/.config/tinydancer/config.json")
-                .expect("Error reading config in bridge");
-            let config: ConfigSchema = serde_json::from_reader(file).unwrap();
-            rpc_url = get_endpoint(config.cluster);
-        } else {
-            println!(
-                "{} {}",
-                "Initialise a config first using:".to_string().yellow(),
-                "tinydancer set config".to_string().green()
-            );
-        }
-        let sampled =
-            pull_and_verify_shreds(slot as usize, String::from(rpc_url), 10 as usize).await;
+        let context = self
+            .sampling
+            .response_context(slot, commitment_config.commitment)
+            .await;
 
         Ok(LiteResponse {
-            context: LiteRpcResponseContext {
-                slot,
-                api_version: None,
-                sampled,
-            },
+            context,
             value: RpcBlockhash {
                 blockhash,
                 last_valid_block_height: block_height + 150,
@@ -362,30 +467,12 @@ impl LiteRpcServer for LiteBridge {
             .get_latest_block_info(CommitmentConfig::finalized())
             .await
             .slot;
-        let mut rpc_url = String::from("http://0.0.0.0:8899");
-        let home_path = std::env::var("HOME").unwrap();
-        let is_existing = home_path.clone() + "/.config/tinydancer/config.json";
-        let path = Path::new(&is_existing);
-        if path.exists() {
-            let file = fs::File::open(home_path.clone() + "/.config/tinydancer/config.json")
-                .expect("Error reading config in bridge");
-            let config: ConfigSchema = serde_json::from_reader(file).unwrap();
-            rpc_url = get_endpoint(config.cluster);
-        } else {
-            println!(
-                "{} {}",
-                "Initialise a config first using:".to_string().yellow(),
-                "tinydancer set config".to_string().green()
-            );
-        }
-        let sampled =
-            pull_and_verify_shreds(slot as usize, String::from(rpc_url), 10 as usize).await;
+        let context = self
+            .sampling
+            .response_context(slot, CommitmentLevel::Finalized)
+            .await;
         Ok(LiteResponse {
-            context: LiteRpcResponseContext {
-                slot,
-                api_version: None,
-                sampled,
-            },
+            context,
             value: sig_statuses,
         })
     }
@@ -400,6 +487,66 @@ impl LiteRpcServer for LiteBridge {
         })
     }
 
+    async fn get_slot(
+        &self,
+        config: Option<RpcContextConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<Slot>> {
+        RPC_GET_SLOT.inc();
+
+        slot_response(&self.rpc_client, &self.sampling, config).await
+    }
+
+    async fn get_block_height(
+        &self,
+        config: Option<RpcContextConfig>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<u64>> {
+        RPC_GET_BLOCK_HEIGHT.inc();
+
+        block_height_response(&self.rpc_client, &self.sampling, config).await
+    }
+
+    async fn get_block(
+        &self,
+        slot: Slot,
+        config: Option<RpcEncodingConfigWrapper<RpcBlockConfig>>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<UiConfirmedBlock>> {
+        RPC_GET_BLOCK.inc();
+
+        block_response(&self.rpc_client, &self.sampling, slot, config).await
+    }
+
+    async fn get_transaction(
+        &self,
+        signature_str: String,
+        config: Option<RpcEncodingConfigWrapper<RpcTransactionConfig>>,
+    ) -> crate::rpc_wrapper::rpc::Result<LiteResponse<EncodedConfirmedTransactionWithStatusMeta>>
+    {
+        RPC_GET_TRANSACTION.inc();
+
+        transaction_response(&self.rpc_client, &self.sampling, signature_str, config).await
+    }
+
+    async fn get_slot_verdicts(
+        &self,
+        from: Slot,
+        to: Option<Slot>,
+    ) -> crate::rpc_wrapper::rpc::Result<Vec<SlotVerdict>> {
+        RPC_GET_SLOT_VERDICTS.inc();
+
+        // a range scan reads up to MAX_VERDICT_RANGE records, kept off the runtime threads
+        let db_instance = self.db_instance.clone();
+        tokio::task::spawn_blocking(move || get_verdicts(&db_instance, from, to.unwrap_or(from)))
+            .await
+            .map_err(|err| jsonrpsee::core::Error::Custom(err.to_string()))?
+            .map_err(|err| jsonrpsee::core::Error::Custom(err.to_string()))
+    }
+
+    fn get_provider_reputation(&self) -> crate::rpc_wrapper::rpc::Result<Vec<ProviderReputation>> {
+        RPC_GET_PROVIDER_REPUTATION.inc();
+
+        Ok(self.provider_reputations.snapshot())
+    }
+
     async fn request_airdrop(
         &self,
         pubkey_str: String,
@@ -445,6 +592,161 @@ impl LiteRpcServer for LiteBridge {
             .signature_subscribe(signature, commitment_config, sink);
         Ok(())
     }
+
+    fn slot_subscribe(&self, mut sink: SubscriptionSink) -> SubscriptionResult {
+        RPC_SLOT_SUBSCRIBE.inc();
+        sink.accept()?;
+        tokio::spawn(notify_updates(sink, self.slots.subscribe(), |slot| slot));
+        Ok(())
+    }
+
+    fn sample_subscribe(&self, mut sink: SubscriptionSink) -> SubscriptionResult {
+        RPC_SAMPLE_SUBSCRIBE.inc();
+        sink.accept()?;
+        let target_confidence = self.sampling.strategy.target_confidence;
+        tokio::spawn(notify_updates(
+            sink,
+            self.sampling.verdicts.subscribe(),
+            move |verdict| SampleNotification::new(&verdict, target_confidence),
+        ));
+        Ok(())
+    }
+}
+
+/// Sends every update to `sink` until the client unsubscribes, a client that falls
+/// behind skips what it missed
+async fn notify_updates<T: Clone, N: Serialize>(
+    mut sink: SubscriptionSink,
+    mut updates: broadcast::Receiver<T>,
+    notification: impl Fn(T) -> N,
+) {
+    loop {
+        let update = match updates.recv().await {
+            Ok(update) => update,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("subscriber fell behind, skipped {} updates", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        match sink.send(&notification(update)) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                warn!("failed to notify subscriber: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+// the methods proxied to the upstream rpc, each response carries the verdict of the
+// slot it is about
+
+async fn slot_response(
+    rpc_client: &RpcClient,
+    sampling: &BridgeSampling,
+    config: Option<RpcContextConfig>,
+) -> crate::rpc_wrapper::rpc::Result<LiteResponse<Slot>> {
+    let commitment = config
+        .and_then(|config| config.commitment)
+        .unwrap_or_default();
+
+    let slot = match rpc_client.get_slot_with_commitment(commitment).await {
+        Ok(slot) => slot,
+        Err(err) => {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+    };
+
+    Ok(LiteResponse {
+        context: sampling.response_context(slot, commitment.commitment).await,
+        value: slot,
+    })
+}
+
+async fn block_height_response(
+    rpc_client: &RpcClient,
+    sampling: &BridgeSampling,
+    config: Option<RpcContextConfig>,
+) -> crate::rpc_wrapper::rpc::Result<LiteResponse<u64>> {
+    let commitment = config
+        .and_then(|config| config.commitment)
+        .unwrap_or_default();
+
+    // epoch info has the block height along with the slot it was read at
+    let epoch_info = match rpc_client.get_epoch_info_with_commitment(commitment).await {
+        Ok(epoch_info) => epoch_info,
+        Err(err) => {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+    };
+
+    Ok(LiteResponse {
+        context: sampling
+            .response_context(epoch_info.absolute_slot, commitment.commitment)
+            .await,
+        value: epoch_info.block_height,
+    })
+}
+
+async fn block_response(
+    rpc_client: &RpcClient,
+    sampling: &BridgeSampling,
+    slot: Slot,
+    config: Option<RpcEncodingConfigWrapper<RpcBlockConfig>>,
+) -> crate::rpc_wrapper::rpc::Result<LiteResponse<UiConfirmedBlock>> {
+    let config = config
+        .map(|config| config.convert_to_current())
+        .unwrap_or_default();
+    let commitment = config.commitment.unwrap_or_default();
+
+    let block = match rpc_client.get_block_with_config(slot, config).await {
+        Ok(block) => block,
+        Err(err) => {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+    };
+
+    Ok(LiteResponse {
+        context: sampling.response_context(slot, commitment.commitment).await,
+        value: block,
+    })
+}
+
+async fn transaction_response(
+    rpc_client: &RpcClient,
+    sampling: &BridgeSampling,
+    signature_str: String,
+    config: Option<RpcEncodingConfigWrapper<RpcTransactionConfig>>,
+) -> crate::rpc_wrapper::rpc::Result<LiteResponse<EncodedConfirmedTransactionWithStatusMeta>> {
+    let signature = match Signature::from_str(&signature_str) {
+        Ok(signature) => signature,
+        Err(err) => {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+    };
+    let config = config
+        .map(|config| config.convert_to_current())
+        .unwrap_or_default();
+    let commitment = config.commitment.unwrap_or_default();
+
+    let transaction = match rpc_client
+        .get_transaction_with_config(&signature, config)
+        .await
+    {
+        Ok(transaction) => transaction,
+        Err(err) => {
+            return Err(jsonrpsee::core::Error::Custom(err.to_string()));
+        }
+    };
+
+    Ok(LiteResponse {
+        context: sampling
+            .response_context(transaction.slot, commitment.commitment)
+            .await,
+        value: transaction,
+    })
 }
 
 impl Deref for LiteBridge {
@@ -455,16 +757,161 @@ impl Deref for LiteBridge {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LiteRpcResponseContext {
     pub slot: Slot,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub api_version: Option<RpcApiVersion>,
-    pub sampled: bool,
+    /// every sampled shred verified and the sample reached the target confidence,
+    /// null if the slot has no verdict
+    pub sampled: Option<bool>,
+    /// how `sampled` was reached, null if the slot has no verdict
+    #[serde(default)]
+    pub sampling: Option<SamplingReport>,
 }
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LiteResponse<T> {
     pub context: LiteRpcResponseContext,
     pub value: T,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rpc_wrapper::sampling::{OnDemandSamples, UnsampledSlotPolicy},
+        sampler::{
+            leader_schedule::LeaderScheduleCache, open_temp_archive, strategy::SamplingStrategy,
+            test_verdict, verdicts::VerdictStore,
+        },
+    };
+    use serde_json::json;
+
+    fn free_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn stopped_servers_free_their_ports() {
+        let (http_addr, ws_addr) = (free_addr(), free_addr());
+        for _ in 0..2 {
+            let mut services = BridgeServices::default();
+            start_servers(
+                &mut services,
+                RpcModule::new(()),
+                http_addr.clone(),
+                ws_addr.clone(),
+                String::from("http://127.0.0.1:8899"),
+                ProxyConfig::default(),
+            )
+            .await
+            .unwrap();
+            services.stop().await;
+        }
+    }
+
+    /// Upstream rpc at slot 10, with a block and a transaction in slot 7
+    async fn upstream() -> (String, ServerHandle) {
+        let mut module = RpcModule::new(());
+        module
+            .register_method("getVersion", |_, _| {
+                Ok(json!({ "solana-core": "1.15.2", "feature-set": 0 }))
+            })
+            .unwrap();
+        module
+            .register_method("getSlot", |_, _| Ok(json!(10)))
+            .unwrap();
+        module
+            .register_method("getEpochInfo", |_, _| {
+                Ok(json!({
+                    "epoch": 0,
+                    "slotIndex": 10,
+                    "slotsInEpoch": 32,
+                    "absoluteSlot": 10,
+                    "blockHeight": 9,
+                }))
+            })
+            .unwrap();
+        module
+            .register_method("getBlock", |_, _| {
+                Ok(json!({
+                    "previousBlockhash": Hash::default().to_string(),
+                    "blockhash": Hash::default().to_string(),
+                    "parentSlot": 6,
+                    "blockTime": null,
+                    "blockHeight": 6,
+                }))
+            })
+            .unwrap();
+        module
+            .register_method("getTransaction", |_, _| {
+                Ok(json!({
+                    "slot": 7,
+                    "transaction": "",
+                    "meta": null,
+                    "blockTime": null,
+                }))
+            })
+            .unwrap();
+        let server = ServerBuilder::default().build("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", server.local_addr().unwrap());
+        (endpoint, server.start(module).unwrap())
+    }
+
+    #[tokio::test]
+    async fn proxied_responses_carry_the_verdict_of_their_slot() {
+        let (instance, path) = open_temp_archive("bridge");
+        let sampling = BridgeSampling {
+            verdicts: VerdictStore::new(Arc::new(instance)),
+            sources: vec![],
+            strategy: SamplingStrategy::default(),
+            leader_schedule: Arc::new(LeaderScheduleCache::new(None, &[])),
+            unsampled_slot_policy: UnsampledSlotPolicy::Null,
+            sample_wait: Duration::ZERO,
+            on_demand: OnDemandSamples::default(),
+        };
+        sampling.verdicts.record(test_verdict(7)).unwrap();
+
+        let (endpoint, _server) = upstream().await;
+        let rpc_client = RpcClient::new(endpoint);
+
+        // the tip has no verdict yet
+        let slot = slot_response(&rpc_client, &sampling, None).await.unwrap();
+        assert_eq!(slot.value, 10);
+        assert_eq!((slot.context.slot, slot.context.sampled), (10, None));
+        let height = block_height_response(&rpc_client, &sampling, None)
+            .await
+            .unwrap();
+        assert_eq!((height.value, height.context.slot), (9, 10));
+
+        let block = block_response(&rpc_client, &sampling, 7, None)
+            .await
+            .unwrap();
+        assert_eq!(block.value.parent_slot, 6);
+        assert_eq!((block.context.slot, block.context.sampled), (7, Some(true)));
+        assert!(block.context.sampling.unwrap().from_cache);
+
+        let transaction = transaction_response(
+            &rpc_client,
+            &sampling,
+            Signature::default().to_string(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            (transaction.context.slot, transaction.context.sampled),
+            (7, Some(true))
+        );
+        assert!(
+            transaction_response(&rpc_client, &sampling, String::from("nope"), None)
+                .await
+                .is_err()
+        );
+
+        drop(sampling);
+        let _ = rocksdb::DB::destroy(&rocksdb::Options::default(), &path);
+    }
+}
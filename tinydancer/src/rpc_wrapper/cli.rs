@@ -1,32 +1,159 @@
-use crate::rpc_wrapper::{
-    DEFAULT_CLEAN_INTERVAL_MS, DEFAULT_FANOUT_SIZE, DEFAULT_RPC_ADDR, DEFAULT_TX_BATCH_INTERVAL_MS,
-    DEFAULT_TX_BATCH_SIZE, DEFAULT_WS_ADDR,
+use crate::{
+    config::{ConfigSchema, ConfigUpdate},
+    rpc_wrapper::{proxy::ProxyConfig, sampling::UnsampledSlotPolicy, BridgeConfig},
 };
-use clap::Parser;
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+/// Lite rpc bridge flags of `tinydancer start`, unset ones leave the config's value
+#[derive(clap::Args, Debug, Default)]
 pub struct Args {
-    #[arg(short, long, default_value_t = String::from(DEFAULT_RPC_ADDR))]
-    pub rpc_addr: String,
-    #[arg(short, long, default_value_t = String::from(DEFAULT_WS_ADDR))]
-    pub ws_addr: String,
-    #[arg(short = 'l', long, default_value_t = String::from("[::]:8890"))]
-    pub lite_rpc_http_addr: String,
-    #[arg(short = 's', long, default_value_t = String::from("[::]:8891"))]
-    pub lite_rpc_ws_addr: String,
+    /// Rpc the bridge forwards to, defaults to the cluster
+    #[clap(long, required = false)]
+    pub rpc_addr: Option<String>,
+    /// Websocket the bridge listens for blocks on, defaults to the cluster's
+    #[clap(long, required = false)]
+    pub ws_addr: Option<String>,
+    /// Address the lite rpc http server listens on
+    #[clap(long, required = false)]
+    pub lite_rpc_http_addr: Option<String>,
+    /// Address the lite rpc websocket server listens on
+    #[clap(long, required = false)]
+    pub lite_rpc_ws_addr: Option<String>,
     /// batch size of each batch forward
-    #[arg(short = 'b', long, default_value_t = DEFAULT_TX_BATCH_SIZE)]
-    pub tx_batch_size: usize,
+    #[clap(long, required = false)]
+    pub tx_batch_size: Option<usize>,
     /// tpu fanout
-    #[arg(short = 'f', long, default_value_t = DEFAULT_FANOUT_SIZE) ]
-    pub fanout_size: u64,
+    #[clap(long, required = false)]
+    pub fanout_size: Option<u64>,
     /// interval between each batch forward
-    #[arg(short = 'i', long, default_value_t = DEFAULT_TX_BATCH_INTERVAL_MS)]
-    pub tx_batch_interval_ms: u64,
+    #[clap(long, required = false)]
+    pub tx_batch_interval_ms: Option<u64>,
     /// interval between clean
-    #[arg(short = 'c', long, default_value_t = DEFAULT_CLEAN_INTERVAL_MS)]
-    pub clean_interval_ms: u64,
-    #[arg(short = 'k', long, default_value_t = String::new())]
-    pub identity_keypair: String,
+    #[clap(long, required = false)]
+    pub clean_interval_ms: Option<u64>,
+    /// Keypair file transactions are signed with, a fresh keypair if unset
+    #[clap(long, required = false)]
+    pub identity_keypair: Option<String>,
+    /// What responses for slots the sampler hasn't gotten to yet get
+    #[clap(long, value_enum, required = false)]
+    pub unsampled_slot_policy: Option<UnsampledSlotPolicy>,
+    /// Longest a response waits for the sampler with the wait policy
+    #[clap(long, required = false)]
+    pub sample_wait_ms: Option<u64>,
+    /// Unknown rpc method forwarded upstream, repeat for more, all of them if none
+    #[clap(long, required = false)]
+    pub proxy_allow: Vec<String>,
+    /// Rpc method never forwarded upstream, repeat for more, `*` for all of them
+    #[clap(long, required = false)]
+    pub proxy_deny: Vec<String>,
+    /// Mark forwarded responses as unverified
+    #[clap(long, required = false)]
+    pub proxy_annotate: Option<bool>,
+}
+
+impl Args {
+    /// Layers these flags over the config, which is validated as a whole afterwards
+    pub fn apply(self, config: &mut ConfigSchema) {
+        ConfigUpdate {
+            rpc_addr: self.rpc_addr,
+            ws_addr: self.ws_addr,
+            lite_rpc_http_addr: self.lite_rpc_http_addr,
+            lite_rpc_ws_addr: self.lite_rpc_ws_addr,
+            tx_batch_size: self.tx_batch_size,
+            fanout_size: self.fanout_size,
+            tx_batch_interval_ms: self.tx_batch_interval_ms,
+            clean_interval_ms: self.clean_interval_ms,
+            identity_path: self.identity_keypair,
+            unsampled_slot_policy: self.unsampled_slot_policy,
+            sample_wait_ms: self.sample_wait_ms,
+            proxy_allow: self.proxy_allow,
+            proxy_deny: self.proxy_deny,
+            proxy_annotate: self.proxy_annotate,
+            ..ConfigUpdate::default()
+        }
+        .apply(config)
+    }
+}
+
+impl From<&ConfigSchema> for BridgeConfig {
+    fn from(config: &ConfigSchema) -> Self {
+        BridgeConfig {
+            rpc_addr: config.rpc_addr.clone(),
+            ws_addr: config.ws_addr.clone(),
+            lite_rpc_http_addr: config.lite_rpc_http_addr.clone(),
+            lite_rpc_ws_addr: config.lite_rpc_ws_addr.clone(),
+            tx_batch_size: config.tx_batch_size,
+            fanout_size: config.fanout_size,
+            tx_batch_interval_ms: config.tx_batch_interval_ms,
+            clean_interval_ms: config.clean_interval_ms,
+            identity_keypair: config.identity_path.clone(),
+            unsampled_slot_policy: config.unsampled_slot_policy,
+            sample_wait_ms: config.sample_wait_ms,
+            proxy: ProxyConfig {
+                allow: config.proxy_allow.clone(),
+                deny: config.proxy_deny.clone(),
+                annotate: config.proxy_annotate,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Start {
+        #[clap(flatten)]
+        bridge: Args,
+    }
+
+    fn bridge_flags(flags: &[&str]) -> Args {
+        Start::try_parse_from(std::iter::once("tinydancer").chain(flags.iter().copied()))
+            .unwrap()
+            .bridge
+    }
+
+    #[test]
+    fn flags_are_layered_over_the_config() {
+        let mut config = ConfigSchema {
+            tx_batch_size: 64,
+            sample_wait_ms: 500,
+            proxy_allow: vec![String::from("getBalance")],
+            proxy_annotate: true,
+            ..ConfigSchema::default()
+        };
+        bridge_flags(&[
+            "--tx-batch-size",
+            "8",
+            "--proxy-deny",
+            "getProgramAccounts",
+            "--proxy-annotate",
+            "false",
+        ])
+        .apply(&mut config);
+
+        let bridge = BridgeConfig::from(&config);
+        assert_eq!(bridge.tx_batch_size, 8);
+        assert_eq!(bridge.sample_wait_ms, 500);
+        assert_eq!(bridge.proxy.allow, vec!["getBalance"]);
+        assert_eq!(bridge.proxy.deny, vec!["getProgramAccounts"]);
+        assert!(!bridge.proxy.annotate);
+    }
+
+    #[test]
+    fn no_flags_leave_the_config_alone() {
+        let mut config = ConfigSchema {
+            rpc_addr: Some(String::from("http://127.0.0.1:8899")),
+            identity_path: Some(String::from("id.json")),
+            ..ConfigSchema::default()
+        };
+        bridge_flags(&[]).apply(&mut config);
+
+        let bridge = BridgeConfig::from(&config);
+        assert_eq!(bridge.rpc_addr.as_deref(), Some("http://127.0.0.1:8899"));
+        assert_eq!(bridge.identity_keypair.as_deref(), Some("id.json"));
+        assert_eq!(bridge.lite_rpc_http_addr, config.lite_rpc_http_addr);
+        assert_eq!(bridge.unsampled_slot_policy, config.unsampled_slot_policy);
+    }
 }
@@ -11,30 +11,39 @@
 pub mod bridge;
 pub mod configs;
 pub mod encoding;
+pub mod proxy;
 pub mod rpc;
+pub mod sampling;
 pub mod tpu_manager;
 pub mod workers;
-// pub mod cli;
+pub mod cli;
 pub mod block_store;
 use crate::convert_to_websocket;
 use crate::rpc_wrapper::bridge::LiteBridge;
+use crate::rpc_wrapper::proxy::ProxyConfig;
+use crate::rpc_wrapper::sampling::{
+    BridgeSampling, OnDemandSamples, UnsampledSlotPolicy, DEFAULT_SAMPLE_WAIT_MS,
+};
+use crate::sampler::{
+    leader_schedule::LeaderScheduleCache, providers::ProviderReputations,
+    shred_source::ShredSource, slot_updates::SlotStream, strategy::SamplingStrategy,
+    verdicts::VerdictStore,
+};
+use crate::supervisor::{ServiceError, Shutdown};
 use crate::tinydancer::{endpoint, ClientService, Cluster};
-use anyhow::bail;
+use anyhow::anyhow;
 use async_trait::async_trait;
-use clap::Parser;
 use const_env::from_env;
 use dotenv::dotenv;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_ledger::shred::Signer;
-use solana_sdk::signer::keypair::Keypair;
+use solana_sdk::signer::{keypair::Keypair, Signer};
 use solana_transaction_status::TransactionConfirmationStatus;
 use std::sync::Arc;
 use std::{env, time::Duration};
 use tiny_logger::logs::info;
 use tokio::task::JoinHandle;
 
-// use self::cli::Args;
-
 #[from_env]
 pub const DEFAULT_RPC_ADDR: &str = "http://0.0.0.0:8899";
 #[from_env]
@@ -60,80 +69,154 @@ pub struct TransactionService {
     tx_handle: JoinHandle<Result<(), anyhow::Error>>,
 }
 
+#[derive(Clone)]
 pub struct TransactionServiceConfig {
     pub cluster: Cluster,
     pub db_instance: Arc<rocksdb::DB>,
+    pub leader_schedule: Arc<LeaderScheduleCache>,
+    pub provider_reputations: Arc<ProviderReputations>,
+    /// verdicts the sample service records
+    pub verdicts: VerdictStore,
+    /// slots the sample service sees, re-exported by `slotSubscribe`
+    pub slots: SlotStream,
+    /// sources slots without a verdict are sampled from
+    pub shred_sources: Vec<Arc<dyn ShredSource>>,
+    pub strategy: SamplingStrategy,
+    pub bridge: BridgeConfig,
+    pub shutdown: Shutdown,
+}
+
+/// Upstream, listen addresses, batching and identity of the lite rpc bridge
+#[derive(Clone, Debug)]
+pub struct BridgeConfig {
+    /// rpc transactions and requests are forwarded to, defaults to the cluster
+    pub rpc_addr: Option<String>,
+    /// websocket blocks are followed on, defaults to the cluster's
+    pub ws_addr: Option<String>,
+    pub lite_rpc_http_addr: String,
+    pub lite_rpc_ws_addr: String,
+    /// transactions forwarded per batch
+    pub tx_batch_size: usize,
+    pub fanout_size: u64,
+    pub tx_batch_interval_ms: u64,
+    pub clean_interval_ms: u64,
+    /// keypair file transactions are signed with, a fresh keypair if unset
+    pub identity_keypair: Option<String>,
+    /// what responses for slots without a verdict get
+    pub unsampled_slot_policy: UnsampledSlotPolicy,
+    /// longest a response waits for the sampler with the wait policy
+    pub sample_wait_ms: u64,
+    /// which unknown methods are forwarded upstream
+    pub proxy: ProxyConfig,
 }
 
-async fn get_identity_keypair(identity_from_cli: &String) -> Keypair {
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        Self {
+            rpc_addr: None,
+            ws_addr: None,
+            lite_rpc_http_addr: String::from("[::]:8890"),
+            lite_rpc_ws_addr: String::from("[::]:8891"),
+            tx_batch_size: DEFAULT_TX_BATCH_SIZE,
+            fanout_size: DEFAULT_FANOUT_SIZE,
+            tx_batch_interval_ms: DEFAULT_TX_BATCH_INTERVAL_MS,
+            clean_interval_ms: DEFAULT_CLEAN_INTERVAL_MS,
+            identity_keypair: None,
+            unsampled_slot_policy: UnsampledSlotPolicy::default(),
+            sample_wait_ms: DEFAULT_SAMPLE_WAIT_MS,
+            proxy: ProxyConfig::default(),
+        }
+    }
+}
+
+async fn read_keypair(path: &str) -> anyhow::Result<Keypair> {
+    let identity_file = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| anyhow!("cannot read identity file {}: {}", path, e))?;
+    let identity_bytes: Vec<u8> = serde_json::from_str(&identity_file)
+        .map_err(|e| anyhow!("identity file {} is not a keypair: {}", path, e))?;
+    Keypair::from_bytes(identity_bytes.as_slice())
+        .map_err(|e| anyhow!("identity file {} is not a keypair: {}", path, e))
+}
+
+/// `IDENTITY` holds either the keypair bytes or a keypair file, then `identity_from_cli`,
+/// a fresh keypair if neither is set
+async fn get_identity_keypair(identity_from_cli: &str) -> anyhow::Result<Keypair> {
     if let Ok(identity_env_var) = env::var("IDENTITY") {
         if let Ok(identity_bytes) = serde_json::from_str::<Vec<u8>>(identity_env_var.as_str()) {
-            print!("HASIII TO HASU");
-            Keypair::from_bytes(identity_bytes.as_slice()).unwrap()
+            Keypair::from_bytes(identity_bytes.as_slice())
+                .map_err(|e| anyhow!("IDENTITY is not a keypair: {}", e))
         } else {
             // must be a file
-            let identity_file = tokio::fs::read_to_string(identity_env_var.as_str())
-                .await
-                .expect("Cannot find the identity file provided");
-            let identity_bytes: Vec<u8> = serde_json::from_str(&identity_file).unwrap();
-            Keypair::from_bytes(identity_bytes.as_slice()).unwrap()
+            read_keypair(&identity_env_var).await
         }
     } else if identity_from_cli.is_empty() {
-        Keypair::new()
+        Ok(Keypair::new())
     } else {
-        let identity_file = tokio::fs::read_to_string(identity_from_cli.as_str())
-            .await
-            .expect("Cannot find the identity file provided");
-        let identity_bytes: Vec<u8> = serde_json::from_str(&identity_file).unwrap();
-        Keypair::from_bytes(identity_bytes.as_slice()).unwrap()
+        read_keypair(identity_from_cli).await
     }
 }
 
 #[async_trait]
 impl ClientService<TransactionServiceConfig> for TransactionService {
-    type ServiceError = tokio::io::Error;
+    type ServiceError = ServiceError;
     fn new(config: TransactionServiceConfig) -> Self {
-        let transaction_handle = tokio::spawn(async {
+        let transaction_handle = tokio::spawn(async move {
             dotenv().ok();
-            let rpc_url = endpoint(config.cluster);
+            let bridge = config.bridge;
+            let rpc_url = bridge.rpc_addr.unwrap_or_else(|| endpoint(config.cluster));
+            let ws_url = bridge
+                .ws_addr
+                .unwrap_or_else(|| convert_to_websocket!(rpc_url));
+            let payer =
+                get_identity_keypair(bridge.identity_keypair.as_deref().unwrap_or_default())
+                    .await?;
+            info!("transaction bridge identity {}", payer.pubkey());
 
-            let payer = Keypair::new();
-
-            let tx_batch_interval_ms = Duration::from_millis(DEFAULT_TX_BATCH_INTERVAL_MS);
-            let clean_interval_ms = Duration::from_millis(DEFAULT_CLEAN_INTERVAL_MS);
+            let tx_batch_interval_ms = Duration::from_millis(bridge.tx_batch_interval_ms);
+            let clean_interval_ms = Duration::from_millis(bridge.clean_interval_ms);
 
             let light_bridge = LiteBridge::new(
-                String::from(rpc_url.clone()),
-                String::from(convert_to_websocket!(rpc_url)),
-                DEFAULT_FANOUT_SIZE,
+                rpc_url,
+                ws_url,
+                bridge.fanout_size,
                 payer,
                 config.db_instance,
+                config.slots,
+                BridgeSampling {
+                    verdicts: config.verdicts,
+                    sources: config.shred_sources,
+                    strategy: config.strategy,
+                    leader_schedule: config.leader_schedule,
+                    unsampled_slot_policy: bridge.unsampled_slot_policy,
+                    sample_wait: Duration::from_millis(bridge.sample_wait_ms),
+                    on_demand: OnDemandSamples::default(),
+                },
+                config.provider_reputations,
             )
             .await?;
 
-            let services = light_bridge
+            let mut services = light_bridge
                 .start_services(
-                    String::from("[::]:8890"),
-                    String::from("[::]:8891"),
-                    DEFAULT_TX_BATCH_SIZE,
+                    bridge.lite_rpc_http_addr,
+                    bridge.lite_rpc_ws_addr,
+                    bridge.tx_batch_size,
                     tx_batch_interval_ms,
                     clean_interval_ms,
+                    bridge.proxy,
                 )
                 .await?;
 
-            let services = futures::future::try_join_all(services);
-
-            let ctrl_c_signal = tokio::signal::ctrl_c();
-
-            tokio::select! {
-                _ = services => {
-                    bail!("Services quit unexpectedly");
-                }
-                _ = ctrl_c_signal => {
-                    info!("Received ctrl+c signal");
-                    Ok(())
-                }
-            }
+            let stopped = tokio::select! {
+                stopped = services.stopped() => Err(match stopped {
+                    Ok(()) => anyhow!("Services quit unexpectedly"),
+                    Err(e) => e.context("Services quit unexpectedly"),
+                }),
+                _ = config.shutdown.cancelled() => Ok(()),
+            };
+            // the ports are free again by the time a restart binds them
+            services.stop().await;
+            stopped
         });
         Self {
             tx_handle: transaction_handle,
@@ -141,7 +224,6 @@ impl ClientService<TransactionServiceConfig> for TransactionService {
     }
 
     async fn join(self) -> std::result::Result<(), Self::ServiceError> {
-        let _ = self.tx_handle.await;
-        Ok(())
+        Ok(self.tx_handle.await??)
     }
 }
@@ -0,0 +1,466 @@
+//! Pass-through proxy for the json rpc methods `LiteRpc` doesn't implement
+//!
+//! Sits in front of the http server as a tower layer. Requests for methods the
+//! bridge knows, and anything that isn't a json rpc call, go to the bridge.
+//! Requests for unknown methods are forwarded verbatim to the upstream rpc
+//! unless the allow/deny lists say otherwise, in which case the bridge answers
+//! them with "method not found". A batch is only forwarded if every call in it
+//! would be.
+//!
+//! Forwarded responses are not sampled, with `annotate` they carry
+//! `"unverified": true` next to the result so clients can tell. Calls that
+//! can't reach the upstream get a json rpc error each.
+//!
+//! Metrics are labelled with the method for the standard solana methods only,
+//! anything else a client makes up is counted as `other`.
+use futures::future::BoxFuture;
+use hyper::{
+    body::HttpBody,
+    header::{self, HeaderValue},
+    Body, Method, Request, Response, StatusCode,
+};
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tiny_logger::logs::warn;
+use tower::{Layer, Service};
+
+/// Largest request body buffered to look at the method, same as the bridge's own limit
+const MAX_PROXY_BODY: usize = 10 * 1024 * 1024;
+const PROXY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Denies every method when in the deny list
+const ANY_METHOD: &str = "*";
+/// Metric label of the methods that aren't in `RPC_METHODS`
+const OTHER_METHOD: &str = "other";
+/// Json rpc error code of calls the upstream couldn't answer
+const UPSTREAM_ERROR_CODE: i64 = -32603;
+
+/// Http methods of the solana json rpc api, the only method labels metrics get
+const RPC_METHODS: &[&str] = &[
+    "getAccountInfo",
+    "getBalance",
+    "getBlock",
+    "getBlockCommitment",
+    "getBlockHeight",
+    "getBlockProduction",
+    "getBlockTime",
+    "getBlocks",
+    "getBlocksWithLimit",
+    "getClusterNodes",
+    "getEpochInfo",
+    "getEpochSchedule",
+    "getFeeForMessage",
+    "getFirstAvailableBlock",
+    "getGenesisHash",
+    "getHealth",
+    "getHighestSnapshotSlot",
+    "getIdentity",
+    "getInflationGovernor",
+    "getInflationRate",
+    "getInflationReward",
+    "getLargestAccounts",
+    "getLatestBlockhash",
+    "getLeaderSchedule",
+    "getMaxRetransmitSlot",
+    "getMaxShredInsertSlot",
+    "getMinimumBalanceForRentExemption",
+    "getMultipleAccounts",
+    "getProgramAccounts",
+    "getRecentPerformanceSamples",
+    "getRecentPrioritizationFees",
+    "getSignatureStatuses",
+    "getSignaturesForAddress",
+    "getSlot",
+    "getSlotLeader",
+    "getSlotLeaders",
+    "getStakeActivation",
+    "getStakeMinimumDelegation",
+    "getSupply",
+    "getTokenAccountBalance",
+    "getTokenAccountsByDelegate",
+    "getTokenAccountsByOwner",
+    "getTokenLargestAccounts",
+    "getTokenSupply",
+    "getTransaction",
+    "getTransactionCount",
+    "getVersion",
+    "getVoteAccounts",
+    "isBlockhashValid",
+    "minimumLedgerSlot",
+    "requestAirdrop",
+    "sendTransaction",
+    "simulateTransaction",
+];
+
+lazy_static::lazy_static! {
+    static ref PROXY_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(PROXY_TIMEOUT)
+        .build()
+        .expect("failed to build the proxy http client");
+    static ref RPC_PROXIED: IntCounterVec =
+    register_int_counter_vec!(opts!("literpc_rpc_proxied", "RPC calls forwarded to the upstream rpc"), &["method"]).unwrap();
+    static ref RPC_PROXY_ERRORS: IntCounterVec =
+    register_int_counter_vec!(opts!("literpc_rpc_proxy_errors", "RPC calls that failed to reach the upstream rpc"), &["method"]).unwrap();
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProxyConfig {
+    /// only these methods are forwarded, every unknown one if empty
+    pub allow: Vec<String>,
+    /// never forwarded, `*` turns the proxy off
+    pub deny: Vec<String>,
+    /// mark forwarded responses as unverified
+    pub annotate: bool,
+}
+
+impl ProxyConfig {
+    fn forwards(&self, method: &str) -> bool {
+        !self
+            .deny
+            .iter()
+            .any(|denied| denied == ANY_METHOD || denied == method)
+            && (self.allow.is_empty() || self.allow.iter().any(|allowed| allowed == method))
+    }
+}
+
+/// A call that's forwarded upstream
+#[derive(Debug, PartialEq)]
+struct ProxiedCall {
+    method: String,
+    id: Value,
+}
+
+impl ProxiedCall {
+    /// Method label for metrics, a closed set whatever clients send
+    fn label(&self) -> &str {
+        RPC_METHODS
+            .iter()
+            .find(|method| **method == self.method)
+            .copied()
+            .unwrap_or(OTHER_METHOD)
+    }
+}
+
+/// Calls of a forwarded request, `batch` if they came as an array
+#[derive(Debug, PartialEq)]
+struct ProxiedRequest {
+    calls: Vec<ProxiedCall>,
+    batch: bool,
+}
+
+#[derive(Clone)]
+pub struct ProxyLayer {
+    upstream: Arc<String>,
+    /// methods the bridge answers itself
+    known_methods: Arc<HashSet<String>>,
+    config: Arc<ProxyConfig>,
+}
+
+impl ProxyLayer {
+    pub fn new(upstream: String, known_methods: HashSet<String>, config: ProxyConfig) -> Self {
+        Self {
+            upstream: Arc::new(upstream),
+            known_methods: Arc::new(known_methods),
+            config: Arc::new(config),
+        }
+    }
+
+    /// Calls of the request in `body` if it should be forwarded
+    fn proxied_request(&self, body: &[u8]) -> Option<ProxiedRequest> {
+        let call = |call: &Value| {
+            Some(ProxiedCall {
+                method: call.get("method")?.as_str()?.to_string(),
+                id: call.get("id").cloned().unwrap_or(Value::Null),
+            })
+        };
+        let request = match serde_json::from_slice::<Value>(body).ok()? {
+            Value::Array(calls) if !calls.is_empty() => ProxiedRequest {
+                calls: calls.iter().map(call).collect::<Option<_>>()?,
+                batch: true,
+            },
+            single @ Value::Object(_) => ProxiedRequest {
+                calls: vec![call(&single)?],
+                batch: false,
+            },
+            _ => return None,
+        };
+        request
+            .calls
+            .iter()
+            .all(|call| {
+                !self.known_methods.contains(&call.method) && self.config.forwards(&call.method)
+            })
+            .then_some(request)
+    }
+}
+
+impl<S> Layer<S> for ProxyLayer {
+    type Service = Proxy<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Proxy {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Proxy<S> {
+    inner: S,
+    layer: ProxyLayer,
+}
+
+impl<S> Service<Request<Body>> for Proxy<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // the clone may not be ready, keep the one that is
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            if request.method() != Method::POST {
+                return inner.call(request).await;
+            }
+
+            let (parts, body) = request.into_parts();
+            let body = match read_body(body).await {
+                Ok(body) => body,
+                Err(status) => return Ok(status_response(status)),
+            };
+
+            match layer.proxied_request(&body) {
+                Some(request) => Ok(forward(&layer, request, body).await),
+                None => {
+                    inner
+                        .call(Request::from_parts(parts, Body::from(body)))
+                        .await
+                }
+            }
+        })
+    }
+}
+
+async fn read_body(mut body: Body) -> Result<Vec<u8>, StatusCode> {
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if bytes.len() + chunk.len() > MAX_PROXY_BODY {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+fn json_response(status: StatusCode, body: Vec<u8>) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+async fn forward(layer: &ProxyLayer, request: ProxiedRequest, body: Vec<u8>) -> Response<Body> {
+    for call in &request.calls {
+        RPC_PROXIED.with_label_values(&[call.label()]).inc();
+    }
+
+    let upstream = PROXY_CLIENT
+        .post(layer.upstream.as_str())
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await;
+    let (status, body) = match upstream {
+        Ok(response) => {
+            let status = response.status();
+            match response.bytes().await {
+                Ok(body) => (status, body.to_vec()),
+                Err(e) => return upstream_error(&request, e),
+            }
+        }
+        Err(e) => return upstream_error(&request, e),
+    };
+
+    let body = match layer.config.annotate {
+        true => annotate(body),
+        false => body,
+    };
+    json_response(
+        StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY),
+        body,
+    )
+}
+
+/// Answers every call of `request` with a json rpc error
+fn upstream_error(request: &ProxiedRequest, error: reqwest::Error) -> Response<Body> {
+    warn!("failed to forward {:?} upstream: {}", request.calls, error);
+    let errors: Vec<Value> = request
+        .calls
+        .iter()
+        .map(|call| {
+            RPC_PROXY_ERRORS.with_label_values(&[call.label()]).inc();
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "error": {
+                    "code": UPSTREAM_ERROR_CODE,
+                    "message": format!("upstream rpc unavailable: {}", error),
+                },
+                "id": call.id,
+            })
+        })
+        .collect();
+    let body = match request.batch {
+        true => Value::Array(errors),
+        false => errors.into_iter().next().unwrap_or(Value::Null),
+    };
+    json_response(
+        StatusCode::OK,
+        serde_json::to_vec(&body).expect("json values serialize"),
+    )
+}
+
+/// Marks every response in `body` unverified, anything that isn't json goes back untouched
+fn annotate(body: Vec<u8>) -> Vec<u8> {
+    let mark = |response: &mut Value| {
+        if let Value::Object(response) = response {
+            response.insert(String::from("unverified"), Value::Bool(true));
+        }
+    };
+    let Ok(mut value) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+    match &mut value {
+        Value::Array(responses) => responses.iter_mut().for_each(mark),
+        response => mark(response),
+    }
+    serde_json::to_vec(&value).unwrap_or(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(config: ProxyConfig) -> ProxyLayer {
+        ProxyLayer::new(
+            String::new(),
+            HashSet::from([String::from("getSlot")]),
+            config,
+        )
+    }
+
+    fn methods(request: Option<ProxiedRequest>) -> Option<Vec<String>> {
+        request.map(|request| request.calls.into_iter().map(|call| call.method).collect())
+    }
+
+    #[test]
+    fn only_unknown_allowed_methods_are_forwarded() {
+        let call = |method: &str| format!(r#"{{"jsonrpc":"2.0","id":1,"method":"{}"}}"#, method);
+        let proxy = layer(ProxyConfig {
+            deny: vec![String::from("getProgramAccounts")],
+            ..ProxyConfig::default()
+        });
+        assert_eq!(
+            methods(proxy.proxied_request(call("getBalance").as_bytes())),
+            Some(vec![String::from("getBalance")])
+        );
+        assert_eq!(proxy.proxied_request(call("getSlot").as_bytes()), None);
+        assert_eq!(
+            proxy.proxied_request(call("getProgramAccounts").as_bytes()),
+            None
+        );
+        assert_eq!(proxy.proxied_request(b"not json"), None);
+
+        let batch = format!("[{},{}]", call("getBalance"), call("getSlot"));
+        assert_eq!(proxy.proxied_request(batch.as_bytes()), None);
+
+        let proxy = layer(ProxyConfig {
+            allow: vec![String::from("getBalance")],
+            ..ProxyConfig::default()
+        });
+        assert!(proxy
+            .proxied_request(call("getBalance").as_bytes())
+            .is_some());
+        assert_eq!(
+            proxy.proxied_request(call("getAccountInfo").as_bytes()),
+            None
+        );
+
+        let proxy = layer(ProxyConfig {
+            deny: vec![String::from(ANY_METHOD)],
+            ..ProxyConfig::default()
+        });
+        assert_eq!(proxy.proxied_request(call("getBalance").as_bytes()), None);
+    }
+
+    #[test]
+    fn made_up_methods_share_a_label() {
+        let call = |method: &str| ProxiedCall {
+            method: method.to_string(),
+            id: Value::Null,
+        };
+        assert_eq!(call("getBalance").label(), "getBalance");
+        assert_eq!(call("getBalance1").label(), OTHER_METHOD);
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstream_answers_each_call_with_its_id() {
+        let proxy = ProxyLayer::new(
+            // nothing listens on the discard port
+            String::from("http://127.0.0.1:9"),
+            HashSet::new(),
+            ProxyConfig::default(),
+        );
+        let body = br#"[{"jsonrpc":"2.0","id":1,"method":"getBalance"},{"jsonrpc":"2.0","id":"b","method":"getSupply"}]"#;
+        let request = proxy.proxied_request(body).unwrap();
+        let response = forward(&proxy, request, body.to_vec()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let errors: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(errors[0]["id"], serde_json::json!(1));
+        assert_eq!(errors[1]["id"], serde_json::json!("b"));
+        assert_eq!(
+            errors[1]["error"]["code"],
+            serde_json::json!(UPSTREAM_ERROR_CODE)
+        );
+    }
+
+    #[test]
+    fn annotation_marks_every_response() {
+        let body = br#"[{"jsonrpc":"2.0","id":1,"result":1},{"jsonrpc":"2.0","id":2,"result":2}]"#;
+        let annotated: Value = serde_json::from_slice(&annotate(body.to_vec())).unwrap();
+        assert!(annotated
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|response| response["unverified"] == Value::Bool(true)));
+        assert_eq!(annotate(b"oops".to_vec()), b"oops".to_vec());
+    }
+}
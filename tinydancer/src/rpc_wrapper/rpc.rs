@@ -1,12 +1,20 @@
 use jsonrpsee::proc_macros::rpc;
 use solana_rpc_client_api::config::{
-    RpcContextConfig, RpcRequestAirdropConfig, RpcSignatureStatusConfig,
+    RpcBlockConfig, RpcContextConfig, RpcEncodingConfigWrapper, RpcRequestAirdropConfig,
+    RpcSignatureStatusConfig, RpcTransactionConfig,
+};
+use solana_rpc_client_api::response::{
+    Response as RpcResponse, RpcBlockhash, RpcVersionInfo, SlotInfo,
 };
-use solana_rpc_client_api::response::{Response as RpcResponse, RpcBlockhash, RpcVersionInfo};
 use solana_sdk::commitment_config::CommitmentConfig;
-use solana_transaction_status::TransactionStatus;
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, TransactionStatus, UiConfirmedBlock,
+};
 
 use crate::rpc_wrapper::configs::{IsBlockHashValidConfig, SendTransactionConfig};
+use crate::rpc_wrapper::sampling::SampleNotification;
+use crate::sampler::{providers::ProviderReputation, verdicts::SlotVerdict};
+use solana_sdk::clock::Slot;
 
 use super::bridge::LiteResponse;
 
@@ -44,6 +52,35 @@ pub trait LiteRpc {
     #[method(name = "getVersion")]
     fn get_version(&self) -> Result<RpcVersionInfo>;
 
+    #[method(name = "getSlot")]
+    async fn get_slot(&self, config: Option<RpcContextConfig>) -> Result<LiteResponse<Slot>>;
+
+    #[method(name = "getBlockHeight")]
+    async fn get_block_height(&self, config: Option<RpcContextConfig>)
+        -> Result<LiteResponse<u64>>;
+
+    #[method(name = "getBlock")]
+    async fn get_block(
+        &self,
+        slot: Slot,
+        config: Option<RpcEncodingConfigWrapper<RpcBlockConfig>>,
+    ) -> Result<LiteResponse<UiConfirmedBlock>>;
+
+    #[method(name = "getTransaction")]
+    async fn get_transaction(
+        &self,
+        signature_str: String,
+        config: Option<RpcEncodingConfigWrapper<RpcTransactionConfig>>,
+    ) -> Result<LiteResponse<EncodedConfirmedTransactionWithStatusMeta>>;
+
+    /// Verdicts of the sampled slots in `from..=to`, `to` defaults to `from`
+    #[method(name = "getSlotVerdicts")]
+    async fn get_slot_verdicts(&self, from: Slot, to: Option<Slot>) -> Result<Vec<SlotVerdict>>;
+
+    /// Reputation of every rpc provider shreds are sampled from
+    #[method(name = "getProviderReputation")]
+    fn get_provider_reputation(&self) -> Result<Vec<ProviderReputation>>;
+
     #[method(name = "requestAirdrop")]
     async fn request_airdrop(
         &self,
@@ -54,4 +91,10 @@ pub trait LiteRpc {
 
     #[subscription(name = "signatureSubscribe" => "signatureNotification", unsubscribe="signatureUnsubscribe", item=RpcResponse<serde_json::Value>)]
     fn signature_subscribe(&self, signature: String, commitment_config: CommitmentConfig);
+
+    #[subscription(name = "slotSubscribe" => "slotNotification", unsubscribe="slotUnsubscribe", item=SlotInfo)]
+    fn slot_subscribe(&self);
+
+    #[subscription(name = "sampleSubscribe" => "sampleNotification", unsubscribe="sampleUnsubscribe", item=SampleNotification)]
+    fn sample_subscribe(&self);
 }
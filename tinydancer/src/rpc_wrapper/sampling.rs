@@ -0,0 +1,326 @@
+//! Sampling verdicts of the slots rpc responses refer to
+//!
+//! Responses are filled from the verdicts the sample service already recorded.
+//! A slot without one is handled according to the `UnsampledSlotPolicy`. Slots
+//! sampled on demand are bounded by `MAX_ON_DEMAND_SAMPLES`, and responses
+//! asking about a slot already being sampled wait for its verdict.
+//!
+//! The response context carries both `sampled`, the pass/fail older clients
+//! read, and `sampling`, a report of how the verdict was reached.
+//! `sampleSubscribe` pushes the same pair for every verdict as it's recorded.
+use crate::{
+    rpc_wrapper::bridge::LiteRpcResponseContext,
+    sampler::{
+        leader_schedule::LeaderScheduleCache,
+        shred_source::ShredSource,
+        strategy::SamplingStrategy,
+        verdicts::{SlotVerdict, VerdictStore},
+        verify_slot_from_sources, Verdict,
+    },
+};
+use anyhow::anyhow;
+use prometheus::{opts, register_int_counter, IntCounter};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{clock::Slot, commitment_config::CommitmentLevel};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tiny_logger::logs::warn;
+use tokio::sync::Semaphore;
+
+pub const DEFAULT_SAMPLE_WAIT_MS: u64 = 2000;
+
+/// Slots sampled on demand at the same time
+pub const MAX_ON_DEMAND_SAMPLES: usize = 8;
+
+lazy_static::lazy_static! {
+    static ref VERDICT_CACHE_HITS: IntCounter =
+    register_int_counter!(opts!("literpc_verdict_cache_hits", "Responses filled from a recorded slot verdict")).unwrap();
+    static ref VERDICT_CACHE_MISSES: IntCounter =
+    register_int_counter!(opts!("literpc_verdict_cache_misses", "Responses for slots without a recorded verdict")).unwrap();
+}
+
+/// What a response for a slot the sampler hasn't recorded a verdict for gets
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum UnsampledSlotPolicy {
+    /// wait for the sampler, up to the sample wait, which holds responses about the tip
+    Wait,
+    /// answer right away with `sampled: null`, the default
+    Null,
+    /// sample the slot before answering
+    Sample,
+}
+
+impl Default for UnsampledSlotPolicy {
+    fn default() -> Self {
+        UnsampledSlotPolicy::Null
+    }
+}
+
+/// How the sampling verdict of a response's slot was reached
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SamplingReport {
+    /// data and coding shreds requested
+    pub requested: usize,
+    /// distinct shreds the sources served
+    pub received: usize,
+    /// served shreds that verified against the scheduled leader
+    pub verified: usize,
+    /// scheduled leader of the slot
+    pub leader: String,
+    /// commitment the slot was sampled at
+    pub commitment: Option<CommitmentLevel>,
+    /// chance a leader withholding shreds would have been caught
+    pub confidence: f64,
+    pub verdict: Verdict,
+    /// recorded by the sample service rather than sampled for this request
+    pub from_cache: bool,
+}
+
+impl SamplingReport {
+    pub fn new(verdict: &SlotVerdict, from_cache: bool) -> Self {
+        Self {
+            requested: verdict.requested_data_indices.len()
+                + verdict.requested_coding_indices.len(),
+            received: verdict.received_data_indices.len() + verdict.received_coding_indices.len(),
+            verified: verdict.valid,
+            leader: verdict.leader.clone(),
+            commitment: verdict.commitment,
+            confidence: verdict.confidence,
+            verdict: verdict.verdict,
+            from_cache,
+        }
+    }
+}
+
+/// What `sampleSubscribe` pushes for every verdict the sampler records
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleNotification {
+    pub slot: Slot,
+    /// whether the slot reached the target confidence
+    pub sampled: bool,
+    pub sampling: SamplingReport,
+}
+
+impl SampleNotification {
+    pub fn new(verdict: &SlotVerdict, target_confidence: f64) -> Self {
+        Self {
+            slot: verdict.slot,
+            sampled: verdict.passed(target_confidence),
+            sampling: SamplingReport::new(verdict, true),
+        }
+    }
+}
+
+pub struct BridgeSampling {
+    pub verdicts: VerdictStore,
+    /// sources slots are sampled from on demand
+    pub sources: Vec<Arc<dyn ShredSource>>,
+    pub strategy: SamplingStrategy,
+    pub leader_schedule: Arc<LeaderScheduleCache>,
+    pub unsampled_slot_policy: UnsampledSlotPolicy,
+    /// longest a response waits for the sampler
+    pub sample_wait: Duration,
+    pub on_demand: OnDemandSamples,
+}
+
+/// Slots being sampled on demand
+pub struct OnDemandSamples {
+    permits: Semaphore,
+    in_progress: Mutex<HashSet<Slot>>,
+}
+
+impl Default for OnDemandSamples {
+    fn default() -> Self {
+        Self {
+            permits: Semaphore::new(MAX_ON_DEMAND_SAMPLES),
+            in_progress: Mutex::new(HashSet::new()),
+        }
+    }
+}
+
+impl OnDemandSamples {
+    /// `None` if the slot is already being sampled
+    fn start(&self, slot: Slot) -> Option<InProgress<'_>> {
+        let started = self.in_progress.lock().unwrap().insert(slot);
+        started.then(|| InProgress {
+            samples: self,
+            slot,
+        })
+    }
+}
+
+/// Takes the slot off the ones in progress once its sample is done or dropped
+struct InProgress<'a> {
+    samples: &'a OnDemandSamples,
+    slot: Slot,
+}
+
+impl Drop for InProgress<'_> {
+    fn drop(&mut self) {
+        self.samples.in_progress.lock().unwrap().remove(&self.slot);
+    }
+}
+
+impl BridgeSampling {
+    /// Verdict of `slot` at `commitment` and whether it was already recorded, `None` if
+    /// it has none and the policy didn't get one. A verdict recorded at a weaker
+    /// commitment counts as none
+    pub async fn slot_verdict(
+        &self,
+        slot: Slot,
+        commitment: CommitmentLevel,
+    ) -> Option<(SlotVerdict, bool)> {
+        match self.verdicts.get(slot) {
+            Ok(Some(verdict)) if verdict.satisfies(commitment) => {
+                VERDICT_CACHE_HITS.inc();
+                return Some((verdict, true));
+            }
+            Ok(_) => VERDICT_CACHE_MISSES.inc(),
+            Err(e) => {
+                warn!("failed to read the verdict of slot {}: {}", slot, e);
+                return None;
+            }
+        }
+
+        let verdict = match self.unsampled_slot_policy {
+            UnsampledSlotPolicy::Null => return None,
+            UnsampledSlotPolicy::Wait => self
+                .verdicts
+                .wait_for(slot, commitment, self.sample_wait)
+                .await
+                .map(|verdict| verdict.map(|verdict| (verdict, true))),
+            UnsampledSlotPolicy::Sample => self
+                .sample(slot, commitment)
+                .await
+                .map(|verdict| verdict.map(|verdict| (verdict, false))),
+        };
+        verdict.unwrap_or_else(|e| {
+            warn!("no verdict for slot {}: {}", slot, e);
+            None
+        })
+    }
+
+    /// Context of a response about `slot`, with its sampling verdict
+    pub async fn response_context(
+        &self,
+        slot: Slot,
+        commitment: CommitmentLevel,
+    ) -> LiteRpcResponseContext {
+        let verdict = self.slot_verdict(slot, commitment).await;
+        LiteRpcResponseContext {
+            slot,
+            api_version: None,
+            sampled: verdict
+                .as_ref()
+                .map(|(verdict, _)| verdict.passed(self.strategy.target_confidence)),
+            sampling: verdict
+                .map(|(verdict, from_cache)| SamplingReport::new(&verdict, from_cache)),
+        }
+    }
+
+    async fn sample(
+        &self,
+        slot: Slot,
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<Option<SlotVerdict>> {
+        // another response is sampling the slot, its verdict gets recorded
+        let Some(_in_progress) = self.on_demand.start(slot) else {
+            return self
+                .verdicts
+                .wait_for(slot, commitment, self.sample_wait)
+                .await;
+        };
+        let permit = tokio::time::timeout(self.sample_wait, self.on_demand.permits.acquire()).await;
+        let Ok(permit) = permit else {
+            return Err(anyhow!(
+                "{} slots are sampled on demand already",
+                MAX_ON_DEMAND_SAMPLES
+            ));
+        };
+        let _permit = permit?;
+
+        let verdict = verify_slot_from_sources(
+            slot,
+            &self.sources,
+            &self.strategy,
+            &self.leader_schedule,
+            commitment,
+            false,
+        )
+        .await?;
+        // nothing was served to judge the slot by, the next response samples it again
+        if verdict.valid == 0 && verdict.invalid == 0 {
+            return Err(anyhow!("no shreds served for slot {}", slot));
+        }
+        // recorded so the next response for the slot doesn't sample it again
+        self.verdicts.record(verdict.clone())?;
+        Ok(Some(verdict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::test_verdict;
+
+    #[test]
+    fn context_keeps_the_plain_sampled_flag() {
+        let verdict = SlotVerdict {
+            requested_data_indices: vec![0, 1, 2],
+            requested_coding_indices: vec![0],
+            received_data_indices: vec![0, 2],
+            valid: 2,
+            confidence: 0.9,
+            ..test_verdict(5)
+        };
+        let report = SamplingReport::new(&verdict, true);
+        assert_eq!(
+            (report.requested, report.received, report.verified),
+            (4, 2, 2)
+        );
+
+        let context = serde_json::to_value(LiteRpcResponseContext {
+            slot: 5,
+            api_version: None,
+            sampled: Some(true),
+            sampling: Some(report),
+        })
+        .unwrap();
+        assert_eq!(context["sampled"], serde_json::json!(true));
+        assert_eq!(context["sampling"]["fromCache"], serde_json::json!(true));
+
+        // contexts from before the report still parse
+        let old: LiteRpcResponseContext =
+            serde_json::from_value(serde_json::json!({ "slot": 5, "sampled": false })).unwrap();
+        assert_eq!(old.sampling, None);
+    }
+
+    #[test]
+    fn slots_are_sampled_on_demand_once_at_a_time() {
+        let samples = OnDemandSamples::default();
+        let first = samples.start(7);
+        assert!(first.is_some());
+        assert!(samples.start(7).is_none());
+        assert!(samples.start(8).is_some());
+
+        drop(first);
+        assert!(samples.start(7).is_some());
+    }
+
+    #[test]
+    fn unsampled_slots_dont_block_by_default() {
+        assert_eq!(UnsampledSlotPolicy::default(), UnsampledSlotPolicy::Null);
+        // the sample wait only matters once the wait policy is opted into
+        let config = crate::config::ConfigSchema {
+            sample_wait_ms: 0,
+            ..crate::config::ConfigSchema::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,686 @@
+//! Shred archive layout and pruning
+//!
+//! Shreds are keyed by big-endian `(slot, shred_type, index)` so everything
+//! archived for a slot sits next to each other and whole slot ranges can be
+//! dropped with a single `delete_range_cf`. Verdicts use the same big-endian
+//! slot prefix and are pruned the same way, against their own duration.
+//!
+//! Slots aren't sampled in slot order (retries, a second pass at root, on
+//! demand samples of old slots), so `SAMPLED_AT_CF` indexes them by big-endian
+//! `(timestamp, slot)` and time based pruning drops each slot whose entry fell
+//! out of the window, unless its verdict says it was sampled again since. How
+//! far each column family was pruned is kept in the default column family, an
+//! entry is only visited once per column family.
+//!
+//! Archives written before this layout keyed shreds by `hashv(slot, type, index)`,
+//! `migrate_legacy_keys` rewrites those under the new keys on startup. The layout
+//! is recorded under `SCHEMA_VERSION_KEY` in the default column family once
+//! migrated so later starts don't scan the archive again.
+//!
+//! Values are the raw shred payload behind a two byte `[VALUE_MAGIC, version]`
+//! header. Older archives stored the shred as JSON, those values are still
+//! read and `convert_json_values` rewrites them in the binary format.
+use crate::sampler::{
+    verdicts::{get_verdict, VERDICT_CF},
+    ArchiveConfig, SHRED_CF,
+};
+use anyhow::anyhow;
+use prometheus::{opts, register_int_gauge, IntGauge};
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use serde::{Deserialize, Serialize};
+use solana_ledger::shred::{Shred, ShredType};
+use solana_sdk::{clock::Slot, timing::timestamp};
+use std::{sync::Arc, time::Duration};
+use tiny_logger::logs::{error, info};
+
+/// How often the pruner checks the archive
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Slots by when they were sampled, values are empty
+pub const SAMPLED_AT_CF: &str = "sampled_at";
+/// Every column family of the archive, a read-write open has to name all of them
+pub const ARCHIVE_CFS: [&str; 3] = [SHRED_CF, VERDICT_CF, SAMPLED_AT_CF];
+
+pub const ARCHIVE_KEY_LEN: usize = 13;
+const SAMPLED_AT_KEY_LEN: usize = 16;
+/// Length of the `hashv` keys used before the slot-ordered layout
+const LEGACY_KEY_LEN: usize = 32;
+/// Legacy entries rewritten per write batch
+const MIGRATION_BATCH_SIZE: usize = 1024;
+/// Default column family key holding the archive layout version
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+/// slot ordered keys
+const SCHEMA_VERSION: u8 = 1;
+/// Default column family keys holding the sample time (ms) each column family was pruned up to
+const SHREDS_PRUNED_THROUGH_KEY: &[u8] = b"shreds_pruned_through";
+const VERDICTS_PRUNED_THROUGH_KEY: &[u8] = b"verdicts_pruned_through";
+
+/// First byte of a binary value, never the start of a JSON document
+const VALUE_MAGIC: u8 = 0xff;
+/// header followed by the raw shred payload
+const VALUE_VERSION_RAW: u8 = 1;
+const VALUE_HEADER_LEN: usize = 2;
+
+lazy_static::lazy_static! {
+    static ref ARCHIVE_SIZE_BYTES: IntGauge =
+    register_int_gauge!(opts!("tinydancer_archive_size_bytes", "Size of the archived shreds on disk")).unwrap();
+    static ref ARCHIVE_ENTRIES: IntGauge =
+    register_int_gauge!(opts!("tinydancer_archive_entries", "Estimated number of archived shreds")).unwrap();
+    static ref ARCHIVE_PRUNED_BELOW: IntGauge =
+    register_int_gauge!(opts!("tinydancer_archive_pruned_below", "Slot the archived shreds were last pruned below")).unwrap();
+}
+
+/// Unit `shred_archive_duration` and `verdict_archive_duration` are measured in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum ArchiveDurationUnit {
+    /// keep the last `n` slots behind the newest archived slot
+    Slots,
+    /// keep slots sampled in the last `n` seconds
+    Seconds,
+}
+
+/// Key a shred is archived under
+pub fn archive_key(slot: Slot, shred_type: ShredType, index: u32) -> [u8; ARCHIVE_KEY_LEN] {
+    let mut key = [0u8; ARCHIVE_KEY_LEN];
+    key[..8].copy_from_slice(&slot.to_be_bytes());
+    key[8] = u8::from(shred_type);
+    key[9..].copy_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Prefix shared by every key of the slot, in both the shred and the verdict column family
+pub fn slot_prefix(slot: Slot) -> [u8; 8] {
+    slot.to_be_bytes()
+}
+
+pub fn slot_of_key(key: &[u8]) -> Option<Slot> {
+    Some(Slot::from_be_bytes(key.get(..8)?.try_into().ok()?))
+}
+
+/// Key of the `SAMPLED_AT_CF` entry of a slot sampled at `timestamp` (ms)
+pub fn sampled_at_key(timestamp: u64, slot: Slot) -> [u8; SAMPLED_AT_KEY_LEN] {
+    let mut key = [0u8; SAMPLED_AT_KEY_LEN];
+    key[..8].copy_from_slice(&timestamp.to_be_bytes());
+    key[8..].copy_from_slice(&slot.to_be_bytes());
+    key
+}
+
+fn parse_sampled_at_key(key: &[u8]) -> Option<(u64, Slot)> {
+    if key.len() != SAMPLED_AT_KEY_LEN {
+        return None;
+    }
+    let timestamp = u64::from_be_bytes(key[..8].try_into().ok()?);
+    Some((timestamp, slot_of_key(&key[8..])?))
+}
+
+/// Inverse of `archive_key`, `None` for keys of any other layout
+pub fn parse_archive_key(key: &[u8]) -> Option<(Slot, ShredType, u32)> {
+    if key.len() != ARCHIVE_KEY_LEN {
+        return None;
+    }
+    let slot = slot_of_key(key)?;
+    let shred_type = ShredType::try_from(key[8]).ok()?;
+    let index = u32::from_be_bytes(key[9..].try_into().ok()?);
+    Some((slot, shred_type, index))
+}
+
+/// A raw entry of the shred column family
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub slot: Slot,
+    pub shred_type: ShredType,
+    pub index: u32,
+    pub value: Box<[u8]>,
+}
+
+impl ArchiveEntry {
+    pub fn shred(&self) -> anyhow::Result<Shred> {
+        decode_shred(&self.value)
+    }
+}
+
+pub fn encode_shred(shred: &Shred) -> Vec<u8> {
+    let payload = shred.payload();
+    let mut value = Vec::with_capacity(VALUE_HEADER_LEN + payload.len());
+    value.extend_from_slice(&[VALUE_MAGIC, VALUE_VERSION_RAW]);
+    value.extend_from_slice(payload);
+    value
+}
+
+/// Reads a value in either the binary or the legacy JSON format
+pub fn decode_shred(value: &[u8]) -> anyhow::Result<Shred> {
+    match value {
+        [VALUE_MAGIC, VALUE_VERSION_RAW, payload @ ..] => {
+            Shred::new_from_serialized_shred(payload.to_vec())
+                .map_err(|e| anyhow!("failed to deserialize shred: {:?}", e))
+        }
+        [VALUE_MAGIC, version, ..] => Err(anyhow!("unknown archive value version {}", version)),
+        _ => Ok(serde_json::from_slice(value)?),
+    }
+}
+
+fn is_json_value(value: &[u8]) -> bool {
+    value.first() != Some(&VALUE_MAGIC)
+}
+
+pub fn put_shred(instance: &DB, shred: &Shred) -> anyhow::Result<()> {
+    let cf = instance
+        .cf_handle(SHRED_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", SHRED_CF))?;
+    let key = archive_key(shred.slot(), shred.shred_type(), shred.index());
+    instance.put_cf(cf, key, encode_shred(shred))?;
+    Ok(())
+}
+
+pub fn get_shred(
+    instance: &DB,
+    slot: Slot,
+    shred_type: ShredType,
+    index: u32,
+) -> anyhow::Result<Option<Shred>> {
+    let cf = instance
+        .cf_handle(SHRED_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", SHRED_CF))?;
+    match instance.get_cf(cf, archive_key(slot, shred_type, index))? {
+        Some(value) => Ok(Some(decode_shred(&value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Entries of the slots in `from..=to`, in `(slot, type, index)` order, read as they're iterated
+pub fn iter_archive(
+    instance: &DB,
+    from: Slot,
+    to: Slot,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<ArchiveEntry>> + '_> {
+    if to < from {
+        return Err(anyhow!("invalid slot range {}..={}", from, to));
+    }
+    let cf = instance
+        .cf_handle(SHRED_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", SHRED_CF))?;
+
+    let start = slot_prefix(from);
+    Ok(instance
+        .iterator_cf(cf, IteratorMode::From(&start, Direction::Forward))
+        .take_while(move |item| match item {
+            Ok((key, _)) => !matches!(slot_of_key(key), Some(slot) if slot > to),
+            Err(_) => true,
+        })
+        .filter_map(|item| match item {
+            Ok((key, value)) => {
+                let (slot, shred_type, index) = parse_archive_key(&key)?;
+                Some(Ok(ArchiveEntry {
+                    slot,
+                    shred_type,
+                    index,
+                    value,
+                }))
+            }
+            Err(e) => Some(Err(anyhow::Error::from(e))),
+        }))
+}
+
+/// Entries of the slots in `from..=to`, in `(slot, type, index)` order
+pub fn scan_archive(instance: &DB, from: Slot, to: Slot) -> anyhow::Result<Vec<ArchiveEntry>> {
+    iter_archive(instance, from, to)?.collect()
+}
+
+/// Stats of the slots in `from..=to`, counted entry by entry so the range can span the whole archive
+pub fn archive_stats(instance: &DB, from: Slot, to: Slot) -> anyhow::Result<ArchiveStats> {
+    let mut stats = ArchiveStats::default();
+    for entry in iter_archive(instance, from, to)? {
+        stats.add(&entry?);
+    }
+    Ok(stats)
+}
+
+/// Summary of the archived shreds of a slot range
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveStats {
+    pub first_slot: Option<Slot>,
+    pub last_slot: Option<Slot>,
+    pub slots: usize,
+    pub data_shreds: usize,
+    pub coding_shreds: usize,
+    pub bytes: usize,
+}
+
+impl ArchiveStats {
+    /// Counts the next entry, entries have to come in slot order
+    pub fn add(&mut self, entry: &ArchiveEntry) {
+        if self.last_slot != Some(entry.slot) {
+            self.slots += 1;
+            self.first_slot.get_or_insert(entry.slot);
+            self.last_slot = Some(entry.slot);
+        }
+        match entry.shred_type {
+            ShredType::Data => self.data_shreds += 1,
+            ShredType::Code => self.coding_shreds += 1,
+        }
+        self.bytes += entry.value.len();
+    }
+}
+
+impl From<&[ArchiveEntry]> for ArchiveStats {
+    fn from(entries: &[ArchiveEntry]) -> Self {
+        let mut stats = ArchiveStats::default();
+        for entry in entries {
+            stats.add(entry);
+        }
+        stats
+    }
+}
+
+/// Rewrites shreds archived under the legacy hashed keys under `archive_key`,
+/// the coordinates are read back from the stored shred since the hash can't be reversed.
+/// Archives already at `SCHEMA_VERSION` are left alone without a scan
+pub fn migrate_legacy_keys(instance: &DB) -> anyhow::Result<usize> {
+    if instance.get(SCHEMA_VERSION_KEY)?.as_deref() == Some(&[SCHEMA_VERSION][..]) {
+        return Ok(0);
+    }
+    let cf = instance
+        .cf_handle(SHRED_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", SHRED_CF))?;
+
+    let mut migrated = 0;
+    let mut batch = WriteBatch::default();
+    for item in instance.iterator_cf(cf, IteratorMode::Start) {
+        let (key, value) = item?;
+        if key.len() != LEGACY_KEY_LEN {
+            continue;
+        }
+
+        match serde_json::from_slice::<Shred>(&value) {
+            Ok(shred) => {
+                let new_key = archive_key(shred.slot(), shred.shred_type(), shred.index());
+                batch.put_cf(cf, new_key, &value);
+            }
+            Err(e) => error!("dropping unreadable legacy archive entry: {}", e),
+        }
+        batch.delete_cf(cf, &key);
+        migrated += 1;
+
+        if batch.len() >= MIGRATION_BATCH_SIZE {
+            instance.write(std::mem::take(&mut batch))?;
+        }
+    }
+    // recorded with the last of the rewrites, an interrupted migration is picked up again
+    batch.put(SCHEMA_VERSION_KEY, [SCHEMA_VERSION]);
+    instance.write(batch)?;
+    Ok(migrated)
+}
+
+/// Rewrites every JSON value in the binary format, returns the number of values converted
+pub fn convert_json_values(instance: &DB) -> anyhow::Result<usize> {
+    let cf = instance
+        .cf_handle(SHRED_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", SHRED_CF))?;
+
+    let mut converted = 0;
+    let mut batch = WriteBatch::default();
+    for item in instance.iterator_cf(cf, IteratorMode::Start) {
+        let (key, value) = item?;
+        if !is_json_value(&value) {
+            continue;
+        }
+
+        match decode_shred(&value) {
+            Ok(shred) => {
+                batch.put_cf(cf, &key, encode_shred(&shred));
+                converted += 1;
+            }
+            Err(e) => error!("skipping unreadable archive entry: {}", e),
+        }
+
+        if batch.len() >= MIGRATION_BATCH_SIZE {
+            instance.write(std::mem::take(&mut batch))?;
+        }
+    }
+    if !batch.is_empty() {
+        instance.write(batch)?;
+    }
+    Ok(converted)
+}
+
+pub async fn archive_pruner(
+    archive_config: ArchiveConfig,
+    instance: Arc<DB>,
+) -> anyhow::Result<()> {
+    loop {
+        match prune_archive(&archive_config, &instance) {
+            Ok(None) => {}
+            Ok(Some(cutoff)) => info!("pruned the archived shreds below slot {}", cutoff),
+            Err(e) => error!("failed to prune archive: {}", e),
+        }
+        update_archive_metrics(&instance);
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+    }
+}
+
+/// Drops the shreds and verdicts older than their configured durations, returns the
+/// slot shreds were pruned below if there were any to prune. Pruned by time the slots
+/// below aren't necessarily all gone, only the ones that fell out of the window
+pub fn prune_archive(config: &ArchiveConfig, instance: &DB) -> anyhow::Result<Option<Slot>> {
+    let pruned_below = match config.duration_unit {
+        ArchiveDurationUnit::Slots => prune_by_slots(config, instance)?,
+        ArchiveDurationUnit::Seconds => prune_by_time(config, instance)?,
+    };
+    if let Some(cutoff) = pruned_below {
+        ARCHIVE_PRUNED_BELOW.set(cutoff as i64);
+    }
+    Ok(pruned_below)
+}
+
+fn prune_by_slots(config: &ArchiveConfig, instance: &DB) -> anyhow::Result<Option<Slot>> {
+    if let Some(newest) = newest_slot(instance, VERDICT_CF)? {
+        let cutoff = newest.saturating_sub(config.verdict_archive_duration);
+        prune_below(instance, VERDICT_CF, cutoff)?;
+    }
+    match newest_slot(instance, SHRED_CF)? {
+        Some(newest) => {
+            let cutoff = newest.saturating_sub(config.shred_archive_duration);
+            Ok(prune_below(instance, SHRED_CF, cutoff)?.then_some(cutoff))
+        }
+        None => Ok(None),
+    }
+}
+
+fn prune_by_time(config: &ArchiveConfig, instance: &DB) -> anyhow::Result<Option<Slot>> {
+    let now = timestamp();
+    let shreds_since = now.saturating_sub(config.shred_archive_duration.saturating_mul(1000));
+    let verdicts_since = now.saturating_sub(config.verdict_archive_duration.saturating_mul(1000));
+
+    let pruned = prune_sampled_before(instance, SHRED_CF, SHREDS_PRUNED_THROUGH_KEY, shreds_since)?;
+    prune_sampled_before(
+        instance,
+        VERDICT_CF,
+        VERDICTS_PRUNED_THROUGH_KEY,
+        verdicts_since,
+    )?;
+    // the index only has to reach back as far as the longer of the two
+    forget_sampled_before(instance, shreds_since.min(verdicts_since))?;
+    Ok(pruned.map(|newest| newest + 1))
+}
+
+/// Deletes every entry of the column family below `cutoff`, returns whether there were any.
+/// Only the oldest key is read, the range is dropped without visiting what's in it
+pub fn prune_below(instance: &DB, cf_name: &str, cutoff: Slot) -> anyhow::Result<bool> {
+    let cf = instance
+        .cf_handle(cf_name)
+        .ok_or_else(|| anyhow!("column family {} not found", cf_name))?;
+
+    let oldest = match instance.iterator_cf(cf, IteratorMode::Start).next() {
+        Some(item) => slot_of_key(&item?.0),
+        None => None,
+    };
+    match oldest {
+        Some(oldest) if oldest < cutoff => {
+            instance.delete_range_cf(cf, slot_prefix(0), slot_prefix(cutoff))?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn newest_slot(instance: &DB, cf_name: &str) -> anyhow::Result<Option<Slot>> {
+    let cf = instance
+        .cf_handle(cf_name)
+        .ok_or_else(|| anyhow!("column family {} not found", cf_name))?;
+    match instance.iterator_cf(cf, IteratorMode::End).next() {
+        Some(item) => Ok(slot_of_key(&item?.0)),
+        None => Ok(None),
+    }
+}
+
+/// Drops every slot of the column family with an index entry from before `since` (ms)
+/// that wasn't sampled again since, starting where the last pass left off. Returns the
+/// newest slot dropped
+fn prune_sampled_before(
+    instance: &DB,
+    cf_name: &str,
+    pruned_through_key: &[u8],
+    since: u64,
+) -> anyhow::Result<Option<Slot>> {
+    let cf = instance
+        .cf_handle(cf_name)
+        .ok_or_else(|| anyhow!("column family {} not found", cf_name))?;
+    let sampled_at_cf = instance
+        .cf_handle(SAMPLED_AT_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", SAMPLED_AT_CF))?;
+
+    let pruned_through = match instance.get(pruned_through_key)? {
+        Some(value) => u64::from_be_bytes(
+            value
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow!("unreadable {} pruning progress", cf_name))?,
+        ),
+        None => 0,
+    };
+    let start = sampled_at_key(pruned_through, 0);
+    let end = sampled_at_key(since, 0);
+
+    let mut newest_pruned: Option<Slot> = None;
+    let mut batch = WriteBatch::default();
+    for item in instance.iterator_cf(
+        sampled_at_cf,
+        IteratorMode::From(&start, Direction::Forward),
+    ) {
+        let (key, _) = item?;
+        if key.as_ref() >= end.as_slice() {
+            break;
+        }
+        let Some((_, slot)) = parse_sampled_at_key(&key) else {
+            continue;
+        };
+        // the verdict carries the slot's latest sample, a later entry decides about it
+        let sampled_again =
+            get_verdict(instance, slot)?.map_or(false, |verdict| verdict.timestamp >= since);
+        if sampled_again {
+            continue;
+        }
+        batch.delete_range_cf(cf, slot_prefix(slot), slot_prefix(slot.saturating_add(1)));
+        newest_pruned = newest_pruned.max(Some(slot));
+    }
+    batch.put(pruned_through_key, since.max(pruned_through).to_be_bytes());
+    instance.write(batch)?;
+    Ok(newest_pruned)
+}
+
+/// Drops the index entries of slots sampled before `before` (ms)
+fn forget_sampled_before(instance: &DB, before: u64) -> anyhow::Result<()> {
+    let cf = instance
+        .cf_handle(SAMPLED_AT_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", SAMPLED_AT_CF))?;
+    instance.delete_range_cf(cf, sampled_at_key(0, 0), sampled_at_key(before, 0))?;
+    Ok(())
+}
+
+fn update_archive_metrics(instance: &DB) {
+    let Some(cf) = instance.cf_handle(SHRED_CF) else {
+        return;
+    };
+    if let Ok(Some(size)) = instance.property_int_value_cf(cf, "rocksdb.total-sst-files-size") {
+        ARCHIVE_SIZE_BYTES.set(size as i64);
+    }
+    if let Ok(Some(entries)) = instance.property_int_value_cf(cf, "rocksdb.estimate-num-keys") {
+        ARCHIVE_ENTRIES.set(entries as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{
+        open_temp_archive, test_verdict,
+        verdicts::{get_verdicts, put_verdict, SlotVerdict},
+    };
+    use solana_ledger::shred::ShredFlags;
+
+    fn archive_slot(instance: &DB, slot: Slot, sampled_at: u64) {
+        let shred = Shred::new_from_data(slot, 0, 1, &[], ShredFlags::empty(), 0, 0, 0);
+        put_shred(instance, &shred).unwrap();
+        let verdict = SlotVerdict {
+            timestamp: sampled_at,
+            ..test_verdict(slot)
+        };
+        put_verdict(instance, &verdict).unwrap();
+    }
+
+    fn archived_slots(instance: &DB) -> Vec<Slot> {
+        let mut slots: Vec<Slot> = scan_archive(instance, 0, Slot::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.slot)
+            .collect();
+        slots.dedup();
+        slots
+    }
+
+    fn verdict_slots(instance: &DB) -> Vec<Slot> {
+        get_verdicts(instance, 0, 1000)
+            .unwrap()
+            .into_iter()
+            .map(|verdict| verdict.slot)
+            .collect()
+    }
+
+    #[test]
+    fn values_round_trip_in_both_formats() {
+        let shred = Shred::new_from_data(7, 3, 1, &[1, 2, 3], ShredFlags::empty(), 0, 0, 0);
+
+        let value = encode_shred(&shred);
+        assert_eq!(
+            &value[..VALUE_HEADER_LEN],
+            &[VALUE_MAGIC, VALUE_VERSION_RAW]
+        );
+        assert!(!is_json_value(&value));
+        assert_eq!(decode_shred(&value).unwrap().payload(), shred.payload());
+
+        let json = serde_json::to_vec(&shred).unwrap();
+        assert!(is_json_value(&json));
+        assert_eq!(decode_shred(&json).unwrap().payload(), shred.payload());
+
+        assert!(decode_shred(&[VALUE_MAGIC, VALUE_VERSION_RAW + 1]).is_err());
+    }
+
+    #[test]
+    fn json_values_are_converted_in_place() {
+        let (instance, path) = open_temp_archive("convert");
+        let cf = instance.cf_handle(SHRED_CF).unwrap();
+        let shred = Shred::new_from_data(7, 3, 1, &[], ShredFlags::empty(), 0, 0, 0);
+        let key = archive_key(7, ShredType::Data, 3);
+        instance
+            .put_cf(cf, key, serde_json::to_vec(&shred).unwrap())
+            .unwrap();
+
+        assert_eq!(convert_json_values(&instance).unwrap(), 1);
+        let value = instance.get_cf(cf, key).unwrap().unwrap();
+        assert_eq!(value, encode_shred(&shred));
+        assert_eq!(convert_json_values(&instance).unwrap(), 0);
+
+        drop(instance);
+        let _ = DB::destroy(&rocksdb::Options::default(), &path);
+    }
+
+    #[test]
+    fn keys_sort_by_slot_type_and_index() {
+        let key = archive_key(7, ShredType::Code, 3);
+        assert_eq!(parse_archive_key(&key), Some((7, ShredType::Code, 3)));
+        assert!(archive_key(7, ShredType::Data, u32::MAX) < archive_key(8, ShredType::Data, 0));
+        assert!(parse_archive_key(&[0u8; LEGACY_KEY_LEN]).is_none());
+    }
+
+    #[test]
+    fn legacy_keys_are_migrated_once() {
+        let (instance, path) = open_temp_archive("migrate");
+        let cf = instance.cf_handle(SHRED_CF).unwrap();
+        let shred = Shred::new_from_data(7, 3, 1, &[], ShredFlags::empty(), 0, 0, 0);
+        let value = serde_json::to_vec(&shred).unwrap();
+        instance.put_cf(cf, [1u8; LEGACY_KEY_LEN], &value).unwrap();
+
+        assert_eq!(migrate_legacy_keys(&instance).unwrap(), 1);
+        assert!(get_shred(&instance, 7, ShredType::Data, 3)
+            .unwrap()
+            .is_some());
+
+        // the recorded schema version skips the scan
+        instance.put_cf(cf, [2u8; LEGACY_KEY_LEN], &value).unwrap();
+        assert_eq!(migrate_legacy_keys(&instance).unwrap(), 0);
+
+        drop(instance);
+        let _ = DB::destroy(&rocksdb::Options::default(), &path);
+    }
+
+    #[test]
+    fn stats_count_every_slot_of_the_range() {
+        let (instance, path) = open_temp_archive("stats");
+        for slot in [4, 5, 9] {
+            archive_slot(&instance, slot, timestamp());
+        }
+
+        let stats = archive_stats(&instance, 0, Slot::MAX).unwrap();
+        assert_eq!(stats.first_slot, Some(4));
+        assert_eq!(stats.last_slot, Some(9));
+        assert_eq!(stats.slots, 3);
+        assert_eq!(stats.data_shreds, 3);
+        assert_eq!(archive_stats(&instance, 5, 8).unwrap().slots, 1);
+
+        drop(instance);
+        let _ = DB::destroy(&rocksdb::Options::default(), &path);
+    }
+
+    #[test]
+    fn slot_durations_prune_shreds_and_verdicts_separately() {
+        let (instance, path) = open_temp_archive("prune-slots");
+        for slot in [1, 50, 100] {
+            archive_slot(&instance, slot, timestamp());
+        }
+        let config = ArchiveConfig {
+            shred_archive_duration: 10,
+            verdict_archive_duration: 60,
+            duration_unit: ArchiveDurationUnit::Slots,
+            archive_path: path.display().to_string(),
+        };
+
+        assert_eq!(prune_archive(&config, &instance).unwrap(), Some(90));
+        assert_eq!(archived_slots(&instance), vec![100]);
+        assert_eq!(verdict_slots(&instance), vec![50, 100]);
+        // nothing left to prune, nothing reported
+        assert_eq!(prune_archive(&config, &instance).unwrap(), None);
+
+        drop(instance);
+        let _ = DB::destroy(&rocksdb::Options::default(), &path);
+    }
+
+    #[test]
+    fn seconds_cutoff_doesnt_assume_slot_order() {
+        let (instance, path) = open_temp_archive("prune-seconds");
+        let now = timestamp();
+        archive_slot(&instance, 3, now - 120_000);
+        // sampled late, slot 5 has to survive even though slot 10 is older
+        archive_slot(&instance, 10, now - 120_000);
+        archive_slot(&instance, 5, now);
+        // an old slot sampled again on demand stays, and doesn't hold back what's older
+        archive_slot(&instance, 2, now - 120_000);
+        archive_slot(&instance, 2, now);
+        let config = ArchiveConfig {
+            shred_archive_duration: 60,
+            verdict_archive_duration: 600,
+            duration_unit: ArchiveDurationUnit::Seconds,
+            archive_path: path.display().to_string(),
+        };
+
+        assert_eq!(prune_archive(&config, &instance).unwrap(), Some(11));
+        assert_eq!(archived_slots(&instance), vec![2, 5]);
+        assert_eq!(verdict_slots(&instance), vec![2, 3, 5, 10]);
+        // the old entries were visited already
+        assert_eq!(prune_archive(&config, &instance).unwrap(), None);
+
+        drop(instance);
+        let _ = DB::destroy(&rocksdb::Options::default(), &path);
+    }
+}
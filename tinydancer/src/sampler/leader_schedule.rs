@@ -0,0 +1,255 @@
+//! Leader schedule cache - the leader for a slot is taken from the epoch's
+//! leader schedule instead of the `leader` field returned with the shreds,
+//! so an rpc can't vouch for shreds it signed itself.
+//!
+//! The schedule is only as trustworthy as where it comes from:
+//!
+//! - with `leaderScheduleEndpoint` set that endpoint is trusted outright, point
+//!   it at a node you run or otherwise trust
+//! - without it the schedule is pulled from every rpc provider and all of them
+//!   have to serve the same one, a provider can't slip in a forged leader
+//!   unless every other provider serves the same forgery
+//! - with a single provider and no trusted endpoint the rpc being audited
+//!   hands out its own schedule, which is warned about on startup
+//!
+//! Every provider has to answer and agree. Nothing is cached while one of them
+//! fails to answer, the schedule is fetched again for the next slot sampled.
+//! The epoch schedule is cross-checked the same way.
+use anyhow::anyhow;
+use dashmap::DashMap;
+use futures::future::join_all;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    clock::{Epoch, Slot},
+    epoch_schedule::EpochSchedule,
+    pubkey::Pubkey,
+};
+use std::{collections::HashMap, future::Future, str::FromStr, sync::Arc};
+use tiny_logger::logs::{info, warn};
+use tokio::sync::{Mutex, OnceCell};
+
+/// Number of epochs kept around, the current one plus a couple of older ones
+/// so slots sampled late around an epoch boundary still hit the cache
+const CACHED_EPOCHS: u64 = 3;
+
+pub struct LeaderScheduleCache {
+    rpc_clients: Vec<RpcClient>,
+    epoch_schedule: OnceCell<EpochSchedule>,
+    // leader of every slot in the epoch, indexed by the slot's offset into the epoch
+    schedules: DashMap<Epoch, Arc<Vec<Pubkey>>>,
+    fetch_lock: Mutex<()>,
+}
+
+impl LeaderScheduleCache {
+    /// Schedule from `trusted_endpoint` if there is one, otherwise cross-checked across `providers`
+    pub fn new(trusted_endpoint: Option<String>, providers: &[String]) -> Self {
+        let endpoints = match trusted_endpoint {
+            Some(endpoint) => vec![endpoint],
+            None => {
+                if providers.len() < 2 {
+                    warn!("leader schedule comes from the rpc being audited, set a leaderScheduleEndpoint or more rpcEndpoints to check it");
+                }
+                providers.to_vec()
+            }
+        };
+        Self {
+            rpc_clients: endpoints.into_iter().map(RpcClient::new).collect(),
+            epoch_schedule: OnceCell::new(),
+            schedules: DashMap::new(),
+            fetch_lock: Mutex::new(()),
+        }
+    }
+
+    /// `fetch` against every endpoint, see `agreed`
+    async fn fetch_agreed<'a, T, F, Fut>(&'a self, what: &str, fetch: F) -> anyhow::Result<T>
+    where
+        T: PartialEq,
+        F: Fn(&'a RpcClient) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let answers = join_all(self.rpc_clients.iter().map(|rpc_client| {
+            let answer = fetch(rpc_client);
+            async move { (rpc_client.url(), answer.await) }
+        }))
+        .await;
+        agreed(what, answers)
+    }
+
+    async fn epoch_schedule(&self) -> anyhow::Result<&EpochSchedule> {
+        self.epoch_schedule
+            .get_or_try_init(|| {
+                self.fetch_agreed("epoch schedule", |rpc_client| async move {
+                    rpc_client
+                        .get_epoch_schedule()
+                        .await
+                        .map_err(anyhow::Error::from)
+                })
+            })
+            .await
+            .map_err(|e| anyhow!("failed to get epoch schedule: {}", e))
+    }
+
+    /// The leader scheduled to produce `slot`
+    pub async fn slot_leader(&self, slot: Slot) -> anyhow::Result<Pubkey> {
+        let (epoch, slot_index) = self.epoch_schedule().await?.get_epoch_and_slot_index(slot);
+        let schedule = self.schedule_for_epoch(epoch, slot).await?;
+
+        schedule.get(slot_index as usize).copied().ok_or_else(|| {
+            anyhow!(
+                "slot {} missing from leader schedule of epoch {}",
+                slot,
+                epoch
+            )
+        })
+    }
+
+    async fn schedule_for_epoch(
+        &self,
+        epoch: Epoch,
+        slot: Slot,
+    ) -> anyhow::Result<Arc<Vec<Pubkey>>> {
+        if let Some(schedule) = self.schedules.get(&epoch) {
+            return Ok(schedule.clone());
+        }
+
+        // only one task fetches a schedule, the others wait and read it from the cache
+        let _lock = self.fetch_lock.lock().await;
+        if let Some(schedule) = self.schedules.get(&epoch) {
+            return Ok(schedule.clone());
+        }
+
+        let slots_in_epoch = self.epoch_schedule().await?.get_slots_in_epoch(epoch);
+        let what = format!("leader schedule of epoch {}", epoch);
+        let schedule = self
+            .fetch_agreed(&what, |rpc_client| async move {
+                let leader_schedule = rpc_client
+                    .get_leader_schedule(Some(slot))
+                    .await?
+                    .ok_or_else(|| anyhow!("no leader schedule for epoch {}", epoch))?;
+                index_schedule(leader_schedule, slots_in_epoch)
+            })
+            .await?;
+        info!("cached leader schedule for epoch {}", epoch);
+
+        let schedule = Arc::new(schedule);
+        self.schedules.insert(epoch, schedule.clone());
+        self.schedules
+            .retain(|cached_epoch, _| cached_epoch + CACHED_EPOCHS > epoch);
+
+        Ok(schedule)
+    }
+}
+
+/// Leader of every slot of the epoch, from `getLeaderSchedule`'s leader -> slot indices map.
+/// A schedule leaving a slot of the epoch without a leader is an error
+fn index_schedule(
+    leader_schedule: HashMap<String, Vec<usize>>,
+    slots_in_epoch: u64,
+) -> anyhow::Result<Vec<Pubkey>> {
+    let mut schedule: Vec<Option<Pubkey>> = vec![None; slots_in_epoch as usize];
+    for (leader, slot_indices) in leader_schedule {
+        let leader = Pubkey::from_str(&leader)?;
+        for slot_index in slot_indices {
+            let entry = schedule.get_mut(slot_index).ok_or_else(|| {
+                anyhow!(
+                    "slot index {} is past the end of an epoch of {} slots",
+                    slot_index,
+                    slots_in_epoch
+                )
+            })?;
+            *entry = Some(leader);
+        }
+    }
+    schedule
+        .into_iter()
+        .enumerate()
+        .map(|(slot_index, leader)| {
+            leader.ok_or_else(|| anyhow!("no leader scheduled for slot index {}", slot_index))
+        })
+        .collect()
+}
+
+/// The answer every endpoint gave, an endpoint failing to answer or two endpoints
+/// disagreeing is an error
+fn agreed<T: PartialEq>(
+    what: &str,
+    answers: Vec<(String, anyhow::Result<T>)>,
+) -> anyhow::Result<T> {
+    let mut agreed: Option<(String, T)> = None;
+    for (endpoint, answer) in answers {
+        let answer =
+            answer.map_err(|e| anyhow!("failed to get the {} from {}: {}", what, endpoint, e))?;
+        match &agreed {
+            Some((agreed_endpoint, agreed_answer)) if *agreed_answer != answer => {
+                return Err(anyhow!(
+                    "{} and {} serve a different {}",
+                    agreed_endpoint,
+                    endpoint,
+                    what
+                ));
+            }
+            Some(_) => {}
+            None => agreed = Some((endpoint, answer)),
+        }
+    }
+    agreed
+        .map(|(_, answer)| answer)
+        .ok_or_else(|| anyhow!("no endpoint served the {}", what))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(endpoint: &str, schedule: &[u8]) -> (String, anyhow::Result<Vec<u8>>) {
+        (endpoint.to_string(), Ok(schedule.to_vec()))
+    }
+
+    #[test]
+    fn providers_have_to_agree_on_the_schedule() {
+        let schedule =
+            agreed("schedule", vec![answer("a", &[1, 2]), answer("c", &[1, 2])]).unwrap();
+        assert_eq!(schedule, vec![1, 2]);
+
+        // an agreement of the providers that answered isn't enough
+        let err = agreed(
+            "schedule",
+            vec![
+                answer("a", &[1, 2]),
+                (String::from("b"), Err(anyhow!("timed out"))),
+                answer("c", &[1, 2]),
+            ],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("from b"));
+
+        let err = agreed("schedule", vec![answer("a", &[1, 2]), answer("b", &[1, 3])]).unwrap_err();
+        assert!(err.to_string().contains("a and b"));
+
+        assert!(agreed::<Vec<u8>>("schedule", vec![]).is_err());
+    }
+
+    #[test]
+    fn schedule_is_indexed_by_slot_offset() {
+        let leader = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let schedule = index_schedule(
+            HashMap::from([
+                (leader.to_string(), vec![1, 3]),
+                (other.to_string(), vec![0, 2]),
+            ]),
+            4,
+        )
+        .unwrap();
+        assert_eq!(schedule, vec![other, leader, other, leader]);
+
+        // slots without a leader, or past the end of the epoch
+        assert!(index_schedule(HashMap::from([(leader.to_string(), vec![1, 3])]), 4).is_err());
+        assert!(index_schedule(
+            HashMap::from([(leader.to_string(), vec![0, 1, 2, 3, 4])]),
+            4
+        )
+        .is_err());
+        assert!(index_schedule(HashMap::from([(String::from("nope"), vec![0])]), 4).is_err());
+    }
+}
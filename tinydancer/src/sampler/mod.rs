@@ -0,0 +1,1562 @@
+pub mod archive;
+pub mod leader_schedule;
+pub mod pipeline;
+pub mod providers;
+pub mod recovery;
+pub mod rpc_client;
+pub mod shred_source;
+pub mod slot_size;
+pub mod slot_updates;
+pub mod strategy;
+pub mod verdicts;
+
+use crate::supervisor::{ServiceError, Shutdown};
+use crate::tinydancer::{endpoint, ClientService, ClientStatus, Cluster};
+use crate::{convert_to_websocket, try_coerce_shred};
+use anyhow::anyhow;
+use archive::{archive_key, archive_pruner, put_shred, ArchiveDurationUnit};
+use async_trait::async_trait;
+use futures::Sink;
+use itertools::Itertools;
+use leader_schedule::LeaderScheduleCache;
+use pipeline::{
+    InFlightPermit, InFlightSample, PipelineConfig, QueuedSlot, SlotQueue, ARCHIVE_QUEUE_DEPTH,
+    SAMPLE_QUEUE_DEPTH, SLOTS_IN_RETRY_QUEUE,
+};
+use prometheus::{opts, register_gauge, register_int_counter, Gauge, IntCounter};
+use rand::distributions::Uniform;
+use rand::prelude::*;
+use rayon::prelude::*;
+use reqwest::Request;
+use rocksdb::{ColumnFamily, Options as RocksOptions, DB};
+use rpc_client::{JsonRpcClient, RpcError};
+use serde::de::DeserializeOwned;
+use shred_source::{cross_check_sources, ShredSource};
+use slot_size::SlotSizeCache;
+use slot_updates::{slot_update_loop, SamplingMode, SlotStream, SlotUpdate};
+use solana_ledger::shred::{ShredId, ShredType};
+use solana_ledger::{
+    ancestor_iterator::{AncestorIterator, AncestorIteratorWithHash},
+    blockstore::Blockstore,
+    // blockstore_db::columns::ShredCode,
+    shred::{Nonce, Shred, ShredCode, ShredData, ShredFetchStats, SIZE_OF_NONCE},
+};
+use solana_sdk::hash::hashv;
+use solana_sdk::{
+    clock::Slot,
+    commitment_config::CommitmentLevel,
+    genesis_config::ClusterType,
+    hash::{Hash, HASH_BYTES},
+    packet::PACKET_DATA_SIZE,
+    pubkey::{Pubkey, PUBKEY_BYTES},
+    signature::{Signable, Signature, Signer, SIGNATURE_BYTES},
+    signer::keypair::Keypair,
+    timing::{duration_as_ms, timestamp},
+};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{error::Error, future::Future, ops::Add};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    thread::Builder,
+};
+use strategy::{SampleIndex, SamplingStrategy};
+use tiny_logger::logs::{debug, error, info, warn};
+use tokio::{
+    sync::{
+        mpsc::{self, UnboundedSender},
+        Semaphore,
+    },
+    task::{JoinError, JoinHandle, JoinSet},
+    time::{Duration, Instant},
+};
+use tungstenite::{connect, Message};
+use url::Url;
+use verdicts::{put_verdict, RejectedShred, SlotVerdict, VerdictStore};
+
+pub const SHRED_CF: &str = "archived_shreds";
+
+lazy_static::lazy_static! {
+    static ref SLOTS_WRONG_LEADER: IntCounter =
+    register_int_counter!(opts!("tinydancer_slots_wrong_leader", "Number of sampled slots whose shreds aren't signed by the scheduled leader")).unwrap();
+    static ref SLOTS_CHANGED_AFTER_CONFIRMATION: IntCounter =
+    register_int_counter!(opts!("tinydancer_slots_changed_after_confirmation", "Number of slots whose shreds changed between confirmation and root")).unwrap();
+    static ref SLOT_CONFIDENCE: Gauge =
+    register_gauge!(opts!("tinydancer_slot_confidence", "Confidence reached by the last sampled slot")).unwrap();
+}
+
+pub struct SampleService {
+    sample_indices: Vec<u64>,
+    // peers: Vec<(Pubkey, SocketAddr)>,
+    sampler_handle: JoinHandle<anyhow::Result<()>>,
+}
+#[derive(Clone)]
+pub struct SampleServiceConfig {
+    pub cluster: Cluster,
+    pub archive_config: ArchiveConfig,
+    pub instance: Arc<rocksdb::DB>,
+    /// where verdicts are recorded, shared with the rpc bridge
+    pub verdicts: VerdictStore,
+    pub status_sampler: Arc<Mutex<ClientStatus>>,
+    pub strategy: SamplingStrategy,
+    /// which slot updates get sampled and at what commitment
+    pub mode: SamplingMode,
+    /// first source is sampled, the rest are cross-checked against it
+    pub shred_sources: Vec<Arc<dyn ShredSource>>,
+    pub leader_schedule: Arc<LeaderScheduleCache>,
+    pub pipeline: PipelineConfig,
+    /// slots the cluster creates are published on, shared with the rpc bridge
+    pub slots: SlotStream,
+    pub shutdown: Shutdown,
+}
+
+#[derive(Clone, Debug)]
+pub struct ArchiveConfig {
+    /// shreds older than this are pruned, in `duration_unit`
+    pub shred_archive_duration: u64,
+    /// verdicts older than this are pruned, in `duration_unit`
+    pub verdict_archive_duration: u64,
+    pub duration_unit: ArchiveDurationUnit,
+    pub archive_path: String,
+}
+
+#[async_trait]
+impl ClientService<SampleServiceConfig> for SampleService {
+    type ServiceError = ServiceError;
+
+    fn new(config: SampleServiceConfig) -> Self {
+        let sampler_handle = tokio::spawn(async move {
+            let rpc_url = endpoint(config.cluster);
+            let pub_sub = convert_to_websocket!(rpc_url);
+
+            // every loop runs until shutdown, one that stops before that takes the service down
+            let mut threads = JoinSet::new();
+            let shutdown = config.shutdown;
+
+            let pipeline = config.pipeline;
+            let slot_queue = Arc::new(SlotQueue::new(
+                pipeline.slot_queue_capacity,
+                pipeline.overflow_policy,
+            ));
+            let in_flight = Arc::new(Semaphore::new(pipeline.max_slots_in_flight.max(1)));
+            let (shred_tx, shred_rx) = mpsc::channel(pipeline.sample_queue_capacity.max(1));
+            let (verified_shred_tx, verified_shred_rx) =
+                mpsc::channel(pipeline.archive_queue_capacity.max(1));
+
+            let status_arc = config.status_sampler.clone();
+
+            // waits on new slots => triggers shred_update_loop
+            threads.spawn(until_shutdown(
+                "slot updates",
+                slot_update_loop(
+                    slot_queue.clone(),
+                    rpc_url,
+                    pub_sub,
+                    config.status_sampler,
+                    config.mode,
+                    config.slots,
+                ),
+                shutdown.clone(),
+            ));
+
+            // on shutdown no new slots are sampled, the samples in flight drain into the archive
+            threads.spawn({
+                let slot_queue = slot_queue.clone();
+                let shutdown = shutdown.clone();
+                async move {
+                    shutdown.cancelled().await;
+                    slot_queue.close();
+                    ("shutdown", Ok(()))
+                }
+            });
+
+            // sample shreds from new slot
+            // verify each shred in shred_verify_loop
+            threads.spawn(named(
+                "sampling",
+                shred_update_loop(
+                    slot_queue,
+                    in_flight,
+                    config.shred_sources,
+                    shred_tx,
+                    status_arc,
+                    config.strategy,
+                    config.leader_schedule,
+                    config.mode,
+                    pipeline.sampling_workers,
+                    pipeline.max_sample_attempts,
+                ),
+            ));
+
+            // verify shreds + store in db in shred_archiver
+            threads.spawn(named(
+                "verification",
+                shred_verify_loop(
+                    shred_rx,
+                    verified_shred_tx,
+                    config.strategy,
+                    config.verdicts,
+                ),
+            ));
+
+            threads.spawn(named(
+                "archiver",
+                shred_archiver(
+                    verified_shred_rx,
+                    config.archive_config.clone(),
+                    config.instance.clone(),
+                ),
+            ));
+
+            // drop shreds older than shred_archive_duration
+            threads.spawn(until_shutdown(
+                "archive pruner",
+                archive_pruner(config.archive_config, config.instance),
+                shutdown.clone(),
+            ));
+
+            while let Some(joined) = threads.join_next().await {
+                let (name, result) = joined?;
+                if shutdown.is_cancelled() {
+                    if let Err(e) = result {
+                        warn!("{} failed while shutting down: {}", name, e);
+                    }
+                    continue;
+                }
+                threads.shutdown().await;
+                return Err(match result {
+                    Err(e) => e.context(format!("{} failed", name)),
+                    Ok(()) => anyhow!("{} stopped", name),
+                });
+            }
+            Ok(())
+        });
+
+        Self {
+            sampler_handle,
+            sample_indices: Vec::default(),
+        }
+    }
+
+    async fn join(self) -> std::result::Result<(), Self::ServiceError> {
+        Ok(self.sampler_handle.await??)
+    }
+}
+
+async fn named(
+    name: &'static str,
+    task: impl Future<Output = anyhow::Result<()>>,
+) -> (&'static str, anyhow::Result<()>) {
+    (name, task.await)
+}
+
+/// Runs `task` until it ends or shutdown starts, for loops that never end on their own
+async fn until_shutdown(
+    name: &'static str,
+    task: impl Future<Output = anyhow::Result<()>>,
+    shutdown: Shutdown,
+) -> (&'static str, anyhow::Result<()>) {
+    tokio::select! {
+        result = task => (name, result),
+        _ = shutdown.cancelled() => (name, Ok(())),
+    }
+}
+
+pub async fn request_shreds(
+    slot: usize,
+    indices: Vec<usize>,
+    endpoint: String,
+) -> Result<GetShredResult, RpcError> {
+    request_shreds_of_type(
+        slot,
+        indices,
+        ShredType::Data,
+        CommitmentLevel::Confirmed,
+        &JsonRpcClient::new(endpoint),
+    )
+    .await
+}
+
+/// Coding shreds are requested through the same `getShreds` call with `"shredType": "code"`,
+/// `indices` are then offsets into the slot's coding index space. An rpc that doesn't know
+/// the option serves the data shreds at those indices instead, a response holding shreds
+/// of the other type is an error rather than a sample
+pub async fn request_shreds_of_type(
+    slot: usize,
+    indices: Vec<usize>,
+    shred_type: ShredType,
+    commitment: CommitmentLevel,
+    client: &JsonRpcClient,
+) -> Result<GetShredResult, RpcError> {
+    let config = match shred_type {
+        ShredType::Data => serde_json::json!({ "commitment": commitment }),
+        ShredType::Code => serde_json::json!({ "commitment": commitment, "shredType": "code" }),
+    };
+    let response: GetShredResult = client
+        .call("getShreds", serde_json::json!([slot, indices, config]))
+        .await?;
+
+    let wrong_type = response
+        .shreds
+        .iter()
+        .filter_map(|shred| try_coerce_shred!(shred))
+        .find(|shred| shred.shred_type() != shred_type);
+    if let Some(shred) = wrong_type {
+        return Err(RpcError::Unexpected {
+            method: String::from("getShreds"),
+            message: format!(
+                "{:?} shred {} served for a {:?} shred request of slot {}",
+                shred.shred_type(),
+                shred.index(),
+                shred_type,
+                slot
+            ),
+        });
+    }
+    Ok(response)
+}
+
+macro_rules! unwrap_or_return {
+    (Result $var:ident) => {
+        if let Err(e) = $var {
+            return Err(e.into());
+        } else {
+            $var.unwrap()
+        }
+    };
+    (Option $var:ident $err:expr) => {
+        if $var.is_none() {
+            return Err(anyhow!($err));
+        } else {
+            $var.unwrap()
+        }
+    };
+    (OptionRef $var:ident $err:expr) => {
+        if $var.is_none() {
+            return Err(anyhow!($err));
+        } else {
+            $var.as_ref().unwrap()
+        }
+    };
+}
+
+/// Shreds sampled for a slot along with the indices that were requested
+#[derive(Debug, Clone)]
+pub struct SlotSample {
+    pub slot: Slot,
+    /// requested data shred indices
+    pub indices: Vec<usize>,
+    /// requested coding shred indices
+    pub coding_indices: Vec<usize>,
+    /// data + coding shreds the slot is assumed to have
+    pub population: usize,
+    pub shreds: Vec<Option<Shred>>,
+    /// leader from the epoch's leader schedule, shreds are verified against this one
+    pub leader: Pubkey,
+    /// leader the shred source says signed the shreds
+    pub claimed_leader: Option<Pubkey>,
+    /// false if another source served different shreds for the slot, or the
+    /// shreds changed between confirmation and root
+    pub consistent: bool,
+    pub commitment: CommitmentLevel,
+}
+
+impl SlotSample {
+    /// Number of distinct shreds received
+    pub fn received(&self) -> usize {
+        self.shreds
+            .iter()
+            .flatten()
+            .map(|s| (s.shred_type(), s.index()))
+            .unique()
+            .count()
+    }
+
+    /// Number of requested shreds that didn't come back, counted as withheld
+    pub fn missing(&self) -> usize {
+        (self.indices.len() + self.coding_indices.len()).saturating_sub(self.received())
+    }
+
+    /// Confidence of the sample assuming every received shred is valid
+    pub fn confidence(&self, strategy: &SamplingStrategy) -> f64 {
+        strategy.confidence(self.population, self.received())
+    }
+
+    fn is_requested(&self, shred: &Shred) -> bool {
+        let indices = match shred.shred_type() {
+            ShredType::Data => &self.indices,
+            ShredType::Code => &self.coding_indices,
+        };
+        indices.contains(&(shred.index() as usize))
+    }
+
+    /// Drops the shreds that weren't asked for, sources don't filter what they serve
+    /// and extra shreds mustn't add to the confidence
+    fn retain_requested(&mut self) {
+        let mut shreds = std::mem::take(&mut self.shreds);
+        shreds.retain(|shred| match shred {
+            Some(shred) if !self.is_requested(shred) => {
+                debug!(
+                    "Received unrequested {:?} shred index: {:?} for slot: {:?}",
+                    shred.shred_type(),
+                    shred.index(),
+                    self.slot
+                );
+                false
+            }
+            _ => true,
+        });
+        self.shreds = shreds;
+    }
+}
+
+/// Outcome of sampling a slot
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Verdict {
+    Valid,
+    Invalid,
+    /// sampled shreds aren't signed by the scheduled leader, or the shred source
+    /// claims another one
+    WrongLeader,
+    /// shreds verified but erasure recovery of the slot failed (deep verify only)
+    Unrecoverable,
+}
+
+impl Verdict {
+    /// Verdict of a sample, the same on every path a slot is sampled on. A requested
+    /// shred that didn't come back is taken for a withheld one. Whether the sample
+    /// also reached the target confidence is up to `SlotVerdict::passed`
+    pub fn of_sample(valid: usize, invalid: usize, missing: usize, consistent: bool) -> Self {
+        match valid > 0 && invalid == 0 && missing == 0 && consistent {
+            true => Verdict::Valid,
+            false => Verdict::Invalid,
+        }
+    }
+}
+
+/// Outcome of verifying a single sampled shred
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SampleVerdict {
+    Valid,
+    /// not signed by the scheduled leader
+    BadSignature,
+    /// signed, but the shred isn't part of the merkle tree it claims
+    BadMerkleProof,
+    /// pre merkle shred, only the signature could be checked
+    LegacySignatureOnly,
+    /// the merkle proof could not even be evaluated
+    Malformed,
+    /// a shred of another slot than the one requested
+    WrongSlot,
+}
+
+impl SampleVerdict {
+    pub fn is_valid(&self) -> bool {
+        matches!(
+            self,
+            SampleVerdict::Valid | SampleVerdict::LegacySignatureOnly
+        )
+    }
+}
+
+async fn get_shreds_and_leader_for_slot(
+    slot: u64,
+    source: &dyn ShredSource,
+    strategy: &SamplingStrategy,
+    leader_schedule: &LeaderScheduleCache,
+    slot_sizes: &SlotSizeCache,
+    commitment: CommitmentLevel,
+) -> anyhow::Result<SlotSample> {
+    let slot_size = slot_sizes.get_or_discover(slot, source, commitment).await?;
+    debug!("slot {} size {:?}", slot, slot_size);
+
+    // get a random sample of shreds across the whole slot, without replacement across data and coding shreds
+    let sample_indices =
+        strategy.sample_indices(slot_size.num_data_shreds, slot_size.num_coding_shreds);
+    let indices_of_type = |shred_type: ShredType| {
+        sample_indices
+            .iter()
+            .filter(|i| i.shred_type == shred_type)
+            .map(|i| i.index as usize)
+            .collect::<Vec<usize>>()
+    };
+    let data_indices = indices_of_type(ShredType::Data);
+    let coding_indices = indices_of_type(ShredType::Code);
+    info!(
+        "indices of: data {:?} code {:?} {:?}",
+        data_indices, coding_indices, slot
+    );
+
+    let shreds_for_slot = source.get_shreds(slot, &data_indices, commitment).await;
+    let shreds_for_slot = unwrap_or_return!(Result shreds_for_slot);
+
+    debug!("get shred for slot in 2nd req");
+    let mut shreds: Vec<Option<Shred>> = shreds_for_slot.shreds;
+    let mut faults = shreds_for_slot.faults;
+    if !coding_indices.is_empty() {
+        match source
+            .get_coding_shreds(slot, &coding_indices, commitment)
+            .await
+        {
+            Ok(coding_shreds) => {
+                shreds.extend(coding_shreds.shreds);
+                faults.extend(coding_shreds.faults);
+            }
+            Err(e) => info!("coding shreds unavailable for slot {:?}: {}", slot, e),
+        }
+    }
+
+    // info!("before leader");
+    let leader = leader_schedule.slot_leader(slot).await;
+    let leader = unwrap_or_return!(Result leader);
+
+    // info!("leader {:?}", leader);
+    dedup_shreds(&mut shreds);
+
+    let mut sample = SlotSample {
+        slot,
+        indices: data_indices,
+        coding_indices,
+        population: slot_size.population(),
+        shreds,
+        leader,
+        claimed_leader: shreds_for_slot.leader,
+        // providers serving different shreds or leaders for the slot
+        consistent: !faults.iter().any(|fault| fault.is_inconsistency()),
+        commitment,
+    };
+    sample.retain_requested();
+    if sample.missing() > 0 {
+        info!("Received incomplete number of shreds, requested {:?} shreds for slot {:?} and received {:?}", sample_indices.len(), slot, sample.received());
+    }
+    Ok(sample)
+}
+
+/// Keeps the first of each shred, sources may serve the same one more than once and
+/// it must only count once towards the confidence
+fn dedup_shreds(shreds: &mut Vec<Option<Shred>>) {
+    let mut seen = HashSet::new();
+    shreds.retain(|shred| match shred {
+        Some(shred) => seen.insert((shred.shred_type(), shred.index())),
+        None => true,
+    });
+}
+
+/// Pulls the exact shreds of an earlier sample again at `commitment`
+async fn resample_slot(
+    sample: &SlotSample,
+    source: &dyn ShredSource,
+    commitment: CommitmentLevel,
+) -> anyhow::Result<SlotSample> {
+    let data = source
+        .get_shreds(sample.slot, &sample.indices, commitment)
+        .await?;
+    let mut shreds = data.shreds;
+    let mut faults = data.faults;
+    if !sample.coding_indices.is_empty() {
+        match source
+            .get_coding_shreds(sample.slot, &sample.coding_indices, commitment)
+            .await
+        {
+            Ok(coding_shreds) => {
+                shreds.extend(coding_shreds.shreds);
+                faults.extend(coding_shreds.faults);
+            }
+            Err(e) => info!(
+                "coding shreds unavailable for slot {:?}: {}",
+                sample.slot, e
+            ),
+        }
+    }
+    dedup_shreds(&mut shreds);
+
+    let mut resample = SlotSample {
+        slot: sample.slot,
+        indices: sample.indices.clone(),
+        coding_indices: sample.coding_indices.clone(),
+        population: sample.population,
+        shreds,
+        leader: sample.leader,
+        claimed_leader: data.leader,
+        consistent: !faults.iter().any(|fault| fault.is_inconsistency()),
+        commitment,
+    };
+    resample.retain_requested();
+    Ok(resample)
+}
+
+/// Shreds of `before` that are missing from or differ in `after`
+fn changed_shreds(before: &SlotSample, after: &SlotSample) -> Vec<u32> {
+    before
+        .shreds
+        .iter()
+        .flatten()
+        .filter(|shred| {
+            !after.shreds.iter().flatten().any(|other| {
+                other.shred_type() == shred.shred_type()
+                    && other.index() == shred.index()
+                    && other.payload() == shred.payload()
+            })
+        })
+        .map(|shred| shred.index())
+        .collect()
+}
+
+/// True if sampled shreds failed the signature check against the scheduled leader,
+/// or the shred source claims another leader for the slot
+fn wrong_leader(sample: &SlotSample, rejected: &[RejectedShred]) -> bool {
+    let bad_signatures = rejected
+        .iter()
+        .filter(|shred| shred.verdict == SampleVerdict::BadSignature)
+        .count();
+    let claimed_leader = sample
+        .claimed_leader
+        .filter(|claimed_leader| *claimed_leader != sample.leader);
+    if bad_signatures == 0 && claimed_leader.is_none() {
+        return false;
+    }
+    error!(
+        "WRONG LEADER for slot: {:?} {} shreds not signed by the scheduled leader {}, source claims {:?}",
+        sample.slot, bad_signatures, sample.leader, claimed_leader
+    );
+    true
+}
+
+/// Cross-checks a sample taken from the primary source against every other source
+async fn cross_check_sample(sample: &SlotSample, sources: &[Arc<dyn ShredSource>]) -> bool {
+    let mut consistent = true;
+    for secondary in sources.iter().skip(1) {
+        match cross_check_sources(
+            sample.slot,
+            &sample.indices,
+            &sample.coding_indices,
+            sample.commitment,
+            sources[0].as_ref(),
+            secondary.as_ref(),
+        )
+        .await
+        {
+            Ok(report) if report.is_consistent() => info!("cross-check passed {}", report),
+            Ok(report) => {
+                consistent = false;
+                error!("cross-check FAILED {}", report);
+            }
+            Err(e) => info!(
+                "cross-check against {} skipped for slot {}: {}",
+                secondary.name(),
+                sample.slot,
+                e
+            ),
+        }
+    }
+    consistent
+}
+
+/// Delay before a slot that failed to sample goes back on the slot queue
+const SAMPLE_RETRY_DELAY: Duration = Duration::from_millis(400);
+
+/// State shared by the sampling workers
+struct SamplingContext {
+    slot_queue: Arc<SlotQueue>,
+    in_flight: Arc<Semaphore>,
+    sources: Vec<Arc<dyn ShredSource>>,
+    shred_tx: mpsc::Sender<InFlightSample>,
+    retry_tx: mpsc::UnboundedSender<(QueuedSlot, Instant)>,
+    status_sampler: Arc<Mutex<ClientStatus>>,
+    strategy: SamplingStrategy,
+    leader_schedule: Arc<LeaderScheduleCache>,
+    slot_sizes: SlotSizeCache,
+    mode: SamplingMode,
+    /// attempts at sampling a slot before giving up on it
+    max_sample_attempts: u8,
+    // samples taken at confirmation, waiting for their slot to be rooted
+    confirmed_samples: Mutex<HashMap<Slot, SlotSample>>,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn shred_update_loop(
+    slot_queue: Arc<SlotQueue>,
+    in_flight: Arc<Semaphore>,
+    sources: Vec<Arc<dyn ShredSource>>,
+    shred_tx: mpsc::Sender<InFlightSample>,
+    status_sampler: Arc<Mutex<ClientStatus>>,
+    strategy: SamplingStrategy,
+    leader_schedule: Arc<LeaderScheduleCache>,
+    mode: SamplingMode,
+    sampling_workers: usize,
+    max_sample_attempts: u8,
+) -> anyhow::Result<()> {
+    let (retry_tx, retry_rx) = mpsc::unbounded_channel();
+    let context = Arc::new(SamplingContext {
+        slot_queue: slot_queue.clone(),
+        in_flight,
+        sources,
+        shred_tx,
+        retry_tx,
+        status_sampler,
+        strategy,
+        leader_schedule,
+        slot_sizes: SlotSizeCache::default(),
+        mode,
+        max_sample_attempts,
+        confirmed_samples: Mutex::new(HashMap::new()),
+    });
+
+    // dropped when this loop stops or is aborted with the service, which aborts every task
+    let mut tasks = JoinSet::new();
+
+    // queues the slots that failed to sample back after a delay
+    tasks.spawn(async move {
+        sample_retry_loop(slot_queue, retry_rx).await;
+        Ok(())
+    });
+
+    for _ in 0..sampling_workers.max(1) {
+        tasks.spawn(sampling_worker(context.clone()));
+    }
+    drop(context);
+
+    while let Some(task) = tasks.join_next().await {
+        task??;
+    }
+    Ok(())
+}
+
+async fn sampling_worker(context: Arc<SamplingContext>) -> anyhow::Result<()> {
+    loop {
+        {
+            let mut status = context.status_sampler.lock().unwrap();
+            if let ClientStatus::Crashed(_) = &*status {
+                context.slot_queue.close();
+                return Err(anyhow!("Client crashed"));
+            }
+        }
+
+        // wait for room in the pipeline before taking the next slot
+        let permit = InFlightPermit::acquire(&context.in_flight).await;
+        // the queue only closes on shutdown or after another worker crashed
+        let Some(queued) = context.slot_queue.pop().await else {
+            return Ok(());
+        };
+        let slot = queued.update.slot;
+
+        let sample = match sample_slot(&context, queued.update).await {
+            Ok(sample) => sample,
+            Err(e) if queued.attempt + 1 < context.max_sample_attempts => {
+                info!("sampling slot {:?} failed, retrying: {}", slot, e);
+                let retry = QueuedSlot {
+                    attempt: queued.attempt + 1,
+                    ..queued
+                };
+                if context
+                    .retry_tx
+                    .send((retry, Instant::now() + SAMPLE_RETRY_DELAY))
+                    .is_ok()
+                {
+                    SLOTS_IN_RETRY_QUEUE.inc();
+                }
+                continue;
+            }
+            Err(e) => {
+                warn!(
+                    "giving up on slot {:?} after {} attempts: {}",
+                    slot, context.max_sample_attempts, e
+                );
+                continue;
+            }
+        };
+
+        mark_sampling(&context.status_sampler);
+        let sample = InFlightSample::new(sample, &queued, permit);
+        if context.shred_tx.send(sample).await.is_err() {
+            context.slot_queue.close();
+            return Err(anyhow!("shred verify loop stopped"));
+        }
+        SAMPLE_QUEUE_DEPTH.inc();
+    }
+}
+
+/// A sampled slot shows the client is up, the states it doesn't settle are left
+/// to whoever set them
+fn mark_sampling(status: &Mutex<ClientStatus>) {
+    let mut status = status.lock().unwrap();
+    if matches!(
+        &*status,
+        ClientStatus::Initializing(_) | ClientStatus::SearchingForRPCService(_)
+    ) {
+        *status = ClientStatus::Active(String::from(
+            "Monitoring Tinydancer: Actively Sampling Shreds",
+        ));
+    }
+}
+
+async fn sample_retry_loop(
+    slot_queue: Arc<SlotQueue>,
+    mut retry_rx: mpsc::UnboundedReceiver<(QueuedSlot, Instant)>,
+) {
+    while let Some((queued, retry_at)) = retry_rx.recv().await {
+        SLOTS_IN_RETRY_QUEUE.dec();
+        tokio::time::sleep_until(retry_at).await;
+        if slot_queue.push(queued).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Samples a slot at the update's commitment, resampling the shreds picked at
+/// confirmation if the slot was already sampled then
+async fn sample_slot(
+    context: &SamplingContext,
+    SlotUpdate { slot, commitment }: SlotUpdate,
+) -> anyhow::Result<SlotSample> {
+    let confirmed_sample = match commitment {
+        CommitmentLevel::Finalized => {
+            let mut confirmed_samples = context.confirmed_samples.lock().unwrap();
+            let confirmed_sample = confirmed_samples.remove(&slot);
+            // slots confirmed below the new root never made it, they were forked out
+            confirmed_samples.retain(|confirmed_slot, _| *confirmed_slot > slot);
+            confirmed_sample
+        }
+        _ => None,
+    };
+
+    let source = context.sources[0].as_ref();
+    let sample = match &confirmed_sample {
+        Some(confirmed_sample) => resample_slot(confirmed_sample, source, commitment).await,
+        None => {
+            get_shreds_and_leader_for_slot(
+                slot,
+                source,
+                &context.strategy,
+                &context.leader_schedule,
+                &context.slot_sizes,
+                commitment,
+            )
+            .await
+        }
+    };
+    let mut sample = match sample {
+        Ok(sample) => sample,
+        Err(e) => {
+            // keep the confirmed sample around for the retry
+            if let Some(confirmed_sample) = confirmed_sample {
+                context
+                    .confirmed_samples
+                    .lock()
+                    .unwrap()
+                    .insert(slot, confirmed_sample);
+            }
+            return Err(e);
+        }
+    };
+    sample.consistent &= cross_check_sample(&sample, &context.sources).await;
+
+    if let Some(confirmed_sample) = confirmed_sample {
+        let changed = changed_shreds(&confirmed_sample, &sample);
+        if !changed.is_empty() {
+            SLOTS_CHANGED_AFTER_CONFIRMATION.inc();
+            error!(
+                "SHREDS CHANGED for slot: {:?} between confirmation and root, indices {:?}",
+                slot, changed
+            );
+            sample.consistent = false;
+        }
+    } else if context.mode == SamplingMode::ConfirmedAndFinalized
+        && commitment == CommitmentLevel::Confirmed
+    {
+        context
+            .confirmed_samples
+            .lock()
+            .unwrap()
+            .insert(slot, sample.clone());
+    }
+
+    Ok(sample)
+}
+
+// use solana_ledger::shred::dispatch;
+
+// verifies the merkle proof of the shread
+/// Checks the shred belongs to `slot`, is signed by `leader` and, for merkle shreds,
+/// that its merkle proof holds
+pub fn verify_sample(
+    shred: &Shred,
+    leader: solana_ledger::shred::Pubkey,
+    slot: Slot,
+) -> SampleVerdict {
+    if shred.slot() != slot {
+        return SampleVerdict::WrongSlot;
+    }
+
+    let verify_merkle_proof = match shred {
+        Shred::ShredData(ShredData::Merkle(shred)) => Some(shred.verify_merkle_proof()),
+        Shred::ShredCode(ShredCode::Merkle(shred)) => Some(shred.verify_merkle_proof()),
+        _ => None,
+    };
+    let signed = shred.verify(&leader);
+
+    match (verify_merkle_proof, signed) {
+        (Some(Err(e)), _) => {
+            debug!("malformed merkle shred {:?}: {}", shred.index(), e);
+            SampleVerdict::Malformed
+        }
+        (_, false) => SampleVerdict::BadSignature,
+        (Some(Ok(false)), true) => SampleVerdict::BadMerkleProof,
+        (Some(Ok(true)), true) => SampleVerdict::Valid,
+        (None, true) => SampleVerdict::LegacySignatureOnly,
+    }
+}
+
+pub async fn shred_verify_loop(
+    mut shred_rx: mpsc::Receiver<InFlightSample>,
+    verified_shred_tx: mpsc::Sender<(Shred, solana_ledger::shred::Pubkey)>,
+    strategy: SamplingStrategy,
+    verdicts: VerdictStore,
+) -> anyhow::Result<()> {
+    loop {
+        if let Some(in_flight) = shred_rx.recv().await {
+            SAMPLE_QUEUE_DEPTH.dec();
+            let leader = in_flight.sample.leader;
+            // signature checks are cpu bound, keep them off the runtime threads
+            let (in_flight, results) = tokio::task::spawn_blocking(move || {
+                let sample = &in_flight.sample;
+                let results: Vec<SampleVerdict> = sample
+                    .shreds
+                    .par_iter()
+                    .flatten()
+                    .map(|shred| verify_sample(shred, leader, sample.slot))
+                    .collect();
+                (in_flight, results)
+            })
+            .await?;
+            let sample = &in_flight.sample;
+
+            let mut rejected = vec![];
+            for (shred, sample_verdict) in sample.shreds.iter().flatten().zip(results) {
+                match sample_verdict {
+                    SampleVerdict::Valid | SampleVerdict::LegacySignatureOnly => {
+                        info!(
+                            "sample {:?} verified ({:?}) for slot: {:?}",
+                            shred.index(),
+                            sample_verdict,
+                            shred.slot()
+                        );
+                        match verified_shred_tx.send((shred.clone(), leader)).await {
+                            Ok(_) => ARCHIVE_QUEUE_DEPTH.inc(),
+                            Err(e) => error!("Error verified_shred_tx: {}", e),
+                        }
+                    }
+                    _ => {
+                        info!(
+                            "sample {:?} INVALID ({:?}) for slot : {:?}",
+                            shred.index(),
+                            sample_verdict,
+                            sample.slot
+                        );
+                        rejected.push(RejectedShred {
+                            shred_type: shred.shred_type(),
+                            index: shred.index(),
+                            verdict: sample_verdict,
+                        });
+                    }
+                }
+            }
+
+            let invalid = rejected.len();
+            let valid = sample.shreds.iter().flatten().count() - invalid;
+            let confidence = strategy.confidence(sample.population, valid);
+            SLOT_CONFIDENCE.set(confidence);
+            info!(
+                "slot {:?} sampled with confidence {:.4} (target {})",
+                sample.slot, confidence, strategy.target_confidence
+            );
+
+            let verdict = match wrong_leader(sample, &rejected) {
+                true => {
+                    SLOTS_WRONG_LEADER.inc();
+                    Verdict::WrongLeader
+                }
+                false => Verdict::of_sample(valid, invalid, sample.missing(), sample.consistent),
+            };
+            record_verdict(
+                &verdicts,
+                SlotVerdict::new(sample, valid, rejected, confidence, verdict),
+            );
+            in_flight.finish();
+        } else {
+            return Ok(());
+        }
+    }
+}
+
+// store verified shreds in db
+fn record_verdict(verdicts: &VerdictStore, verdict: SlotVerdict) {
+    let (slot, slot_verdict) = (verdict.slot, verdict.verdict);
+    match verdicts.record(verdict) {
+        Ok(_) => info!("Saved verdict {:?} for slot {:?}", slot_verdict, slot),
+        Err(e) => error!("failed to save verdict for slot {:?}: {}", slot, e),
+    }
+}
+
+pub async fn shred_archiver(
+    mut verified_shred_rx: mpsc::Receiver<(Shred, solana_ledger::shred::Pubkey)>,
+    _archive_config: ArchiveConfig,
+    instance: Arc<rocksdb::DB>,
+) -> anyhow::Result<()> {
+    loop {
+        if let Some((verified_shred, leader)) = verified_shred_rx.recv().await {
+            ARCHIVE_QUEUE_DEPTH.dec();
+            let mut opts = RocksOptions::default();
+            opts.create_if_missing(true);
+            opts.set_error_if_exists(false);
+            opts.create_missing_column_families(true);
+
+            // info!("archiver {:?}", verified_shred.slot(),);
+            // let cfs =
+            //     rocksdb::DB::list_cf(&opts, archive_config.archive_path.clone()).unwrap_or(vec![]);
+            // let shred_cf = cfs.clone().into_iter().find(|cf| cf.as_str() == SHRED_CF);
+            // let instance =
+            //     DB::open_cf(&opts, archive_config.archive_path.clone(), vec![SHRED_CF]).unwrap();
+            // match shred_cf {
+            //     Some(cf_name) => {
+            let put_response = put_shred(&instance, &verified_shred);
+            match put_response {
+                Ok(_) => info!("Saved Shred {:?} to db", verified_shred.id().seed(&leader)),
+                Err(e) => info!("{:?}", e),
+            }
+            //     }
+            //     None => instance
+            //         .create_cf(SHRED_CF, &RocksOptions::default())
+            //         .unwrap(),
+            // }
+        } else {
+            return Ok(());
+        }
+    }
+}
+
+/// True if every sampled shred is valid and the sample reached the target confidence,
+/// with `deep_verify` the slot also has to pass erasure recovery
+pub async fn pull_and_verify_shreds(
+    slot: usize,
+    endpoint: String,
+    strategy: &SamplingStrategy,
+    leader_schedule: &LeaderScheduleCache,
+    commitment: CommitmentLevel,
+    deep_verify: bool,
+) -> bool {
+    let sources: Vec<Arc<dyn ShredSource>> =
+        vec![Arc::new(shred_source::RpcShredSource::new(endpoint))];
+    match verify_slot_from_sources(
+        slot as u64,
+        &sources,
+        strategy,
+        leader_schedule,
+        commitment,
+        deep_verify,
+    )
+    .await
+    {
+        Ok(verdict) => verdict.passed(strategy.target_confidence),
+        Err(e) => {
+            info!("{}", e);
+            false
+        }
+    }
+}
+
+/// Samples the slot from the first source, verifies it and cross-checks it against the others,
+/// returns the verdict along with the confidence the sample reached
+pub async fn pull_and_verify_shreds_from_sources(
+    slot: usize,
+    sources: &[Arc<dyn ShredSource>],
+    strategy: &SamplingStrategy,
+    leader_schedule: &LeaderScheduleCache,
+    commitment: CommitmentLevel,
+    deep_verify: bool,
+) -> (Verdict, f64) {
+    match verify_slot_from_sources(
+        slot as u64,
+        sources,
+        strategy,
+        leader_schedule,
+        commitment,
+        deep_verify,
+    )
+    .await
+    {
+        Ok(verdict) if verdict.verdict == Verdict::Valid => (Verdict::Valid, verdict.confidence),
+        Ok(verdict) => (verdict.verdict, 0.0),
+        Err(e) => {
+            info!("{}", e);
+            (Verdict::Invalid, 0.0)
+        }
+    }
+}
+
+/// Same as `pull_and_verify_shreds_from_sources` but keeps the per shred results,
+/// fails if the slot couldn't be sampled at all
+pub async fn verify_slot_from_sources(
+    slot: Slot,
+    sources: &[Arc<dyn ShredSource>],
+    strategy: &SamplingStrategy,
+    leader_schedule: &LeaderScheduleCache,
+    commitment: CommitmentLevel,
+    deep_verify: bool,
+) -> anyhow::Result<SlotVerdict> {
+    let sample = get_shreds_and_leader_for_slot(
+        slot,
+        sources[0].as_ref(),
+        strategy,
+        leader_schedule,
+        // a one off sample, the slot's size isn't needed again
+        &SlotSizeCache::new(1),
+        commitment,
+    )
+    .await?;
+
+    let rejected: Vec<RejectedShred> = sample
+        .shreds
+        .par_iter()
+        .flatten()
+        .filter_map(|shred| {
+            let verdict = verify_sample(shred, sample.leader, sample.slot);
+            (!verdict.is_valid()).then(|| RejectedShred {
+                shred_type: shred.shred_type(),
+                index: shred.index(),
+                verdict,
+            })
+        })
+        .collect();
+    let valid = sample.shreds.iter().flatten().count() - rejected.len();
+    let confidence = strategy.confidence(sample.population, valid);
+    let consistent = sample.consistent && cross_check_sample(&sample, sources).await;
+
+    info!(
+        "pull and verify slot {} valid {} invalid {} consistent {:?}",
+        slot,
+        valid,
+        rejected.len(),
+        consistent
+    );
+    let verdict = match Verdict::of_sample(valid, rejected.len(), sample.missing(), consistent) {
+        _ if wrong_leader(&sample, &rejected) => Verdict::WrongLeader,
+        Verdict::Valid if deep_verify => {
+            let report = recovery::deep_verify_slot(&sample, sources[0].as_ref()).await;
+            info!("{}", report);
+            match report.is_recoverable() {
+                true => Verdict::Valid,
+                false => Verdict::Unrecoverable,
+            }
+        }
+        verdict => verdict,
+    };
+    Ok(SlotVerdict::new(
+        &sample, valid, rejected, confidence, verdict,
+    ))
+}
+
+pub fn put_serialized<T: serde::Serialize + std::fmt::Debug>(
+    instance: &rocksdb::DB,
+    cf: &ColumnFamily,
+    key: impl AsRef<[u8]>,
+    value: &T,
+) -> Result<(), String> {
+    match serde_json::to_string(&value) {
+        Ok(serialized) => instance
+            .put_cf(cf, key, serialized.into_bytes())
+            .map_err(|err| format!("Failed to put to ColumnFamily:{:?}", err)),
+        Err(err) => Err(format!(
+            "Failed to serialize to String. T: {:?}, err: {:?}",
+            value, err
+        )),
+    }
+}
+pub fn get_serialized<T: DeserializeOwned>(
+    instance: &rocksdb::DB,
+    cf: &ColumnFamily,
+    key: impl AsRef<[u8]>,
+) -> Result<Option<T>, String> {
+    match instance.get_cf(cf, key) {
+        Ok(opt) => match opt {
+            Some(found) => match String::from_utf8(found) {
+                Ok(s) => match serde_json::from_str::<T>(&s) {
+                    Ok(t) => Ok(Some(t)),
+                    Err(err) => Err(format!("Failed to deserialize: {:?}", err)),
+                },
+                Err(err) => Err(format!("Failed to convert to String: {:?}", err)),
+            },
+            None => Ok(None),
+        },
+        Err(err) => Err(format!("Failed to get from ColumnFamily: {:?}", err)),
+    }
+}
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotSubscribeResponse {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: SlotSubscribeParams,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotSubscribeParams {
+    pub result: SlotSubscribeResult,
+    pub subscription: i64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotSubscribeResult {
+    pub parent: i64,
+    pub root: i64,
+    pub slot: i64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotsUpdatesResponse {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: SlotsUpdatesParams,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotsUpdatesParams {
+    pub result: SlotsUpdatesResult,
+    pub subscription: i64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotsUpdatesResult {
+    pub slot: u64,
+    pub timestamp: u64,
+    #[serde(rename = "type")]
+    pub update_type: String,
+    /// only set on `createdBank` updates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<u64>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetShredResponse {
+    pub jsonrpc: String,
+    pub result: GetShredResult,
+    pub id: i64,
+}
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetShredResult {
+    pub leader: String,
+    pub shreds: Vec<Option<RpcShred>>, // This has to be an option
+}
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcShred {
+    #[serde(rename = "ShredData")]
+    pub shred_data: Option<ShredData>,
+    #[serde(rename = "ShredCode")]
+    pub shred_code: Option<ShredCode>,
+}
+
+/// A valid, finalized verdict for `slot`, adjust it with struct update syntax
+#[cfg(test)]
+pub(crate) fn test_verdict(slot: Slot) -> SlotVerdict {
+    SlotVerdict {
+        slot,
+        requested_data_indices: vec![0],
+        requested_coding_indices: vec![],
+        received_data_indices: vec![0],
+        received_coding_indices: vec![],
+        valid: 1,
+        invalid: 0,
+        rejected: vec![],
+        leader: String::new(),
+        claimed_leader: None,
+        confidence: 1.0,
+        commitment: Some(CommitmentLevel::Finalized),
+        timestamp: timestamp(),
+        verdict: Verdict::Valid,
+    }
+}
+
+/// Opens an empty archive with every column family under the temp dir, the
+/// caller destroys it through the returned path
+#[cfg(test)]
+pub(crate) fn open_temp_archive(name: &str) -> (DB, std::path::PathBuf) {
+    let path = std::env::temp_dir().join(format!("tinydancer-{}-{}", name, timestamp()));
+    let mut opts = RocksOptions::default();
+    opts.create_if_missing(true);
+    opts.create_missing_column_families(true);
+    let instance = DB::open_cf(&opts, &path, archive::ARCHIVE_CFS).unwrap();
+    (instance, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_serialized, SHRED_CF};
+    use rocksdb::{Options as RocksOptions, DB};
+    use solana_client::nonblocking::rpc_client::RpcClient;
+    use solana_ledger::shred::{hashv, Shred, ShredType, Signer};
+    use solana_sdk::signer::keypair::Keypair;
+    use tiny_logger::logs::info;
+
+    #[test]
+    fn get_shred_from_db() {
+        let mut opts = RocksOptions::default();
+        opts.create_if_missing(true);
+        opts.set_error_if_exists(false);
+        opts.create_missing_column_families(true);
+        let instance = DB::open_cf(&opts, "/tmp", vec![SHRED_CF]).unwrap();
+        let slot: u64 = 1963754;
+        let _index: u32 = 11;
+        let key = hashv(&[
+            &slot.to_le_bytes(),
+            &u8::from(ShredType::Data).to_le_bytes(),
+            &0_u32.to_le_bytes(), // can be random
+        ])
+        .to_bytes();
+        let cf = instance.cf_handle(SHRED_CF).unwrap();
+        let shred = get_serialized::<Shred>(&instance, cf, key);
+        println!("shred {:?}", shred);
+        assert!(
+            shred.is_ok(),
+            "error retrieving and serializing shred from db"
+        );
+    }
+
+    #[tokio::test]
+    async fn call_lite_rpc() {
+        let rpc_client = RpcClient::new("http://0.0.0.0:8890".to_string());
+
+        //let identity = get_identity_keypair(&identity_keypair).await;
+        // let blockhash = rpc_client.get_latest_blockhash().await.unwrap();
+        let payer = Keypair::new();
+        let airdrop_sign = rpc_client
+            .request_airdrop(&payer.try_pubkey().unwrap(), 2000000000)
+            .await
+            .unwrap();
+        println!("AIRDROP CONFIRMED:{}", airdrop_sign);
+    }
+
+    #[test]
+    fn sampling_only_settles_startup_states() {
+        use super::{mark_sampling, ClientStatus};
+        use std::sync::Mutex;
+
+        let status = Mutex::new(ClientStatus::SearchingForRPCService(String::new()));
+        mark_sampling(&status);
+        assert!(matches!(&*status.lock().unwrap(), ClientStatus::Active(_)));
+
+        let status = Mutex::new(ClientStatus::Degraded(String::from("ui restarting")));
+        mark_sampling(&status);
+        assert!(matches!(
+            &*status.lock().unwrap(),
+            ClientStatus::Degraded(_)
+        ));
+    }
+
+    #[test]
+    fn sample_verdicts_need_clean_consistent_shreds() {
+        use super::Verdict;
+
+        assert_eq!(Verdict::of_sample(10, 0, 0, true), Verdict::Valid);
+        assert_eq!(Verdict::of_sample(10, 1, 0, true), Verdict::Invalid);
+        assert_eq!(Verdict::of_sample(10, 0, 1, true), Verdict::Invalid);
+        assert_eq!(Verdict::of_sample(10, 0, 0, false), Verdict::Invalid);
+        assert_eq!(Verdict::of_sample(0, 0, 0, true), Verdict::Invalid);
+    }
+
+    #[test]
+    fn bad_signatures_mean_a_wrong_leader() {
+        use super::{verdicts::RejectedShred, wrong_leader, SampleVerdict, SlotSample};
+        use solana_sdk::{commitment_config::CommitmentLevel, pubkey::Pubkey};
+
+        let leader = Pubkey::new_unique();
+        let sample = |claimed_leader| SlotSample {
+            slot: 5,
+            indices: vec![1],
+            coding_indices: vec![],
+            population: 64,
+            shreds: vec![],
+            leader,
+            claimed_leader,
+            consistent: true,
+            commitment: CommitmentLevel::Confirmed,
+        };
+        let rejected = |verdict| {
+            vec![RejectedShred {
+                shred_type: ShredType::Data,
+                index: 1,
+                verdict,
+            }]
+        };
+
+        // the source reports the scheduled leader, or none at all
+        assert!(wrong_leader(
+            &sample(Some(leader)),
+            &rejected(SampleVerdict::BadSignature)
+        ));
+        assert!(wrong_leader(
+            &sample(None),
+            &rejected(SampleVerdict::BadSignature)
+        ));
+        assert!(!wrong_leader(
+            &sample(None),
+            &rejected(SampleVerdict::BadMerkleProof)
+        ));
+        assert!(wrong_leader(&sample(Some(Pubkey::new_unique())), &[]));
+    }
+
+    #[test]
+    fn only_requested_shreds_count() {
+        use super::SlotSample;
+        use solana_ledger::shred::ShredFlags;
+        use solana_sdk::{commitment_config::CommitmentLevel, pubkey::Pubkey};
+
+        let shred = |index| {
+            Some(Shred::new_from_data(
+                5,
+                index,
+                1,
+                &[],
+                ShredFlags::empty(),
+                0,
+                0,
+                0,
+            ))
+        };
+        let mut sample = SlotSample {
+            slot: 5,
+            indices: vec![1, 2, 3],
+            coding_indices: vec![],
+            population: 64,
+            // 2 is withheld, 7 and 8 weren't asked for
+            shreds: vec![shred(1), None, shred(3), shred(7), shred(8)],
+            leader: Pubkey::new_unique(),
+            claimed_leader: None,
+            consistent: true,
+            commitment: CommitmentLevel::Confirmed,
+        };
+        sample.retain_requested();
+        assert_eq!(sample.received(), 2);
+        assert_eq!(sample.missing(), 1);
+    }
+
+    #[tokio::test]
+    async fn coding_requests_only_take_coding_shreds() {
+        use super::{request_shreds_of_type, rpc_client::JsonRpcClient, GetShredResult, RpcShred};
+        use jsonrpsee::{server::ServerBuilder, RpcModule};
+        use solana_ledger::shred::ShredFlags;
+        use solana_sdk::commitment_config::CommitmentLevel;
+
+        // honours "shredType" only if `supports_shred_type`
+        let rpc = |supports_shred_type: bool| async move {
+            let mut module = RpcModule::new(());
+            module
+                .register_method("getShreds", move |params, _| {
+                    let (slot, indices, config): (u64, Vec<u32>, serde_json::Value) =
+                        params.parse()?;
+                    let code = supports_shred_type && config["shredType"] == "code";
+                    let shreds = indices
+                        .into_iter()
+                        .map(|index| match code {
+                            true => match Shred::new_from_parity_shard(
+                                slot,
+                                index,
+                                &[],
+                                0,
+                                32,
+                                32,
+                                index as u16,
+                                0,
+                            ) {
+                                Shred::ShredCode(shred) => Some(RpcShred {
+                                    shred_data: None,
+                                    shred_code: Some(shred),
+                                }),
+                                Shred::ShredData(_) => None,
+                            },
+                            false => match Shred::new_from_data(
+                                slot,
+                                index,
+                                1,
+                                &[],
+                                ShredFlags::empty(),
+                                0,
+                                0,
+                                0,
+                            ) {
+                                Shred::ShredData(shred) => Some(RpcShred {
+                                    shred_data: Some(shred),
+                                    shred_code: None,
+                                }),
+                                Shred::ShredCode(_) => None,
+                            },
+                        })
+                        .collect();
+                    Ok(GetShredResult {
+                        leader: String::new(),
+                        shreds,
+                    })
+                })
+                .unwrap();
+            let server = ServerBuilder::default().build("127.0.0.1:0").await.unwrap();
+            let endpoint = format!("http://{}", server.local_addr().unwrap());
+            (JsonRpcClient::new(endpoint), server.start(module).unwrap())
+        };
+        let request = |client: JsonRpcClient, shred_type| async move {
+            request_shreds_of_type(
+                5,
+                vec![0, 1],
+                shred_type,
+                CommitmentLevel::Confirmed,
+                &client,
+            )
+            .await
+        };
+
+        let (client, _server) = rpc(true).await;
+        let served = request(client.clone(), ShredType::Code).await.unwrap();
+        assert_eq!(served.shreds.iter().flatten().count(), 2);
+        assert!(request(client, ShredType::Data).await.is_ok());
+
+        let (client, _server) = rpc(false).await;
+        assert!(request(client, ShredType::Code).await.is_err());
+    }
+
+    #[test]
+    fn duplicate_shreds_count_once() {
+        use super::dedup_shreds;
+        use solana_ledger::shred::ShredFlags;
+
+        let shred = |index| {
+            Some(Shred::new_from_data(
+                5,
+                index,
+                1,
+                &[],
+                ShredFlags::empty(),
+                0,
+                0,
+                0,
+            ))
+        };
+        // the same shred from two providers, not next to each other
+        let mut shreds = vec![shred(1), shred(2), None, shred(1), None];
+        dedup_shreds(&mut shreds);
+        assert_eq!(shreds.iter().flatten().count(), 2);
+        assert_eq!(shreds.len(), 4);
+    }
+}
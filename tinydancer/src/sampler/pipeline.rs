@@ -0,0 +1,385 @@
+//! Sampler pipeline - bounded queues between the sampler stages
+//!
+//! slot_update_loop -> SlotQueue -> shred_update_loop -> samples -> shred_verify_loop -> verified -> shred_archiver
+//!
+//! The slot queue applies the overflow policy when sampling falls behind the
+//! cluster, the channels after it are plain bounded tokio channels that push
+//! back on the stage before them. A slot holds an in-flight permit from the
+//! moment it is taken off the slot queue until its verdict is recorded.
+//!
+//! `shred_update_loop` runs `sampling_workers` workers off the slot queue,
+//! slots whose shreds aren't served yet go back on the queue after a delay.
+use crate::sampler::{slot_updates::SlotUpdate, SlotSample};
+use prometheus::{
+    core::GenericGauge, exponential_buckets, histogram_opts, opts, register_histogram,
+    register_int_counter, register_int_gauge, Histogram, IntCounter,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use tiny_logger::logs::warn;
+use tokio::{
+    sync::{Notify, OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+lazy_static::lazy_static! {
+    pub static ref SLOT_QUEUE_DEPTH: GenericGauge<prometheus::core::AtomicI64> =
+    register_int_gauge!(opts!("tinydancer_slot_queue_depth", "Slots waiting to be sampled")).unwrap();
+    pub static ref SAMPLE_QUEUE_DEPTH: GenericGauge<prometheus::core::AtomicI64> =
+    register_int_gauge!(opts!("tinydancer_sample_queue_depth", "Sampled slots waiting to be verified")).unwrap();
+    pub static ref ARCHIVE_QUEUE_DEPTH: GenericGauge<prometheus::core::AtomicI64> =
+    register_int_gauge!(opts!("tinydancer_archive_queue_depth", "Verified shreds waiting to be archived")).unwrap();
+    pub static ref SLOTS_IN_FLIGHT: GenericGauge<prometheus::core::AtomicI64> =
+    register_int_gauge!(opts!("tinydancer_slots_in_flight", "Slots taken off the slot queue whose verdict isn't recorded yet")).unwrap();
+    pub static ref SLOTS_IN_RETRY_QUEUE: GenericGauge<prometheus::core::AtomicI64> =
+    register_int_gauge!(opts!("tinydancer_slots_in_retry_queue", "Slots waiting to be sampled again")).unwrap();
+    static ref SLOTS_DROPPED: IntCounter =
+    register_int_counter!(opts!("tinydancer_slots_dropped", "Slots dropped from the slot queue by the overflow policy")).unwrap();
+    static ref SLOT_LATENCY: Histogram = register_histogram!(histogram_opts!(
+        "tinydancer_slot_latency",
+        "Time from a slot notification to its recorded verdict",
+        exponential_buckets(0.1, 2.0, 10).unwrap()
+    ))
+    .unwrap();
+}
+
+/// What the slot queue does with a new slot once it is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum OverflowPolicy {
+    /// wait for room, pushing back on the slot subscription
+    Block,
+    /// drop the oldest queued slot, sampling keeps up with the tip
+    DropOldest,
+    /// drop the new slot, sampling keeps its order and loses the tip
+    DropNewest,
+    /// drop everything queued, the sampler jumps straight to the new slot
+    Coalesce,
+}
+
+#[derive(Clone, Debug)]
+pub struct PipelineConfig {
+    pub slot_queue_capacity: usize,
+    /// sampled slots waiting for verification
+    pub sample_queue_capacity: usize,
+    /// verified shreds waiting to be archived
+    pub archive_queue_capacity: usize,
+    /// slots between the slot queue and their recorded verdict
+    pub max_slots_in_flight: usize,
+    pub overflow_policy: OverflowPolicy,
+    /// slots sampled concurrently
+    pub sampling_workers: usize,
+    /// attempts at sampling a slot before giving up on it, its shreds may not be served yet
+    pub max_sample_attempts: u8,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            slot_queue_capacity: 256,
+            sample_queue_capacity: 32,
+            archive_queue_capacity: 4096,
+            max_slots_in_flight: 32,
+            overflow_policy: OverflowPolicy::DropOldest,
+            sampling_workers: 6,
+            max_sample_attempts: 5,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("slot queue closed")]
+pub struct SlotQueueClosed;
+
+/// A slot waiting in the slot queue
+#[derive(Clone, Copy, Debug)]
+pub struct QueuedSlot {
+    pub update: SlotUpdate,
+    /// failed sampling attempts so far
+    pub attempt: u8,
+    pub notified_at: Instant,
+}
+
+impl QueuedSlot {
+    pub fn new(update: SlotUpdate) -> Self {
+        Self {
+            update,
+            attempt: 0,
+            notified_at: Instant::now(),
+        }
+    }
+}
+
+/// Bounded queue of slots waiting to be sampled
+pub struct SlotQueue {
+    slots: Mutex<VecDeque<QueuedSlot>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    closed: AtomicBool,
+    /// notified when a slot is pushed or the queue is closed
+    pushed: Notify,
+    /// notified when a slot is popped
+    popped: Notify,
+}
+
+impl SlotQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            slots: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            policy,
+            closed: AtomicBool::new(false),
+            pushed: Notify::new(),
+            popped: Notify::new(),
+        }
+    }
+
+    pub async fn push(&self, queued: QueuedSlot) -> Result<(), SlotQueueClosed> {
+        loop {
+            if self.closed.load(Ordering::Relaxed) {
+                return Err(SlotQueueClosed);
+            }
+            let popped = self.popped.notified();
+            {
+                let mut slots = self.slots.lock().unwrap();
+                if slots.len() < self.capacity {
+                    slots.push_back(queued);
+                    SLOT_QUEUE_DEPTH.set(slots.len() as i64);
+                    self.pushed.notify_one();
+                    return Ok(());
+                }
+
+                let dropped = match self.policy {
+                    OverflowPolicy::Block => 0,
+                    OverflowPolicy::DropOldest => {
+                        slots.pop_front();
+                        slots.push_back(queued);
+                        1
+                    }
+                    OverflowPolicy::DropNewest => 1,
+                    OverflowPolicy::Coalesce => {
+                        let dropped = slots.len();
+                        slots.clear();
+                        slots.push_back(queued);
+                        dropped
+                    }
+                };
+                if dropped > 0 {
+                    SLOTS_DROPPED.inc_by(dropped as u64);
+                    SLOT_QUEUE_DEPTH.set(slots.len() as i64);
+                    warn!(
+                        "sampler falling behind, {:?} dropped {} slot(s) at slot {}",
+                        self.policy, dropped, queued.update.slot
+                    );
+                    self.pushed.notify_one();
+                    return Ok(());
+                }
+            }
+            popped.await;
+        }
+    }
+
+    /// Next slot to sample, `None` once the queue is closed
+    pub async fn pop(&self) -> Option<QueuedSlot> {
+        loop {
+            let pushed = self.pushed.notified();
+            {
+                let mut slots = self.slots.lock().unwrap();
+                if let Some(queued) = slots.pop_front() {
+                    SLOT_QUEUE_DEPTH.set(slots.len() as i64);
+                    self.popped.notify_one();
+                    return Some(queued);
+                }
+            }
+            if self.closed.load(Ordering::Relaxed) {
+                return None;
+            }
+            pushed.await;
+        }
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.pushed.notify_waiters();
+        self.popped.notify_waiters();
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.lock().unwrap().is_empty()
+    }
+}
+
+/// Held by a slot from the slot queue until its verdict is recorded
+pub struct InFlightPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl InFlightPermit {
+    pub async fn acquire(in_flight: &Arc<Semaphore>) -> Self {
+        let permit = in_flight
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("in flight semaphore closed");
+        SLOTS_IN_FLIGHT.inc();
+        Self { _permit: permit }
+    }
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        SLOTS_IN_FLIGHT.dec();
+    }
+}
+
+/// A sampled slot on its way to verification
+pub struct InFlightSample {
+    pub sample: SlotSample,
+    notified_at: Instant,
+    _permit: InFlightPermit,
+}
+
+impl InFlightSample {
+    pub fn new(sample: SlotSample, queued: &QueuedSlot, permit: InFlightPermit) -> Self {
+        Self {
+            sample,
+            notified_at: queued.notified_at,
+            _permit: permit,
+        }
+    }
+
+    /// Records the slot's latency and frees its in-flight permit, once its verdict is stored
+    pub fn finish(self) {
+        SLOT_LATENCY.observe(self.notified_at.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::commitment_config::CommitmentLevel;
+
+    fn update(slot: u64) -> QueuedSlot {
+        QueuedSlot::new(SlotUpdate {
+            slot,
+            commitment: CommitmentLevel::Finalized,
+        })
+    }
+
+    async fn drain(queue: &SlotQueue) -> Vec<u64> {
+        let mut slots = vec![];
+        while !queue.is_empty() {
+            slots.push(queue.pop().await.unwrap().update.slot);
+        }
+        slots
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_the_tip() {
+        let queue = SlotQueue::new(2, OverflowPolicy::DropOldest);
+        for slot in 1..=4 {
+            queue.push(update(slot)).await.unwrap();
+        }
+        assert_eq!(drain(&queue).await, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_the_order() {
+        let queue = SlotQueue::new(2, OverflowPolicy::DropNewest);
+        for slot in 1..=4 {
+            queue.push(update(slot)).await.unwrap();
+        }
+        assert_eq!(drain(&queue).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn coalesce_jumps_to_the_new_slot() {
+        let queue = SlotQueue::new(2, OverflowPolicy::Coalesce);
+        for slot in 1..=3 {
+            queue.push(update(slot)).await.unwrap();
+        }
+        assert_eq!(drain(&queue).await, vec![3]);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room() {
+        let queue = Arc::new(SlotQueue::new(1, OverflowPolicy::Block));
+        queue.push(update(1)).await.unwrap();
+
+        let pusher = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.push(update(2)).await })
+        };
+        assert_eq!(queue.pop().await.unwrap().update.slot, 1);
+        pusher.await.unwrap().unwrap();
+        assert_eq!(queue.pop().await.unwrap().update.slot, 2);
+    }
+
+    #[tokio::test]
+    async fn closed_queue_drains_then_ends() {
+        let queue = SlotQueue::new(2, OverflowPolicy::Block);
+        queue.push(update(1)).await.unwrap();
+        queue.close();
+        assert!(queue.push(update(2)).await.is_err());
+        assert_eq!(queue.pop().await.unwrap().update.slot, 1);
+        assert!(queue.pop().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn blocked_push_ends_when_the_queue_closes() {
+        let queue = Arc::new(SlotQueue::new(1, OverflowPolicy::Block));
+        queue.push(update(1)).await.unwrap();
+
+        let pusher = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.push(update(2)).await })
+        };
+        tokio::task::yield_now().await;
+        queue.close();
+        assert!(pusher.await.unwrap().is_err());
+        assert_eq!(drain(&queue).await, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn in_flight_permits_bound_the_slots() {
+        let in_flight = Arc::new(Semaphore::new(1));
+        let permit = InFlightPermit::acquire(&in_flight).await;
+
+        let waiting = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            InFlightPermit::acquire(&in_flight),
+        )
+        .await;
+        assert!(waiting.is_err());
+
+        drop(permit);
+        tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            InFlightPermit::acquire(&in_flight),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn requeued_slots_keep_their_attempts() {
+        let queue = SlotQueue::new(2, OverflowPolicy::Block);
+        let mut retry = update(1);
+        retry.attempt = 3;
+        queue.push(retry).await.unwrap();
+
+        let queued = queue.pop().await.unwrap();
+        assert_eq!(queued.attempt, 3);
+        assert_eq!(queued.notified_at, retry.notified_at);
+    }
+}
@@ -0,0 +1,520 @@
+//! Multi-RPC sampling - spreads a slot's sample across several rpc providers
+//!
+//! Every sample index is requested from `overlap` providers, rotating with the
+//! slot so each provider sees a different part of each slot. Shreds served for
+//! the same index are compared byte for byte and the payload most providers
+//! agree on is kept. A provider is flagged when it
+//!
+//! * serves a shred the others disagree with (on a tie every party is flagged)
+//! * claims a different leader than the other providers
+//! * withholds a shred another provider served
+//!
+//! and every flag counts against its reputation.
+use crate::sampler::shred_source::{RpcShredSource, ShredSource, SourceShreds};
+use async_trait::async_trait;
+use prometheus::{opts, register_gauge_vec, GaugeVec};
+use serde::{Deserialize, Serialize};
+use solana_ledger::shred::{Shred, ShredType};
+use solana_sdk::{clock::Slot, commitment_config::CommitmentLevel, pubkey::Pubkey};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tiny_logger::logs::{error, warn};
+
+/// Providers each sample index is requested from by default
+pub const DEFAULT_PROVIDER_OVERLAP: usize = 2;
+
+/// Weight of a mismatched shred or leader against a shred served correctly
+const MISBEHAVIOUR_WEIGHT: u64 = 10;
+
+lazy_static::lazy_static! {
+    static ref PROVIDER_REPUTATION: GaugeVec =
+    register_gauge_vec!(opts!("tinydancer_provider_reputation", "Reputation score of each rpc provider shreds are sampled from"), &["provider"]).unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Fault {
+    /// served a shred whose payload the other providers disagree with
+    ShredMismatch { shred_type: ShredType, index: u32 },
+    /// didn't serve a shred another provider served
+    Withheld { shred_type: ShredType, index: u32 },
+    /// claimed a different slot leader than the other providers
+    LeaderMismatch { leader: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderFault {
+    pub provider: String,
+    pub slot: Slot,
+    pub fault: Fault,
+}
+
+impl ProviderFault {
+    /// Providers disagreeing on what the slot contains, as opposed to one of
+    /// them just not serving a shred
+    pub fn is_inconsistency(&self) -> bool {
+        !matches!(self.fault, Fault::Withheld { .. })
+    }
+}
+
+impl fmt::Display for ProviderFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.fault {
+            Fault::ShredMismatch { shred_type, index } => write!(
+                f,
+                "{} served a mismatched {:?} shred {} for slot {}",
+                self.provider, shred_type, index, self.slot
+            ),
+            Fault::Withheld { shred_type, index } => write!(
+                f,
+                "{} withheld {:?} shred {} of slot {}",
+                self.provider, shred_type, index, self.slot
+            ),
+            Fault::LeaderMismatch { leader } => write!(
+                f,
+                "{} claimed leader {} for slot {}",
+                self.provider, leader, self.slot
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderReputation {
+    pub provider: String,
+    /// shreds served that agreed with the other providers
+    pub served: u64,
+    pub withheld: u64,
+    pub mismatched: u64,
+    pub wrong_leader: u64,
+    /// requests that errored out
+    pub failed_requests: u64,
+    pub score: f64,
+}
+
+impl ProviderReputation {
+    /// Share of the provider's weighted record that was good, 1.0 until it has one
+    pub fn score(&self) -> f64 {
+        let penalty = self.withheld
+            + self.failed_requests
+            + MISBEHAVIOUR_WEIGHT * (self.mismatched + self.wrong_leader);
+        match self.served + penalty {
+            0 => 1.0,
+            total => self.served as f64 / total as f64,
+        }
+    }
+}
+
+/// Running reputation of every provider, shared with the rpc service so it can be queried
+#[derive(Debug, Default)]
+pub struct ProviderReputations {
+    providers: Mutex<HashMap<String, ProviderReputation>>,
+}
+
+impl ProviderReputations {
+    fn record(&self, provider: &str, update: impl FnOnce(&mut ProviderReputation)) {
+        let mut providers = self.providers.lock().unwrap();
+        let reputation =
+            providers
+                .entry(provider.to_string())
+                .or_insert_with(|| ProviderReputation {
+                    provider: provider.to_string(),
+                    ..ProviderReputation::default()
+                });
+        update(reputation);
+        reputation.score = reputation.score();
+        PROVIDER_REPUTATION
+            .with_label_values(&[provider])
+            .set(reputation.score);
+    }
+
+    fn record_fault(&self, fault: &ProviderFault) {
+        self.record(&fault.provider, |reputation| match fault.fault {
+            Fault::ShredMismatch { .. } => reputation.mismatched += 1,
+            Fault::Withheld { .. } => reputation.withheld += 1,
+            Fault::LeaderMismatch { .. } => reputation.wrong_leader += 1,
+        });
+    }
+
+    /// Reputation of every provider seen so far, ordered by provider
+    pub fn snapshot(&self) -> Vec<ProviderReputation> {
+        let mut reputations: Vec<ProviderReputation> =
+            self.providers.lock().unwrap().values().cloned().collect();
+        reputations.sort_by(|a, b| a.provider.cmp(&b.provider));
+        reputations
+    }
+}
+
+/// Positions into `indices` each provider is asked for, every index goes to
+/// `overlap` consecutive providers starting at an offset that rotates with `rotation`
+pub fn assign_indices(
+    num_indices: usize,
+    num_providers: usize,
+    overlap: usize,
+    rotation: u64,
+) -> Vec<Vec<usize>> {
+    let mut assignments = vec![Vec::new(); num_providers];
+    if num_providers == 0 {
+        return assignments;
+    }
+    let overlap = overlap.clamp(1, num_providers);
+    for position in 0..num_indices {
+        let first = (position as u64 + rotation) as usize % num_providers;
+        for k in 0..overlap {
+            assignments[(first + k) % num_providers].push(position);
+        }
+    }
+    assignments
+}
+
+/// Samples a slot from several rpc providers at once, see the module docs
+pub struct MultiRpcShredSource {
+    providers: Vec<RpcShredSource>,
+    overlap: usize,
+    reputations: Arc<ProviderReputations>,
+}
+
+impl MultiRpcShredSource {
+    pub fn new(
+        endpoints: Vec<String>,
+        overlap: usize,
+        reputations: Arc<ProviderReputations>,
+    ) -> Self {
+        Self {
+            providers: endpoints.into_iter().map(RpcShredSource::new).collect(),
+            overlap,
+            reputations,
+        }
+    }
+
+    async fn fetch(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        shred_type: ShredType,
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        if indices.is_empty() {
+            return Ok(SourceShreds::default());
+        }
+        let assignments = assign_indices(indices.len(), self.providers.len(), self.overlap, slot);
+        let requests = self
+            .providers
+            .iter()
+            .zip(assignments.iter())
+            .filter(|(_, positions)| !positions.is_empty())
+            .map(|(provider, positions)| async move {
+                let provider_indices: Vec<usize> = positions.iter().map(|p| indices[*p]).collect();
+                let response = match shred_type {
+                    ShredType::Data => {
+                        provider
+                            .get_shreds(slot, &provider_indices, commitment)
+                            .await
+                    }
+                    ShredType::Code => {
+                        provider
+                            .get_coding_shreds(slot, &provider_indices, commitment)
+                            .await
+                    }
+                };
+                (provider.endpoint().to_string(), positions, response)
+            });
+        let responses = futures::future::join_all(requests).await;
+
+        let mut answered = Vec::new();
+        let mut last_error = None;
+        for (provider, positions, response) in responses {
+            match response {
+                Ok(shreds) => answered.push((provider, positions, shreds)),
+                Err(e) => {
+                    warn!("provider {} failed to serve slot {}: {}", provider, slot, e);
+                    self.reputations
+                        .record(&provider, |reputation| reputation.failed_requests += 1);
+                    last_error = Some(e);
+                }
+            }
+        }
+        if let (true, Some(e)) = (answered.is_empty(), last_error) {
+            return Err(e);
+        }
+
+        let mut faults = Vec::new();
+        let mut shreds = Vec::with_capacity(indices.len());
+        for (position, index) in indices.iter().enumerate() {
+            let index = *index as u32;
+            let mut served: Vec<(&str, &Shred)> = Vec::new();
+            let mut not_served: Vec<&str> = Vec::new();
+            for (provider, positions, response) in &answered {
+                if !positions.contains(&position) {
+                    continue;
+                }
+                let shred = response
+                    .shreds
+                    .iter()
+                    .flatten()
+                    .find(|s| s.index() == index && s.shred_type() == shred_type);
+                match shred {
+                    Some(shred) => served.push((provider.as_str(), shred)),
+                    None => not_served.push(provider.as_str()),
+                }
+            }
+
+            let Some(agreed) = agreed_payload(&served) else {
+                shreds.push(None);
+                continue;
+            };
+            let no_majority = served
+                .iter()
+                .filter(|(_, s)| s.payload() == agreed.payload())
+                .count()
+                * 2
+                <= served.len();
+            for (provider, shred) in &served {
+                if no_majority || shred.payload() != agreed.payload() {
+                    faults.push(ProviderFault {
+                        provider: provider.to_string(),
+                        slot,
+                        fault: Fault::ShredMismatch { shred_type, index },
+                    });
+                } else {
+                    self.reputations
+                        .record(provider, |reputation| reputation.served += 1);
+                }
+            }
+            for provider in not_served {
+                faults.push(ProviderFault {
+                    provider: provider.to_string(),
+                    slot,
+                    fault: Fault::Withheld { shred_type, index },
+                });
+            }
+            shreds.push(Some(agreed.clone()));
+        }
+
+        // the leader most providers claim, leaders are only compared, never trusted
+        let claimed: Vec<(&str, Pubkey)> = answered
+            .iter()
+            .filter_map(|(provider, _, response)| {
+                response.leader.map(|leader| (provider.as_str(), leader))
+            })
+            .collect();
+        let (leader, mismatched) = claimed_leader(&claimed);
+        for (provider, claimed_leader) in mismatched {
+            faults.push(ProviderFault {
+                provider: provider.to_string(),
+                slot,
+                fault: Fault::LeaderMismatch {
+                    leader: claimed_leader.to_string(),
+                },
+            });
+        }
+
+        for fault in &faults {
+            match fault.is_inconsistency() {
+                true => error!("PROVIDER FAULT {}", fault),
+                false => warn!("provider fault {}", fault),
+            }
+            self.reputations.record_fault(fault);
+        }
+
+        Ok(SourceShreds {
+            shreds,
+            leader,
+            faults,
+        })
+    }
+}
+
+/// The leader most providers claim along with the claims that disagree with it,
+/// without a strict majority every claim is flagged since there's no telling
+/// which side is lying
+fn claimed_leader<'a>(claimed: &[(&'a str, Pubkey)]) -> (Option<Pubkey>, Vec<(&'a str, Pubkey)>) {
+    let claims = |leader: &Pubkey| claimed.iter().filter(|(_, l)| l == leader).count();
+    let Some(leader) = claimed
+        .iter()
+        .map(|(_, leader)| *leader)
+        .max_by_key(|leader| claims(leader))
+    else {
+        return (None, vec![]);
+    };
+    let no_majority = claims(&leader) * 2 <= claimed.len();
+    let mismatched = claimed
+        .iter()
+        .filter(|(_, claimed_leader)| no_majority || *claimed_leader != leader)
+        .copied()
+        .collect();
+    (Some(leader), mismatched)
+}
+
+/// The shred most providers served, ties go to the first one served
+fn agreed_payload<'a>(served: &[(&str, &'a Shred)]) -> Option<&'a Shred> {
+    served
+        .iter()
+        .rev()
+        .max_by_key(|(_, shred)| {
+            served
+                .iter()
+                .filter(|(_, s)| s.payload() == shred.payload())
+                .count()
+        })
+        .map(|(_, shred)| *shred)
+}
+
+#[async_trait]
+impl ShredSource for MultiRpcShredSource {
+    fn name(&self) -> String {
+        format!(
+            "rpc({})",
+            self.providers
+                .iter()
+                .map(|p| p.endpoint())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )
+    }
+
+    async fn get_shreds(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        self.fetch(slot, indices, ShredType::Data, commitment).await
+    }
+
+    async fn get_coding_shreds(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        self.fetch(slot, indices, ShredType::Code, commitment).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_index_goes_to_overlap_providers() {
+        let assignments = assign_indices(10, 3, 2, 7);
+        for position in 0..10 {
+            let providers = assignments
+                .iter()
+                .filter(|positions| positions.contains(&position))
+                .count();
+            assert_eq!(providers, 2);
+        }
+    }
+
+    #[test]
+    fn overlap_is_clamped_to_the_providers() {
+        let assignments = assign_indices(4, 2, 5, 0);
+        assert_eq!(assignments, vec![vec![0, 1, 2, 3], vec![0, 1, 2, 3]]);
+
+        let assignments = assign_indices(4, 1, 0, 3);
+        assert_eq!(assignments, vec![vec![0, 1, 2, 3]]);
+    }
+
+    #[test]
+    fn rotation_spreads_indices_across_providers() {
+        let assignments = assign_indices(3, 3, 1, 1);
+        assert_eq!(assignments, vec![vec![2], vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn misbehaviour_weighs_more_than_withholding() {
+        let withheld = ProviderReputation {
+            served: 10,
+            withheld: 1,
+            ..ProviderReputation::default()
+        };
+        let mismatched = ProviderReputation {
+            served: 10,
+            mismatched: 1,
+            ..ProviderReputation::default()
+        };
+        assert_eq!(ProviderReputation::default().score(), 1.0);
+        assert!(mismatched.score() < withheld.score());
+    }
+
+    #[test]
+    fn majority_payload_wins_and_ties_go_to_the_first() {
+        use solana_ledger::shred::ShredFlags;
+        let shred = |data: &[u8]| Shred::new_from_data(1, 0, 1, data, ShredFlags::empty(), 0, 0, 0);
+        let (honest, forged) = (shred(&[1]), shred(&[2]));
+
+        let agreed = agreed_payload(&[("a", &forged), ("b", &honest), ("c", &honest)]);
+        assert_eq!(agreed.map(Shred::payload), Some(honest.payload()));
+
+        let agreed = agreed_payload(&[("a", &forged), ("b", &honest)]);
+        assert_eq!(agreed.map(Shred::payload), Some(forged.payload()));
+
+        assert!(agreed_payload(&[]).is_none());
+    }
+
+    #[test]
+    fn a_tied_leader_flags_every_claim() {
+        let (honest, forged) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let (leader, mismatched) = claimed_leader(&[("a", forged), ("b", honest), ("c", honest)]);
+        assert_eq!(leader, Some(honest));
+        assert_eq!(mismatched, vec![("a", forged)]);
+
+        let (_, mismatched) = claimed_leader(&[("a", forged), ("b", honest)]);
+        assert_eq!(mismatched, vec![("a", forged), ("b", honest)]);
+
+        let (leader, mismatched) = claimed_leader(&[("a", honest)]);
+        assert_eq!((leader, mismatched.len()), (Some(honest), 0));
+        assert_eq!(claimed_leader(&[]), (None, vec![]));
+    }
+
+    #[test]
+    fn faults_count_against_the_provider() {
+        let reputations = ProviderReputations::default();
+        let fault = |provider: &str, fault| ProviderFault {
+            provider: provider.to_string(),
+            slot: 1,
+            fault,
+        };
+        let faults = [
+            fault(
+                "b",
+                Fault::ShredMismatch {
+                    shred_type: ShredType::Data,
+                    index: 0,
+                },
+            ),
+            fault(
+                "b",
+                Fault::LeaderMismatch {
+                    leader: Pubkey::new_unique().to_string(),
+                },
+            ),
+            fault(
+                "a",
+                Fault::Withheld {
+                    shred_type: ShredType::Code,
+                    index: 0,
+                },
+            ),
+        ];
+        for fault in &faults {
+            reputations.record_fault(fault);
+        }
+        reputations.record("a", |reputation| reputation.served += 1);
+
+        let [a, b] = <[ProviderReputation; 2]>::try_from(reputations.snapshot()).unwrap();
+        assert_eq!((a.provider.as_str(), a.served, a.withheld), ("a", 1, 1));
+        assert_eq!((b.mismatched, b.wrong_leader), (1, 1));
+        assert_eq!(a.score, 0.5);
+        assert_eq!(b.score, 0.0);
+        assert!(faults[0].is_inconsistency() && !faults[2].is_inconsistency());
+    }
+}
@@ -0,0 +1,378 @@
+//! Deep verify - checks the sampled slot can actually be reconstructed
+//!
+//! For every FEC set touched by the sample enough data + coding shreds are
+//! pulled to run Reed-Solomon recovery through the shredder, favouring coding
+//! shreds so some data shreds have to be recovered. The recovered shreds have
+//! to verify against the scheduled leader and carry the same signature (the
+//! signed merkle root) as the rest of the erasure batch.
+//!
+//! The coding shreds of a set are located from one coding shred of the set, its
+//! index less its position in the erasure batch is where they start. The
+//! sample's own coding shred of the set is used if it has one, otherwise the
+//! coding shred at `fec_set_index` is probed, which is where the 32:32 merkle
+//! erasure batches the cluster produces put it. A set whose coding shreds can't
+//! be located is reported unchecked rather than failed.
+use crate::sampler::{shred_source::ShredSource, verify_sample, SlotSample};
+use anyhow::anyhow;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use solana_ledger::shred::{ReedSolomonCache, Shred, Shredder};
+use solana_sdk::{clock::Slot, commitment_config::CommitmentLevel, pubkey::Pubkey};
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FecSetRecovery {
+    pub fec_set_index: u32,
+    pub num_data_shreds: usize,
+    pub num_coding_shreds: usize,
+    /// data shreds that were pulled instead of recovered
+    pub data_shreds_fetched: usize,
+    pub recovered: usize,
+    pub error: Option<String>,
+    /// why the set couldn't be checked, its coding shreds weren't located
+    #[serde(default)]
+    pub unchecked: Option<String>,
+}
+
+impl FecSetRecovery {
+    pub fn is_recovered(&self) -> bool {
+        self.error.is_none() && self.unchecked.is_none()
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryReport {
+    pub slot: Slot,
+    pub fec_sets: Vec<FecSetRecovery>,
+}
+
+impl RecoveryReport {
+    /// True unless recovery of a set failed, unchecked sets don't count against the slot
+    pub fn is_recoverable(&self) -> bool {
+        !self.fec_sets.is_empty() && self.fec_sets.iter().all(|set| set.error.is_none())
+    }
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "slot {} recovery:", self.slot)?;
+        for set in &self.fec_sets {
+            match (&set.error, &set.unchecked) {
+                (Some(e), _) => write!(f, " [fec set {} FAILED {}]", set.fec_set_index, e)?,
+                (None, Some(reason)) => {
+                    write!(f, " [fec set {} unchecked, {}]", set.fec_set_index, reason)?
+                }
+                (None, None) => write!(
+                    f,
+                    " [fec set {} recovered {}/{}]",
+                    set.fec_set_index, set.recovered, set.num_data_shreds
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs erasure recovery over every FEC set the sample touched
+pub async fn deep_verify_slot(sample: &SlotSample, source: &dyn ShredSource) -> RecoveryReport {
+    let reed_solomon_cache = ReedSolomonCache::default();
+    let fec_set_indices = sample
+        .shreds
+        .iter()
+        .flatten()
+        .map(|s| s.fec_set_index())
+        .unique()
+        .sorted();
+
+    let mut report = RecoveryReport {
+        slot: sample.slot,
+        ..RecoveryReport::default()
+    };
+    for fec_set_index in fec_set_indices {
+        let mut set = FecSetRecovery {
+            fec_set_index,
+            num_data_shreds: 0,
+            num_coding_shreds: 0,
+            data_shreds_fetched: 0,
+            recovered: 0,
+            error: None,
+            unchecked: None,
+        };
+        if let Err(e) = recover_fec_set(sample, source, &reed_solomon_cache, &mut set).await {
+            set.error = Some(e.to_string());
+        }
+        report.fec_sets.push(set);
+    }
+    report
+}
+
+/// Offset of a coding shred's position in its erasure batch into the payload, past
+/// the 83 byte common header and the coding header's data and coding shred counts
+const CODING_POSITION_OFFSET: usize = 83 + 2 + 2;
+
+/// Index of the first coding shred of the erasure batch `shred` is a coding shred of
+pub fn first_coding_index(shred: &Shred) -> Option<u32> {
+    if !shred.is_code() {
+        return None;
+    }
+    let position = shred
+        .payload()
+        .get(CODING_POSITION_OFFSET..CODING_POSITION_OFFSET + 2)?;
+    let position = u16::from_le_bytes(position.try_into().ok()?);
+    shred.index().checked_sub(u32::from(position))
+}
+
+/// A coding shred of the set, the sample's own if it holds one
+async fn coding_shred_of_set(
+    sample: &SlotSample,
+    fec_set_index: u32,
+    source: &dyn ShredSource,
+) -> anyhow::Result<Option<Shred>> {
+    let in_set = |shred: &Shred| {
+        shred.is_code() && shred.slot() == sample.slot && shred.fec_set_index() == fec_set_index
+    };
+    if let Some(shred) = sample.shreds.iter().flatten().find(|s| in_set(s)) {
+        return Ok(Some(shred.clone()));
+    }
+    Ok(source
+        .get_coding_shreds(sample.slot, &[fec_set_index as usize], sample.commitment)
+        .await?
+        .shreds
+        .into_iter()
+        .flatten()
+        .find(in_set))
+}
+
+async fn recover_fec_set(
+    sample: &SlotSample,
+    source: &dyn ShredSource,
+    reed_solomon_cache: &ReedSolomonCache,
+    set: &mut FecSetRecovery,
+) -> anyhow::Result<()> {
+    let (slot, leader, commitment) = (sample.slot, sample.leader, sample.commitment);
+    let fec_set_index = set.fec_set_index;
+    let in_set = |shred: &Shred| shred.slot() == slot && shred.fec_set_index() == fec_set_index;
+
+    // a coding shred of the set gives the batch's shape and where its coding shreds are
+    let located = coding_shred_of_set(sample, fec_set_index, source)
+        .await?
+        .and_then(|shred| first_coding_index(&shred).map(|index| (shred, index)));
+    let Some((probe, first_coding_index)) = located else {
+        set.unchecked = Some(String::from("no coding shred of the set found"));
+        return Ok(());
+    };
+    set.num_data_shreds = probe.num_data_shreds()? as usize;
+    set.num_coding_shreds = probe.num_coding_shreds()? as usize;
+
+    // as many coding shreds as possible, topped up with data shreds to reach num_data_shreds
+    let coding_count = set.num_coding_shreds.min(set.num_data_shreds);
+    let data_count = set.num_data_shreds - coding_count;
+    let coding_indices: Vec<usize> = (0..coding_count)
+        .map(|i| first_coding_index as usize + i)
+        .collect();
+    let data_indices: Vec<usize> = (0..data_count)
+        .map(|i| fec_set_index as usize + i)
+        .collect();
+
+    let (coding, data) = futures::try_join!(
+        source.get_coding_shreds(slot, &coding_indices, commitment),
+        source.get_shreds(slot, &data_indices, commitment)
+    )?;
+    let shreds: Vec<Shred> = coding
+        .shreds
+        .into_iter()
+        .chain(data.shreds)
+        .flatten()
+        .filter(in_set)
+        .unique_by(|s| (s.shred_type(), s.index()))
+        .collect();
+    if shreds.len() < set.num_data_shreds {
+        return Err(anyhow!(
+            "only {} of the {} shreds needed for recovery were served",
+            shreds.len(),
+            set.num_data_shreds
+        ));
+    }
+    set.data_shreds_fetched = shreds.iter().filter(|s| s.is_data()).count();
+
+    let signature = *probe.signature();
+    let recovered = Shredder::try_recovery(shreds, reed_solomon_cache)
+        .map_err(|e| anyhow!("reed-solomon recovery failed: {:?}", e))?;
+    set.recovered = recovered.len();
+
+    let expected = set.num_data_shreds - set.data_shreds_fetched;
+    if recovered.len() != expected {
+        return Err(anyhow!(
+            "recovered {} data shreds, expected {}",
+            recovered.len(),
+            expected
+        ));
+    }
+    if let Some(shred) = recovered
+        .iter()
+        .find(|s| *s.signature() != signature || !verify_sample(s, leader, slot).is_valid())
+    {
+        return Err(anyhow!(
+            "recovered data shred {} does not match the batch's merkle root",
+            shred.index()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::shred_source::SourceShreds;
+    use async_trait::async_trait;
+    use solana_ledger::{blockstore::make_slot_entries, shred::ProcessShredsStats};
+    use solana_sdk::signature::{Keypair, Signer};
+
+    const SLOT: Slot = 5;
+
+    /// Serves one slot's shreds by index
+    struct Batch {
+        data: Vec<Shred>,
+        coding: Vec<Shred>,
+    }
+
+    impl Batch {
+        fn signed_by(leader: &Keypair) -> Self {
+            Self::with_coding_from(leader, 0)
+        }
+
+        /// Batch whose coding shreds start at `next_code_index` instead of the data's index
+        fn with_coding_from(leader: &Keypair, next_code_index: u32) -> Self {
+            let (_, entries) = make_slot_entries(SLOT, SLOT - 1, 10, true);
+            let (data, coding) = Shredder::new(SLOT, SLOT - 1, 0, 0)
+                .unwrap()
+                .entries_to_shreds(
+                    leader,
+                    &entries,
+                    true,
+                    0,
+                    next_code_index,
+                    true,
+                    &ReedSolomonCache::default(),
+                    &mut ProcessShredsStats::default(),
+                );
+            Self { data, coding }
+        }
+
+        fn sample(&self, leader: Pubkey) -> SlotSample {
+            SlotSample {
+                slot: SLOT,
+                indices: vec![0],
+                coding_indices: vec![],
+                population: self.data.len() + self.coding.len(),
+                shreds: vec![self.data.first().cloned()],
+                leader,
+                claimed_leader: None,
+                consistent: true,
+                commitment: CommitmentLevel::Confirmed,
+            }
+        }
+
+        /// Sample that also holds the first coding shred
+        fn sample_with_coding(&self, leader: Pubkey) -> SlotSample {
+            let mut sample = self.sample(leader);
+            sample.coding_indices = vec![self.coding[0].index() as usize];
+            sample.shreds.push(self.coding.first().cloned());
+            sample
+        }
+    }
+
+    fn by_index(shreds: &[Shred], indices: &[usize]) -> SourceShreds {
+        SourceShreds {
+            shreds: indices
+                .iter()
+                .map(|index| {
+                    shreds
+                        .iter()
+                        .find(|s| s.index() as usize == *index)
+                        .cloned()
+                })
+                .collect(),
+            ..SourceShreds::default()
+        }
+    }
+
+    #[async_trait]
+    impl ShredSource for Batch {
+        fn name(&self) -> String {
+            String::from("batch")
+        }
+
+        async fn get_shreds(
+            &self,
+            _slot: Slot,
+            indices: &[usize],
+            _commitment: CommitmentLevel,
+        ) -> anyhow::Result<SourceShreds> {
+            Ok(by_index(&self.data, indices))
+        }
+
+        async fn get_coding_shreds(
+            &self,
+            _slot: Slot,
+            indices: &[usize],
+            _commitment: CommitmentLevel,
+        ) -> anyhow::Result<SourceShreds> {
+            Ok(by_index(&self.coding, indices))
+        }
+    }
+
+    #[tokio::test]
+    async fn served_batches_are_recovered() {
+        let leader = Keypair::new();
+        let batch = Batch::signed_by(&leader);
+
+        let report = deep_verify_slot(&batch.sample(leader.pubkey()), &batch).await;
+        assert!(report.is_recoverable(), "{}", report);
+        assert!(report.fec_sets.iter().all(|set| set.recovered > 0));
+    }
+
+    #[tokio::test]
+    async fn coding_shreds_are_located_by_their_position() {
+        let leader = Keypair::new();
+        let batch = Batch::with_coding_from(&leader, 7);
+        assert_eq!(first_coding_index(&batch.coding[1]), Some(7));
+        assert_eq!(first_coding_index(&batch.data[0]), None);
+
+        let report = deep_verify_slot(&batch.sample_with_coding(leader.pubkey()), &batch).await;
+        assert!(report.is_recoverable(), "{}", report);
+        assert!(report.fec_sets.iter().all(FecSetRecovery::is_recovered));
+
+        // nothing at the set's index, the set can't be checked but didn't fail either
+        let report = deep_verify_slot(&batch.sample(leader.pubkey()), &batch).await;
+        assert!(report.is_recoverable(), "{}", report);
+        assert!(report.fec_sets[0].unchecked.is_some());
+    }
+
+    #[tokio::test]
+    async fn withheld_coding_shreds_fail_recovery() {
+        let leader = Keypair::new();
+        let mut batch = Batch::signed_by(&leader);
+        let sample = batch.sample_with_coding(leader.pubkey());
+        batch.coding.clear();
+
+        let report = deep_verify_slot(&sample, &batch).await;
+        assert!(!report.is_recoverable());
+        assert!(report.fec_sets[0].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn batches_of_another_leader_fail_recovery() {
+        let batch = Batch::signed_by(&Keypair::new());
+
+        let report = deep_verify_slot(&batch.sample(Pubkey::new_unique()), &batch).await;
+        assert!(!report.is_recoverable());
+    }
+
+    #[test]
+    fn empty_report_isnt_recoverable() {
+        assert!(!RecoveryReport::default().is_recoverable());
+    }
+}
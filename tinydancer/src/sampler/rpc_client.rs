@@ -0,0 +1,400 @@
+//! Sampler rpc client - JSON-RPC over a pooled http client
+//!
+//! Every `JsonRpcClient` shares one `reqwest::Client`, so connections to the
+//! same rpc are reused across sources and tasks. Transport failures, 429/5xx
+//! responses and the node-is-behind family of rpc errors are retried with
+//! exponential backoff plus jitter, everything else comes straight back as a
+//! typed `RpcError`.
+use rand::Rng;
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE},
+    StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tiny_logger::logs::debug;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// solana rpc error codes worth retrying, the node may just not be caught up yet
+const BLOCK_NOT_AVAILABLE: i64 = -32004;
+const NODE_UNHEALTHY: i64 = -32005;
+const MIN_CONTEXT_SLOT_NOT_REACHED: i64 = -32016;
+
+lazy_static::lazy_static! {
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .pool_idle_timeout(Duration::from_secs(90))
+        .build()
+        .expect("failed to build the rpc http client");
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RpcError {
+    #[error("request to {endpoint} failed: {source}")]
+    Transport {
+        endpoint: String,
+        source: reqwest::Error,
+    },
+    #[error("{endpoint} responded with http {status}")]
+    Http {
+        endpoint: String,
+        status: StatusCode,
+    },
+    #[error("{method} failed with rpc error {code}: {message}")]
+    Rpc {
+        method: String,
+        code: i64,
+        message: String,
+        data: Option<Value>,
+    },
+    #[error("invalid {method} response: {source}")]
+    Decode {
+        method: String,
+        source: serde_json::Error,
+    },
+    /// the response decoded but doesn't answer what was asked for
+    #[error("unexpected {method} response: {message}")]
+    Unexpected { method: String, message: String },
+}
+
+impl RpcError {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RpcError::Transport { .. } => true,
+            RpcError::Http { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            RpcError::Rpc { code, .. } => matches!(
+                *code,
+                BLOCK_NOT_AVAILABLE | NODE_UNHEALTHY | MIN_CONTEXT_SLOT_NOT_REACHED
+            ),
+            RpcError::Decode { .. } | RpcError::Unexpected { .. } => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryConfig {
+    /// retries after the first attempt
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Backoff before retry number `retry` (1 based), somewhere in the upper half
+    /// of the exponential delay so concurrent callers don't retry in lockstep
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+        let half = delay / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonRpcResponse<T> {
+    result: Option<T>,
+    error: Option<JsonRpcErrorObject>,
+}
+
+#[derive(Deserialize)]
+struct JsonRpcErrorObject {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+#[derive(Clone)]
+pub struct JsonRpcClient {
+    endpoint: String,
+    retry: RetryConfig,
+    next_id: Arc<AtomicU64>,
+}
+
+impl fmt::Debug for JsonRpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JsonRpcClient({})", self.endpoint)
+    }
+}
+
+impl JsonRpcClient {
+    pub fn new(endpoint: String) -> Self {
+        Self::new_with_retry(endpoint, RetryConfig::default())
+    }
+
+    pub fn new_with_retry(endpoint: String, retry: RetryConfig) -> Self {
+        Self {
+            endpoint,
+            retry,
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Calls `method` with `params`, retrying transient failures
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, RpcError> {
+        let mut retry = 0;
+        loop {
+            match self.call_once(method, &params).await {
+                Err(e) if e.is_retryable() && retry < self.retry.max_retries => {
+                    retry += 1;
+                    let delay = self.retry.delay(retry);
+                    debug!(
+                        "{} to {} failed, retry {} in {:?}: {}",
+                        method, self.endpoint, retry, delay, e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn call_once<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &Value,
+    ) -> Result<T, RpcError> {
+        let transport = |source| RpcError::Transport {
+            endpoint: self.endpoint.clone(),
+            source,
+        };
+        let decode = |source| RpcError::Decode {
+            method: method.to_string(),
+            source,
+        };
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id.fetch_add(1, Ordering::Relaxed),
+            "method": method,
+            "params": params,
+        });
+        let response = HTTP_CLIENT
+            .post(&self.endpoint)
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT, "application/json")
+            .body(request.to_string())
+            .send()
+            .await
+            .map_err(transport)?;
+
+        // rpc errors usually come with a 200, anything else is the http layer failing
+        let status = response.status();
+        let body = response.text().await.map_err(transport)?;
+        let response: JsonRpcResponse<T> = match serde_json::from_str(&body) {
+            Ok(response) => response,
+            Err(_) if !status.is_success() => {
+                return Err(RpcError::Http {
+                    endpoint: self.endpoint.clone(),
+                    status,
+                })
+            }
+            Err(e) => return Err(decode(e)),
+        };
+
+        match response {
+            JsonRpcResponse {
+                error: Some(error), ..
+            } => Err(RpcError::Rpc {
+                method: method.to_string(),
+                code: error.code,
+                message: error.message,
+                data: error.data,
+            }),
+            JsonRpcResponse {
+                result: Some(result),
+                ..
+            } => Ok(result),
+            // a null result, let `T` decide if that's acceptable
+            _ => serde_json::from_value(Value::Null).map_err(decode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    const NO_DELAY: RetryConfig = RetryConfig {
+        max_retries: 3,
+        base_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+    };
+
+    /// Rpc answering each request with the next of `responses`, repeating the last one
+    async fn scripted_rpc(responses: Vec<(u16, &'static str)>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let served = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                read_request(&mut stream).await;
+                let n = served.fetch_add(1, Ordering::SeqCst);
+                let (status, body) = responses[n.min(responses.len() - 1)];
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (endpoint, requests)
+    }
+
+    async fn read_request(stream: &mut tokio::net::TcpStream) {
+        let mut request = vec![];
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let request = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = request.split_once("\r\n\r\n") {
+                let content_length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if body.len() >= content_length {
+                    return;
+                }
+            }
+            if n == 0 {
+                return;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_until_success() {
+        let (endpoint, requests) = scripted_rpc(vec![
+            (503, ""),
+            (
+                200,
+                r#"{"jsonrpc":"2.0","id":2,"error":{"code":-32005,"message":"node is behind"}}"#,
+            ),
+            (200, r#"{"jsonrpc":"2.0","id":3,"result":42}"#),
+        ])
+        .await;
+        let client = JsonRpcClient::new_with_retry(endpoint, NO_DELAY);
+
+        let slot: u64 = client.call("getSlot", Value::Null).await.unwrap();
+        assert_eq!(slot, 42);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn rpc_errors_come_straight_back() {
+        let (endpoint, requests) = scripted_rpc(vec![(
+            200,
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"invalid params"}}"#,
+        )])
+        .await;
+        let client = JsonRpcClient::new_with_retry(endpoint, NO_DELAY);
+
+        let err = client
+            .call::<u64>("getSlot", Value::Null)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RpcError::Rpc { code: -32602, .. }));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_give_up_after_max_retries() {
+        let (endpoint, requests) = scripted_rpc(vec![(429, "")]).await;
+        let client = JsonRpcClient::new_with_retry(endpoint, NO_DELAY);
+
+        let err = client
+            .call::<u64>("getSlot", Value::Null)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RpcError::Http {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                ..
+            }
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn retry_delay_backs_off_up_to_the_cap() {
+        let retry = RetryConfig {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+        };
+        for _ in 0..100 {
+            let first = retry.delay(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let capped = retry.delay(5);
+            assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+        }
+    }
+
+    #[test]
+    fn only_transient_rpc_errors_are_retried() {
+        let rpc_error = |code| RpcError::Rpc {
+            method: String::from("getShreds"),
+            code,
+            message: String::new(),
+            data: None,
+        };
+        assert!(rpc_error(NODE_UNHEALTHY).is_retryable());
+        assert!(!rpc_error(-32602).is_retryable());
+
+        let http_error = |status| RpcError::Http {
+            endpoint: String::new(),
+            status,
+        };
+        assert!(http_error(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(http_error(StatusCode::BAD_GATEWAY).is_retryable());
+        assert!(!http_error(StatusCode::NOT_FOUND).is_retryable());
+    }
+}
@@ -0,0 +1,639 @@
+//! Shred sources - where the sampler pulls shreds from
+//!
+//! rpc     -> `getShreds` JSON-RPC call against the configured cluster, spread
+//!            across every configured provider when there is more than one
+//! gossip  -> `getShreds` against the rpc port of the cluster's repair peers
+//! archive -> the local rocksdb archive written by `shred_archiver`
+use crate::sampler::{
+    archive::get_shred,
+    providers::{MultiRpcShredSource, ProviderFault, ProviderReputations},
+    request_shreds_of_type,
+    rpc_client::JsonRpcClient,
+};
+use crate::try_coerce_shred;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use solana_ledger::shred::{Shred, ShredType};
+use solana_sdk::{
+    clock::Slot, commitment_config::CommitmentLevel, pubkey::Pubkey, signature::Keypair,
+};
+use solana_streamer::socket::SocketAddrSpace;
+use std::{
+    fmt,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{atomic::AtomicBool, Arc},
+};
+use tiny_gossip::{
+    cluster_info::ClusterInfo,
+    contact_info::ContactInfo,
+    gossip_service::{make_gossip_node, GossipService},
+};
+use tiny_logger::logs::{info, warn};
+
+/// Number of repair peers asked for a slot before giving up
+const GOSSIP_PEERS_PER_REQUEST: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum ShredSourceKind {
+    Rpc,
+    Gossip,
+    Archive,
+}
+
+/// Shreds returned by a source along with the leader it claims signed them,
+/// sources that can't tell (the archive) return `None`
+#[derive(Debug, Default, Clone)]
+pub struct SourceShreds {
+    pub shreds: Vec<Option<Shred>>,
+    pub leader: Option<Pubkey>,
+    /// providers that disagreed with the rest, only set by multi-provider sources
+    pub faults: Vec<ProviderFault>,
+}
+
+#[async_trait]
+pub trait ShredSource: Send + Sync {
+    /// Name used in logs and cross-check reports
+    fn name(&self) -> String;
+
+    /// Data shreds at `indices` of the slot, as seen at `commitment`
+    async fn get_shreds(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds>;
+
+    /// Same as `get_shreds` but for indices into the slot's coding shreds
+    async fn get_coding_shreds(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds>;
+}
+
+/// Pulls shreds from a single rpc node through `getShreds`
+#[derive(Clone, Debug)]
+pub struct RpcShredSource {
+    client: JsonRpcClient,
+}
+
+impl RpcShredSource {
+    pub fn new(endpoint: String) -> Self {
+        Self {
+            client: JsonRpcClient::new(endpoint),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        self.client.endpoint()
+    }
+
+    async fn fetch(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        shred_type: ShredType,
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        let response = request_shreds_of_type(
+            slot as usize,
+            indices.to_vec(),
+            shred_type,
+            commitment,
+            &self.client,
+        )
+        .await?;
+
+        let shreds = response
+            .shreds
+            .iter()
+            .map(|s| try_coerce_shred!(s))
+            .collect();
+        let leader = Pubkey::from_str(response.leader.as_str())?;
+
+        Ok(SourceShreds {
+            shreds,
+            leader: Some(leader),
+            ..SourceShreds::default()
+        })
+    }
+}
+
+#[async_trait]
+impl ShredSource for RpcShredSource {
+    fn name(&self) -> String {
+        format!("rpc({})", self.endpoint())
+    }
+
+    async fn get_shreds(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        self.fetch(slot, indices, ShredType::Data, commitment).await
+    }
+
+    async fn get_coding_shreds(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        self.fetch(slot, indices, ShredType::Code, commitment).await
+    }
+}
+
+/// Pulls shreds from the repair peers of the cluster discovered over gossip,
+/// each peer is queried on the rpc address it advertises in its contact info
+pub struct GossipShredSource {
+    cluster_info: Arc<ClusterInfo>,
+    _gossip_service: GossipService,
+    exit: Arc<AtomicBool>,
+}
+
+impl GossipShredSource {
+    /// Spins up a gossip spy node and joins the cluster at `entrypoint`
+    pub fn new(entrypoint: SocketAddr) -> anyhow::Result<Self> {
+        let shred_version = solana_net_utils::get_cluster_shred_version(&entrypoint)
+            .map_err(|e| anyhow!("failed to get shred version from {}: {}", entrypoint, e))?;
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let (gossip_service, _ip_echo, cluster_info) = make_gossip_node(
+            Keypair::new(),
+            Some(&entrypoint),
+            &exit,
+            None,
+            shred_version,
+            true,
+            SocketAddrSpace::Unspecified,
+        );
+        info!(
+            "gossip shred source joined {} with shred version {}",
+            entrypoint, shred_version
+        );
+
+        Ok(Self {
+            cluster_info,
+            _gossip_service: gossip_service,
+            exit,
+        })
+    }
+
+    fn rpc_peers(&self, slot: Slot) -> Vec<ContactInfo> {
+        let socket_addr_space = self.cluster_info.socket_addr_space();
+        let mut peers: Vec<ContactInfo> = self
+            .cluster_info
+            .repair_peers(slot)
+            .into_iter()
+            .filter(|peer| ContactInfo::is_valid_address(&peer.rpc, socket_addr_space))
+            .collect();
+        peers.shuffle(&mut rand::thread_rng());
+        peers
+    }
+
+    async fn fetch(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        shred_type: ShredType,
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        let peers = self.rpc_peers(slot);
+        if peers.is_empty() {
+            return Err(anyhow!("no repair peers found for slot {}", slot));
+        }
+
+        for peer in peers.iter().take(GOSSIP_PEERS_PER_REQUEST) {
+            let source = RpcShredSource::new(format!("http://{}", peer.rpc));
+            match source.fetch(slot, indices, shred_type, commitment).await {
+                Ok(shreds) => return Ok(shreds),
+                Err(e) => warn!("repair peer {} failed to serve shreds: {}", peer.id, e),
+            }
+        }
+
+        Err(anyhow!("none of the repair peers served slot {}", slot))
+    }
+}
+
+impl Drop for GossipShredSource {
+    fn drop(&mut self) {
+        self.exit.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl ShredSource for GossipShredSource {
+    fn name(&self) -> String {
+        String::from("gossip")
+    }
+
+    async fn get_shreds(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        self.fetch(slot, indices, ShredType::Data, commitment).await
+    }
+
+    async fn get_coding_shreds(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        self.fetch(slot, indices, ShredType::Code, commitment).await
+    }
+}
+
+/// Reads shreds back out of the local archive
+pub struct ArchiveShredSource {
+    instance: Arc<rocksdb::DB>,
+}
+
+impl ArchiveShredSource {
+    pub fn new(instance: Arc<rocksdb::DB>) -> Self {
+        Self { instance }
+    }
+
+    fn fetch(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        shred_type: ShredType,
+        // only verified shreds get archived, whatever commitment they were sampled at
+        _commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        let shreds = indices
+            .iter()
+            .map(|index| get_shred(&self.instance, slot, shred_type, *index as u32))
+            .collect::<anyhow::Result<Vec<Option<Shred>>>>()?;
+
+        Ok(SourceShreds {
+            shreds,
+            leader: None,
+            ..SourceShreds::default()
+        })
+    }
+}
+
+#[async_trait]
+impl ShredSource for ArchiveShredSource {
+    fn name(&self) -> String {
+        String::from("archive")
+    }
+
+    async fn get_shreds(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        self.fetch(slot, indices, ShredType::Data, commitment)
+    }
+
+    async fn get_coding_shreds(
+        &self,
+        slot: Slot,
+        indices: &[usize],
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SourceShreds> {
+        self.fetch(slot, indices, ShredType::Code, commitment)
+    }
+}
+
+/// Rpc providers shreds are sampled from and how many of them each index is requested from
+#[derive(Clone, Debug)]
+pub struct RpcProviders {
+    pub endpoints: Vec<String>,
+    pub overlap: usize,
+    pub reputations: Arc<ProviderReputations>,
+}
+
+impl RpcProviders {
+    /// `primary` followed by the extra `endpoints`, duplicates dropped
+    pub fn new(primary: String, endpoints: Vec<String>, overlap: usize) -> Self {
+        let mut all = vec![primary];
+        for endpoint in endpoints {
+            if !all.contains(&endpoint) {
+                all.push(endpoint);
+            }
+        }
+        Self {
+            endpoints: all,
+            overlap,
+            reputations: Arc::new(ProviderReputations::default()),
+        }
+    }
+}
+
+/// Builds the configured sources, `archive` needs a db instance and `gossip` an entrypoint
+pub fn build_shred_sources(
+    kinds: &[ShredSourceKind],
+    rpc_providers: RpcProviders,
+    archive: Option<Arc<rocksdb::DB>>,
+    gossip_entrypoint: Option<SocketAddr>,
+) -> anyhow::Result<Vec<Arc<dyn ShredSource>>> {
+    let mut sources: Vec<Arc<dyn ShredSource>> = Vec::with_capacity(kinds.len());
+    for kind in kinds {
+        match kind {
+            ShredSourceKind::Rpc => match rpc_providers.endpoints.as_slice() {
+                [] => return Err(anyhow!("rpc shred source needs at least one rpc endpoint")),
+                [endpoint] => sources.push(Arc::new(RpcShredSource::new(endpoint.clone()))),
+                endpoints => sources.push(Arc::new(MultiRpcShredSource::new(
+                    endpoints.to_vec(),
+                    rpc_providers.overlap,
+                    rpc_providers.reputations.clone(),
+                ))),
+            },
+            ShredSourceKind::Gossip => {
+                let entrypoint = gossip_entrypoint
+                    .ok_or_else(|| anyhow!("gossip shred source needs a gossip entrypoint"))?;
+                sources.push(Arc::new(GossipShredSource::new(entrypoint)?));
+            }
+            ShredSourceKind::Archive => {
+                let instance = archive
+                    .clone()
+                    .ok_or_else(|| anyhow!("archive shred source needs an archive path"))?;
+                sources.push(Arc::new(ArchiveShredSource::new(instance)));
+            }
+        }
+    }
+
+    if sources.is_empty() {
+        return Err(anyhow!("at least one shred source has to be configured"));
+    }
+    Ok(sources)
+}
+
+/// Outcome of comparing the same shreds pulled from two sources
+#[derive(Debug, Default, Clone)]
+pub struct CrossCheckReport {
+    pub slot: Slot,
+    pub primary: String,
+    pub secondary: String,
+    pub matched: usize,
+    /// indices both sources returned but with different payloads
+    pub mismatched: Vec<u32>,
+    /// indices the primary returned but the secondary didn't
+    pub missing: Vec<u32>,
+    /// same as `mismatched` for the coding shreds
+    pub mismatched_coding: Vec<u32>,
+    /// same as `missing` for the coding shreds
+    pub missing_coding: Vec<u32>,
+}
+
+impl CrossCheckReport {
+    pub fn is_consistent(&self) -> bool {
+        self.mismatched.is_empty() && self.mismatched_coding.is_empty()
+    }
+}
+
+impl fmt::Display for CrossCheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "slot {} {} vs {}: matched {} mismatched {:?} missing {:?} mismatched coding {:?} missing coding {:?}",
+            self.slot,
+            self.primary,
+            self.secondary,
+            self.matched,
+            self.mismatched,
+            self.missing,
+            self.mismatched_coding,
+            self.missing_coding
+        )
+    }
+}
+
+/// Pulls the same data and coding indices from both sources and compares the
+/// shreds byte for byte
+pub async fn cross_check_sources(
+    slot: Slot,
+    indices: &[usize],
+    coding_indices: &[usize],
+    commitment: CommitmentLevel,
+    primary: &dyn ShredSource,
+    secondary: &dyn ShredSource,
+) -> anyhow::Result<CrossCheckReport> {
+    let ((primary_data, primary_coding), (secondary_data, secondary_coding)) = futures::try_join!(
+        pull_shreds(primary, slot, indices, coding_indices, commitment),
+        pull_shreds(secondary, slot, indices, coding_indices, commitment)
+    )?;
+
+    let mut report = CrossCheckReport {
+        slot,
+        primary: primary.name(),
+        secondary: secondary.name(),
+        ..CrossCheckReport::default()
+    };
+    report.matched += compare_shreds(
+        &primary_data,
+        &secondary_data,
+        &mut report.mismatched,
+        &mut report.missing,
+    );
+    report.matched += compare_shreds(
+        &primary_coding,
+        &secondary_coding,
+        &mut report.mismatched_coding,
+        &mut report.missing_coding,
+    );
+
+    Ok(report)
+}
+
+/// Data and coding shreds of a source, coding shreds are only requested when
+/// some were sampled
+async fn pull_shreds(
+    source: &dyn ShredSource,
+    slot: Slot,
+    indices: &[usize],
+    coding_indices: &[usize],
+    commitment: CommitmentLevel,
+) -> anyhow::Result<(SourceShreds, SourceShreds)> {
+    let data = source.get_shreds(slot, indices, commitment).await?;
+    let coding = match coding_indices.is_empty() {
+        true => SourceShreds::default(),
+        false => {
+            source
+                .get_coding_shreds(slot, coding_indices, commitment)
+                .await?
+        }
+    };
+    Ok((data, coding))
+}
+
+/// Matches the shreds the primary served against the secondary's, returning how
+/// many were identical
+fn compare_shreds(
+    primary: &SourceShreds,
+    secondary: &SourceShreds,
+    mismatched: &mut Vec<u32>,
+    missing: &mut Vec<u32>,
+) -> usize {
+    let mut matched = 0;
+    for shred in primary.shreds.iter().flatten() {
+        let other = secondary
+            .shreds
+            .iter()
+            .flatten()
+            .find(|s| s.index() == shred.index() && s.shred_type() == shred.shred_type());
+        match other {
+            Some(other) if other.payload() == shred.payload() => matched += 1,
+            Some(_) => mismatched.push(shred.index()),
+            None => missing.push(shred.index()),
+        }
+    }
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{archive::put_shred, open_temp_archive};
+    use solana_ledger::shred::ShredFlags;
+
+    fn data_shred(slot: Slot, index: u32, data: &[u8]) -> Shred {
+        Shred::new_from_data(slot, index, 1, data, ShredFlags::empty(), 0, 0, 0)
+    }
+
+    fn coding_shred(slot: Slot, index: u32, parity: &[u8]) -> Shred {
+        Shred::new_from_parity_shard(slot, index, parity, 0, 32, 32, index as u16, 0)
+    }
+
+    /// Serves the shreds it was built with, whatever the slot
+    struct FixedShreds(Vec<Shred>);
+
+    impl FixedShreds {
+        fn serve(&self, indices: &[usize], shred_type: ShredType) -> SourceShreds {
+            let shreds = indices
+                .iter()
+                .map(|index| {
+                    self.0
+                        .iter()
+                        .find(|s| s.index() as usize == *index && s.shred_type() == shred_type)
+                        .cloned()
+                })
+                .collect();
+            SourceShreds {
+                shreds,
+                ..SourceShreds::default()
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ShredSource for FixedShreds {
+        fn name(&self) -> String {
+            String::from("fixed")
+        }
+
+        async fn get_shreds(
+            &self,
+            _slot: Slot,
+            indices: &[usize],
+            _commitment: CommitmentLevel,
+        ) -> anyhow::Result<SourceShreds> {
+            Ok(self.serve(indices, ShredType::Data))
+        }
+
+        async fn get_coding_shreds(
+            &self,
+            _slot: Slot,
+            indices: &[usize],
+            _commitment: CommitmentLevel,
+        ) -> anyhow::Result<SourceShreds> {
+            Ok(self.serve(indices, ShredType::Code))
+        }
+    }
+
+    #[tokio::test]
+    async fn archive_source_serves_what_was_archived() {
+        let (instance, path) = open_temp_archive("source");
+        let instance = Arc::new(instance);
+        put_shred(&instance, &data_shred(5, 1, &[1])).unwrap();
+
+        let source = ArchiveShredSource::new(instance.clone());
+        let served = source
+            .get_shreds(5, &[0, 1], CommitmentLevel::Finalized)
+            .await
+            .unwrap();
+        assert!(served.shreds[0].is_none());
+        assert_eq!(served.shreds[1].as_ref().map(|s| s.index()), Some(1));
+        assert!(served.leader.is_none());
+
+        drop((source, instance));
+        let _ = rocksdb::DB::destroy(&rocksdb::Options::default(), &path);
+    }
+
+    #[tokio::test]
+    async fn cross_check_flags_differing_and_missing_shreds() {
+        let primary = FixedShreds(vec![
+            data_shred(5, 0, &[1]),
+            data_shred(5, 1, &[1]),
+            data_shred(5, 2, &[1]),
+        ]);
+        let secondary = FixedShreds(vec![data_shred(5, 0, &[1]), data_shred(5, 1, &[2])]);
+
+        let report = cross_check_sources(
+            5,
+            &[0, 1, 2],
+            &[],
+            CommitmentLevel::Confirmed,
+            &primary,
+            &secondary,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.matched, 1);
+        assert_eq!(report.mismatched, vec![1]);
+        assert_eq!(report.missing, vec![2]);
+        assert!(!report.is_consistent());
+    }
+
+    #[tokio::test]
+    async fn cross_check_compares_coding_shreds() {
+        let primary = FixedShreds(vec![
+            data_shred(5, 3, &[1]),
+            coding_shred(5, 3, &[1]),
+            coding_shred(5, 4, &[1]),
+        ]);
+        let secondary = FixedShreds(vec![data_shred(5, 3, &[1]), coding_shred(5, 3, &[2])]);
+
+        let report = cross_check_sources(
+            5,
+            &[3],
+            &[3, 4],
+            CommitmentLevel::Confirmed,
+            &primary,
+            &secondary,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.matched, 1);
+        assert!(report.mismatched.is_empty());
+        assert_eq!(report.mismatched_coding, vec![3]);
+        assert_eq!(report.missing_coding, vec![4]);
+        assert!(!report.is_consistent());
+    }
+
+    #[test]
+    fn sources_need_what_they_pull_from() {
+        let providers = RpcProviders::new(String::from("http://localhost:8899"), vec![], 1);
+        let build = |kinds: &[ShredSourceKind]| {
+            build_shred_sources(kinds, providers.clone(), None, None).map(|sources| sources.len())
+        };
+
+        assert_eq!(build(&[ShredSourceKind::Rpc]).unwrap(), 1);
+        assert!(build(&[]).is_err());
+        assert!(build(&[ShredSourceKind::Archive]).is_err());
+        assert!(build(&[ShredSourceKind::Rpc, ShredSourceKind::Gossip]).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! Slot size discovery - how many data and coding shreds a slot was produced with
+//!
+//! A single shred only knows the size of its own FEC set, so the last data
+//! shred of the slot is searched for instead. The first round probes indices
+//! 0, 1, 3, 7, ... up to `MAX_DATA_SHREDS_PER_SLOT`, later rounds probe evenly
+//! across the remaining gap between the highest served index and the lowest
+//! missing one above it. A served shred flagged last-in-slot ends the search
+//! early. The slot's coding shreds end where the last FEC set's do, a coding
+//! shred of that set served at its `fec_set_index` tells where the set's coding
+//! shreds start from its index less its position in the erasure batch.
+//!
+//! Sizes are cached per slot, a slot sampled at confirmation and again at root
+//! is only probed once.
+use crate::sampler::{recovery::first_coding_index, shred_source::ShredSource};
+use anyhow::anyhow;
+use solana_ledger::shred::{Shred, MAX_DATA_SHREDS_PER_SLOT};
+use solana_sdk::{clock::Slot, commitment_config::CommitmentLevel};
+use std::{collections::BTreeMap, sync::Mutex};
+use tiny_logger::logs::debug;
+
+/// Indices requested per probing round
+const PROBES_PER_ROUND: usize = 16;
+/// Slots whose size is kept around
+pub const SLOT_SIZE_CACHE_SLOTS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlotSize {
+    pub num_data_shreds: usize,
+    pub num_coding_shreds: usize,
+}
+
+impl SlotSize {
+    pub fn population(&self) -> usize {
+        self.num_data_shreds + self.num_coding_shreds
+    }
+}
+
+pub struct SlotSizeCache {
+    sizes: Mutex<BTreeMap<Slot, SlotSize>>,
+    capacity: usize,
+}
+
+impl Default for SlotSizeCache {
+    fn default() -> Self {
+        Self::new(SLOT_SIZE_CACHE_SLOTS)
+    }
+}
+
+impl SlotSizeCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sizes: Mutex::new(BTreeMap::new()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Size of `slot`, probed through `source` the first time the slot is seen
+    pub async fn get_or_discover(
+        &self,
+        slot: Slot,
+        source: &dyn ShredSource,
+        commitment: CommitmentLevel,
+    ) -> anyhow::Result<SlotSize> {
+        if let Some(size) = self.sizes.lock().unwrap().get(&slot) {
+            return Ok(*size);
+        }
+
+        let size = discover_slot_size(slot, source, commitment).await?;
+        let mut sizes = self.sizes.lock().unwrap();
+        sizes.insert(slot, size);
+        // oldest slots go first, they won't be sampled again
+        while sizes.len() > self.capacity {
+            sizes.pop_first();
+        }
+        Ok(size)
+    }
+}
+
+/// Indices to probe for the end of the slot, somewhere in `from..to`
+fn probe_indices(from: usize, to: usize, first_round: bool) -> Vec<usize> {
+    if first_round {
+        return (0..)
+            .map(|exp| (1usize << exp) - 1)
+            .take_while(|index| *index < to)
+            .filter(|index| *index >= from)
+            .collect();
+    }
+    let gap = to - from;
+    if gap <= PROBES_PER_ROUND {
+        return (from..to).collect();
+    }
+    (0..PROBES_PER_ROUND)
+        .map(|i| from + i * gap / PROBES_PER_ROUND)
+        .collect()
+}
+
+pub async fn discover_slot_size(
+    slot: Slot,
+    source: &dyn ShredSource,
+    commitment: CommitmentLevel,
+) -> anyhow::Result<SlotSize> {
+    // every index below `from` is covered by a served shred, `to` and above are past the end
+    let mut from = 0;
+    let mut to = MAX_DATA_SHREDS_PER_SLOT;
+    let mut last_shred: Option<Shred> = None;
+    let mut first_round = true;
+
+    while from < to {
+        let probes = probe_indices(from, to, first_round);
+        first_round = false;
+        let served: Vec<Shred> = source
+            .get_shreds(slot, &probes, commitment)
+            .await?
+            .shreds
+            .into_iter()
+            .flatten()
+            .filter(|s| s.is_data() && s.slot() == slot && probes.contains(&(s.index() as usize)))
+            .collect();
+
+        if let Some(last) = served.iter().find(|s| s.last_in_slot()) {
+            last_shred = Some(last.clone());
+            break;
+        }
+        if let Some(highest) = served.into_iter().max_by_key(|s| s.index()) {
+            from = highest.index() as usize + 1;
+            last_shred = Some(highest);
+        }
+        // a served shred above a missing one wins, the missing one was just withheld
+        to = probes
+            .iter()
+            .copied()
+            .filter(|index| *index >= from)
+            .min()
+            .unwrap_or(to)
+            .min(to);
+    }
+
+    let last_shred =
+        last_shred.ok_or_else(|| anyhow!("no data shreds served for slot {}", slot))?;
+    let num_data_shreds = last_shred.index() as usize + 1;
+    if !last_shred.last_in_slot() {
+        debug!(
+            "slot {} ends at data shred {} but it isn't flagged last in slot",
+            slot,
+            last_shred.index()
+        );
+    }
+
+    let fec_set_index = last_shred.fec_set_index();
+    let of_last_set =
+        |s: &Shred| s.is_code() && s.slot() == slot && s.fec_set_index() == fec_set_index;
+    let located = source
+        .get_coding_shreds(slot, &[fec_set_index as usize], commitment)
+        .await
+        .ok()
+        .and_then(|coding| coding.shreds.into_iter().flatten().find(of_last_set))
+        .and_then(|shred| first_coding_index(&shred).map(|first| (shred, first)));
+    let num_coding_shreds = match located {
+        Some((coding_shred, first_coding_index)) => {
+            first_coding_index as usize + coding_shred.num_coding_shreds()? as usize
+        }
+        // no coding shred to go by, assume the slot is made of 32:32 erasure batches
+        _ => {
+            debug!(
+                "no coding shred served for fec set {} of slot {}",
+                fec_set_index, slot
+            );
+            num_data_shreds
+        }
+    };
+
+    Ok(SlotSize {
+        num_data_shreds,
+        num_coding_shreds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::shred_source::SourceShreds;
+    use async_trait::async_trait;
+    use solana_ledger::shred::ShredFlags;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serves the data shreds of a slot of `num_data_shreds`, except the `withheld` ones,
+    /// and the coding shreds of the last fec set if it has `num_coding_shreds`. These
+    /// start at the set's `fec_set_index` unless `first_coding_index` is set
+    #[derive(Default)]
+    struct FakeSlot {
+        num_data_shreds: u32,
+        withheld: Vec<u32>,
+        num_coding_shreds: u16,
+        first_coding_index: Option<u32>,
+        probes: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ShredSource for FakeSlot {
+        fn name(&self) -> String {
+            String::from("fake")
+        }
+
+        async fn get_shreds(
+            &self,
+            slot: Slot,
+            indices: &[usize],
+            _commitment: CommitmentLevel,
+        ) -> anyhow::Result<SourceShreds> {
+            self.probes.fetch_add(1, Ordering::Relaxed);
+            let shreds = indices
+                .iter()
+                .map(|index| *index as u32)
+                .map(|index| {
+                    if index >= self.num_data_shreds || self.withheld.contains(&index) {
+                        return None;
+                    }
+                    let flags = if index + 1 == self.num_data_shreds {
+                        ShredFlags::LAST_SHRED_IN_SLOT
+                    } else {
+                        ShredFlags::empty()
+                    };
+                    Some(Shred::new_from_data(
+                        slot,
+                        index,
+                        1,
+                        &[],
+                        flags,
+                        0,
+                        0,
+                        index - index % 32,
+                    ))
+                })
+                .collect();
+            Ok(SourceShreds {
+                shreds,
+                ..SourceShreds::default()
+            })
+        }
+
+        async fn get_coding_shreds(
+            &self,
+            slot: Slot,
+            indices: &[usize],
+            _commitment: CommitmentLevel,
+        ) -> anyhow::Result<SourceShreds> {
+            let fec_set_index = self.num_data_shreds.saturating_sub(1) / 32 * 32;
+            let first_coding_index = self.first_coding_index.unwrap_or(fec_set_index);
+            let shreds = indices
+                .iter()
+                .map(|index| *index as u32)
+                .map(|index| {
+                    let position = index
+                        .checked_sub(first_coding_index)
+                        .filter(|position| *position < u32::from(self.num_coding_shreds))?;
+                    Some(Shred::new_from_parity_shard(
+                        slot,
+                        index,
+                        &[],
+                        fec_set_index,
+                        (self.num_data_shreds - fec_set_index) as u16,
+                        self.num_coding_shreds,
+                        position as u16,
+                        0,
+                    ))
+                })
+                .collect();
+            Ok(SourceShreds {
+                shreds,
+                ..SourceShreds::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_the_last_data_shred() {
+        for num_data_shreds in [1, 2, 33, 300, 1024] {
+            let slot = FakeSlot {
+                num_data_shreds,
+                withheld: vec![],
+                ..FakeSlot::default()
+            };
+            let size = discover_slot_size(1, &slot, CommitmentLevel::Confirmed)
+                .await
+                .unwrap();
+            assert_eq!(size.num_data_shreds, num_data_shreds as usize);
+        }
+    }
+
+    #[tokio::test]
+    async fn withheld_probes_dont_cut_the_slot_short() {
+        let slot = FakeSlot {
+            num_data_shreds: 300,
+            withheld: vec![127],
+            ..FakeSlot::default()
+        };
+        let size = discover_slot_size(1, &slot, CommitmentLevel::Confirmed)
+            .await
+            .unwrap();
+        assert_eq!(size.num_data_shreds, 300);
+    }
+
+    #[tokio::test]
+    async fn empty_slot_is_an_error() {
+        let slot = FakeSlot {
+            num_data_shreds: 0,
+            withheld: vec![],
+            ..FakeSlot::default()
+        };
+        assert!(discover_slot_size(1, &slot, CommitmentLevel::Confirmed)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn coding_shreds_end_with_the_last_fec_set() {
+        let slot = FakeSlot {
+            num_data_shreds: 40,
+            num_coding_shreds: 12,
+            ..FakeSlot::default()
+        };
+        let size = discover_slot_size(1, &slot, CommitmentLevel::Confirmed)
+            .await
+            .unwrap();
+        assert_eq!(
+            size,
+            SlotSize {
+                num_data_shreds: 40,
+                num_coding_shreds: 32 + 12,
+            }
+        );
+
+        // the last set's coding shreds start before its data shreds
+        let slot = FakeSlot {
+            num_data_shreds: 40,
+            num_coding_shreds: 12,
+            first_coding_index: Some(30),
+            ..FakeSlot::default()
+        };
+        let size = discover_slot_size(1, &slot, CommitmentLevel::Confirmed)
+            .await
+            .unwrap();
+        assert_eq!(size.num_coding_shreds, 30 + 12);
+
+        // without a coding shred to go by the slot is assumed to be 32:32 batches
+        let slot = FakeSlot {
+            num_data_shreds: 40,
+            ..FakeSlot::default()
+        };
+        let size = discover_slot_size(1, &slot, CommitmentLevel::Confirmed)
+            .await
+            .unwrap();
+        assert_eq!(size.num_coding_shreds, 40);
+    }
+
+    #[tokio::test]
+    async fn slots_are_probed_once() {
+        let cache = SlotSizeCache::new(2);
+        let slot = FakeSlot {
+            num_data_shreds: 300,
+            ..FakeSlot::default()
+        };
+
+        let size = cache
+            .get_or_discover(1, &slot, CommitmentLevel::Confirmed)
+            .await
+            .unwrap();
+        let probes = slot.probes.load(Ordering::Relaxed);
+        let cached = cache
+            .get_or_discover(1, &slot, CommitmentLevel::Finalized)
+            .await
+            .unwrap();
+        assert_eq!(cached, size);
+        assert_eq!(slot.probes.load(Ordering::Relaxed), probes);
+
+        // the oldest slot is evicted past the capacity and probed again
+        for newer in [2, 3] {
+            cache
+                .get_or_discover(newer, &slot, CommitmentLevel::Confirmed)
+                .await
+                .unwrap();
+        }
+        let probes = slot.probes.load(Ordering::Relaxed);
+        cache
+            .get_or_discover(1, &slot, CommitmentLevel::Confirmed)
+            .await
+            .unwrap();
+        assert!(slot.probes.load(Ordering::Relaxed) > probes);
+    }
+
+    #[test]
+    fn probes_stay_in_the_gap() {
+        assert_eq!(probe_indices(0, 16, true), vec![0, 1, 3, 7, 15]);
+        assert_eq!(probe_indices(128, 256, false).len(), PROBES_PER_ROUND);
+        assert!(probe_indices(128, 256, false)
+            .iter()
+            .all(|index| (128..256).contains(index)));
+        assert_eq!(probe_indices(4, 7, false), vec![4, 5, 6]);
+    }
+}
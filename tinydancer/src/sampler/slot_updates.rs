@@ -0,0 +1,414 @@
+//! Slot updates - `slotsUpdatesSubscribe` over an async websocket
+//!
+//! The sampling mode picks which updates are sampled: `frozen` slots for
+//! processed, `optimisticConfirmation` for confirmed and `root` for finalized.
+//! In confirmed-and-finalized mode a slot is sampled at confirmation and again
+//! once it is rooted.
+//!
+//! Dropped connections are retried with exponential backoff. The first update
+//! of each commitment seen after a reconnect is compared with the last one
+//! seen before it and the slots in between are queued for sampling, so an
+//! outage doesn't leave silent holes in the sampled history. Backfilled slots
+//! may include skipped slots, those simply fail to sample and are logged.
+//!
+//! Every bank the cluster creates is also published on the `SlotStream`, as
+//! `slotSubscribe` would report it, for the rpc bridge to re-export. The root
+//! it's reported with is seeded through `getSlot` on every connect, until the
+//! first root update comes in.
+use crate::sampler::{
+    pipeline::{QueuedSlot, SlotQueue, SlotQueueClosed},
+    rpc_client::JsonRpcClient,
+    SlotsUpdatesResponse, SlotsUpdatesResult,
+};
+use crate::tinydancer::ClientStatus;
+use futures::{SinkExt, StreamExt};
+use prometheus::{opts, register_int_counter, IntCounter};
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_response::SlotInfo;
+use solana_sdk::{clock::Slot, commitment_config::CommitmentLevel};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tiny_logger::logs::{info, warn};
+use tokio::sync::broadcast;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+const RECONNECT_BACKOFF_INITIAL: Duration = Duration::from_millis(500);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// Most slots queued after a reconnect, anything older than that is given up on
+const MAX_BACKFILL_SLOTS: u64 = 1_000;
+/// Slots a `SlotStream` subscriber can fall behind before it misses some
+const SLOT_STREAM_CAPACITY: usize = 256;
+
+lazy_static::lazy_static! {
+    static ref SLOT_SUBSCRIPTION_RECONNECTS: IntCounter =
+    register_int_counter!(opts!("tinydancer_slot_subscription_reconnects", "Number of times the slot subscription was re-established")).unwrap();
+    static ref SLOTS_BACKFILLED: IntCounter =
+    register_int_counter!(opts!("tinydancer_slots_backfilled", "Number of slots queued to cover a slot subscription outage")).unwrap();
+}
+
+/// Commitment level(s) slots are sampled at
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum SamplingMode {
+    Processed,
+    Confirmed,
+    Finalized,
+    /// sample at confirmation and again at root, flagging slots whose shreds changed
+    ConfirmedAndFinalized,
+}
+
+impl Default for SamplingMode {
+    fn default() -> Self {
+        SamplingMode::Finalized
+    }
+}
+
+impl SamplingMode {
+    /// Commitment a `slotsUpdatesSubscribe` update is sampled at, if this mode samples it
+    pub fn commitment_for(&self, update_type: &str) -> Option<CommitmentLevel> {
+        match (self, update_type) {
+            (SamplingMode::Processed, "frozen") => Some(CommitmentLevel::Processed),
+            (SamplingMode::Confirmed, "optimisticConfirmation")
+            | (SamplingMode::ConfirmedAndFinalized, "optimisticConfirmation") => {
+                Some(CommitmentLevel::Confirmed)
+            }
+            (SamplingMode::Finalized, "root") | (SamplingMode::ConfirmedAndFinalized, "root") => {
+                Some(CommitmentLevel::Finalized)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A slot that reached `commitment` and should be sampled at it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlotUpdate {
+    pub slot: Slot,
+    pub commitment: CommitmentLevel,
+}
+
+/// Slots the cluster creates, shared with the rpc bridge
+#[derive(Clone, Debug)]
+pub struct SlotStream {
+    updates: broadcast::Sender<SlotInfo>,
+}
+
+impl Default for SlotStream {
+    fn default() -> Self {
+        let (updates, _) = broadcast::channel(SLOT_STREAM_CAPACITY);
+        Self { updates }
+    }
+}
+
+impl SlotStream {
+    pub fn publish(&self, slot: SlotInfo) {
+        // nobody listening is fine
+        let _ = self.updates.send(slot);
+    }
+
+    /// Every slot published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<SlotInfo> {
+        self.updates.subscribe()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum SlotSubscriptionError {
+    #[error("websocket error: {0}")]
+    Websocket(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error("slot queue closed")]
+    QueueClosed(#[from] SlotQueueClosed),
+}
+
+/// Newest slot queued per commitment, plus the commitments that still have to
+/// backfill the gap left by the last reconnect
+#[derive(Default)]
+struct SlotCursor {
+    last_seen: HashMap<CommitmentLevel, Slot>,
+    backfill: HashSet<CommitmentLevel>,
+    /// newest root, reported with every published slot
+    root: Slot,
+}
+
+impl SlotCursor {
+    /// Every commitment seen so far has to cover the gap left by the reconnect
+    fn reconnected(&mut self) {
+        self.backfill = self.last_seen.keys().copied().collect();
+    }
+}
+
+pub async fn slot_update_loop(
+    slot_queue: Arc<SlotQueue>,
+    rpc_url: String,
+    pub_sub: String,
+    status_sampler: Arc<Mutex<ClientStatus>>,
+    mode: SamplingMode,
+    slots: SlotStream,
+) -> anyhow::Result<()> {
+    let rpc_client = JsonRpcClient::new(rpc_url);
+    let mut cursor = SlotCursor::default();
+    let mut backoff = RECONNECT_BACKOFF_INITIAL;
+
+    loop {
+        match subscribe_slots(
+            &rpc_client,
+            &pub_sub,
+            &slot_queue,
+            mode,
+            &slots,
+            &mut cursor,
+            &mut backoff,
+        )
+        .await
+        {
+            Ok(_) => warn!("slot subscription closed by {}", pub_sub),
+            Err(SlotSubscriptionError::QueueClosed(e)) => return Err(e.into()),
+            Err(e) => warn!("slot subscription to {} failed: {}", pub_sub, e),
+        }
+
+        {
+            let mut status = status_sampler.lock().unwrap();
+            *status = ClientStatus::SearchingForRPCService(String::from(
+                "Reconnecting to the slot subscription",
+            ));
+        }
+        info!("reconnecting to {} in {:?}", pub_sub, backoff);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+        SLOT_SUBSCRIPTION_RECONNECTS.inc();
+    }
+}
+
+/// Streams the slots `mode` samples into `slot_queue` until the connection drops
+async fn subscribe_slots(
+    rpc_client: &JsonRpcClient,
+    pub_sub: &str,
+    slot_queue: &SlotQueue,
+    mode: SamplingMode,
+    slots: &SlotStream,
+    cursor: &mut SlotCursor,
+    backoff: &mut Duration,
+) -> Result<(), SlotSubscriptionError> {
+    let (mut socket, _response) = connect_async(pub_sub).await?;
+    socket
+        .send(Message::Text(
+            r#"{ "jsonrpc": "2.0", "id": 1, "method": "slotsUpdatesSubscribe" }"#.into(),
+        ))
+        .await?;
+    info!("subscribed to slot updates on {} ({:?})", pub_sub, mode);
+    *backoff = RECONNECT_BACKOFF_INITIAL;
+
+    cursor.reconnected();
+    seed_root(rpc_client, cursor).await;
+    while let Some(msg) = socket.next().await {
+        match msg? {
+            Message::Text(text) => {
+                if let Ok(res) = serde_json::from_str::<SlotsUpdatesResponse>(&text) {
+                    let update = res.params.result;
+                    publish_slot(&update, cursor, slots);
+                    if let Some(commitment) = mode.commitment_for(&update.update_type) {
+                        enqueue_slot(update.slot, commitment, cursor, slot_queue).await?;
+                    }
+                }
+            }
+            Message::Ping(payload) => socket.send(Message::Pong(payload)).await?,
+            Message::Close(_) => return Ok(()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Newest finalized slot, so banks created before the first root update don't report root 0
+async fn seed_root(rpc_client: &JsonRpcClient, cursor: &mut SlotCursor) {
+    match rpc_client
+        .call::<Slot>(
+            "getSlot",
+            serde_json::json!([{ "commitment": "finalized" }]),
+        )
+        .await
+    {
+        Ok(root) => cursor.root = cursor.root.max(root),
+        Err(e) => warn!(
+            "failed to get the finalized slot from {}: {}",
+            rpc_client.endpoint(),
+            e
+        ),
+    }
+}
+
+fn publish_slot(update: &SlotsUpdatesResult, cursor: &mut SlotCursor, slots: &SlotStream) {
+    match update.update_type.as_str() {
+        "root" => cursor.root = cursor.root.max(update.slot),
+        "createdBank" => slots.publish(SlotInfo {
+            slot: update.slot,
+            parent: update.parent.unwrap_or(update.slot.saturating_sub(1)),
+            root: cursor.root,
+        }),
+        _ => {}
+    }
+}
+
+async fn enqueue_slot(
+    slot: Slot,
+    commitment: CommitmentLevel,
+    cursor: &mut SlotCursor,
+    slot_queue: &SlotQueue,
+) -> Result<(), SlotSubscriptionError> {
+    let last_seen = cursor.last_seen.get(&commitment).copied();
+    // each commitment only moves forward, replays after a reconnect are dropped
+    // without using up the backfill
+    if matches!(last_seen, Some(last) if slot <= last) {
+        return Ok(());
+    }
+    let backfill = cursor.backfill.remove(&commitment);
+    match last_seen {
+        Some(last) if backfill && slot > last + 1 => {
+            let from = (last + 1).max(slot.saturating_sub(MAX_BACKFILL_SLOTS));
+            if from > last + 1 {
+                warn!(
+                    "slot subscription gap too large, not backfilling {:?} slots {}..{}",
+                    commitment,
+                    last + 1,
+                    from
+                );
+            }
+            info!(
+                "backfilling {:?} slots {}..{} missed while reconnecting",
+                commitment, from, slot
+            );
+            for slot in from..slot {
+                slot_queue
+                    .push(QueuedSlot::new(SlotUpdate { slot, commitment }))
+                    .await?;
+            }
+            SLOTS_BACKFILLED.inc_by(slot - from);
+        }
+        _ => {}
+    }
+
+    slot_queue
+        .push(QueuedSlot::new(SlotUpdate { slot, commitment }))
+        .await?;
+    info!("slot updated: {:?} ({:?})", slot, commitment);
+    cursor.last_seen.insert(commitment, slot);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::pipeline::OverflowPolicy;
+
+    fn update(slot: Slot, update_type: &str, parent: Option<Slot>) -> SlotsUpdatesResult {
+        SlotsUpdatesResult {
+            slot,
+            update_type: update_type.to_string(),
+            parent,
+            ..SlotsUpdatesResult::default()
+        }
+    }
+
+    #[test]
+    fn created_banks_are_published_with_the_latest_root() {
+        let slots = SlotStream::default();
+        let mut published = slots.subscribe();
+        let mut cursor = SlotCursor::default();
+
+        publish_slot(&update(90, "root", None), &mut cursor, &slots);
+        publish_slot(&update(100, "frozen", None), &mut cursor, &slots);
+        publish_slot(&update(102, "createdBank", Some(100)), &mut cursor, &slots);
+
+        let slot = published.try_recv().unwrap();
+        assert_eq!((slot.slot, slot.parent, slot.root), (102, 100, 90));
+        assert!(published.try_recv().is_err());
+    }
+
+    async fn queued(queue: &SlotQueue) -> Vec<Slot> {
+        let mut slots = vec![];
+        while !queue.is_empty() {
+            slots.push(queue.pop().await.unwrap().update.slot);
+        }
+        slots
+    }
+
+    #[test]
+    fn sampling_modes_pick_their_updates() {
+        use CommitmentLevel::*;
+        let picked = |mode: SamplingMode| {
+            ["createdBank", "frozen", "optimisticConfirmation", "root"]
+                .into_iter()
+                .filter_map(|update_type| mode.commitment_for(update_type))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(picked(SamplingMode::Processed), vec![Processed]);
+        assert_eq!(picked(SamplingMode::Confirmed), vec![Confirmed]);
+        assert_eq!(picked(SamplingMode::Finalized), vec![Finalized]);
+        assert_eq!(
+            picked(SamplingMode::ConfirmedAndFinalized),
+            vec![Confirmed, Finalized]
+        );
+    }
+
+    #[tokio::test]
+    async fn first_connect_doesnt_backfill() {
+        let queue = SlotQueue::new(64, OverflowPolicy::Block);
+        let mut cursor = SlotCursor::default();
+
+        cursor.reconnected();
+        enqueue_slot(100, CommitmentLevel::Finalized, &mut cursor, &queue)
+            .await
+            .unwrap();
+        assert_eq!(queued(&queue).await, vec![100]);
+    }
+
+    #[tokio::test]
+    async fn backfill_is_capped() {
+        let queue = SlotQueue::new(2 * MAX_BACKFILL_SLOTS as usize, OverflowPolicy::Block);
+        let mut cursor = SlotCursor::default();
+        let finalized = CommitmentLevel::Finalized;
+
+        enqueue_slot(100, finalized, &mut cursor, &queue)
+            .await
+            .unwrap();
+        queued(&queue).await;
+        cursor.reconnected();
+        enqueue_slot(5_000, finalized, &mut cursor, &queue)
+            .await
+            .unwrap();
+
+        let slots = queued(&queue).await;
+        assert_eq!(slots.len() as u64, MAX_BACKFILL_SLOTS + 1);
+        assert_eq!(slots.first(), Some(&(5_000 - MAX_BACKFILL_SLOTS)));
+        assert_eq!(slots.last(), Some(&5_000));
+    }
+
+    #[tokio::test]
+    async fn replays_after_a_reconnect_are_dropped() {
+        let queue = SlotQueue::new(64, OverflowPolicy::Block);
+        let mut cursor = SlotCursor::default();
+        let confirmed = CommitmentLevel::Confirmed;
+
+        enqueue_slot(100, confirmed, &mut cursor, &queue)
+            .await
+            .unwrap();
+        cursor.reconnected();
+        for slot in [99, 100, 103, 103, 104] {
+            enqueue_slot(slot, confirmed, &mut cursor, &queue)
+                .await
+                .unwrap();
+        }
+        assert_eq!(queued(&queue).await, vec![100, 101, 102, 103, 104]);
+
+        // nothing was missed, nothing to backfill
+        cursor.reconnected();
+        enqueue_slot(105, confirmed, &mut cursor, &queue)
+            .await
+            .unwrap();
+        assert_eq!(queued(&queue).await, vec![105]);
+    }
+}
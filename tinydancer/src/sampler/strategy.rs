@@ -0,0 +1,152 @@
+//! Sampling strategy - which shreds of a slot get sampled and how many
+//!
+//! Shreds are drawn without replacement from the combined data + coding index
+//! space of the slot. A leader withholding a fraction `f` of the `N` shreds
+//! goes unnoticed only if every one of the `k` samples lands on a shred that
+//! was published, which happens with probability
+//!
+//!   P(miss) = prod_{i=0}^{k-1} (N - W - i) / (N - i),  W = ceil(f * N)
+//!
+//! so the confidence of a sample is `1 - P(miss)`. An erasure batch can be
+//! recovered from any half of its shreds, so withholding only matters past
+//! ~50% which is why that's the default fraction.
+use rand::seq::index::sample;
+use solana_ledger::shred::ShredType;
+
+pub const DEFAULT_TARGET_CONFIDENCE: f64 = 0.99;
+pub const DEFAULT_WITHHOLDING_FRACTION: f64 = 0.5;
+
+/// A shred index in either the data or the coding index space of a slot
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SampleIndex {
+    pub shred_type: ShredType,
+    pub index: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SamplingStrategy {
+    /// samples taken per slot even if fewer would reach the target confidence
+    pub min_samples: usize,
+    /// probability with which a withholding leader should be caught
+    pub target_confidence: f64,
+    /// fraction of the slot's shreds a leader is assumed to withhold
+    pub withholding_fraction: f64,
+}
+
+impl Default for SamplingStrategy {
+    fn default() -> Self {
+        Self {
+            min_samples: 0,
+            target_confidence: DEFAULT_TARGET_CONFIDENCE,
+            withholding_fraction: DEFAULT_WITHHOLDING_FRACTION,
+        }
+    }
+}
+
+impl SamplingStrategy {
+    pub fn new(min_samples: usize, target_confidence: f64, withholding_fraction: f64) -> Self {
+        Self {
+            min_samples,
+            target_confidence,
+            withholding_fraction,
+        }
+    }
+
+    fn withheld(&self, population: usize) -> usize {
+        ((self.withholding_fraction * population as f64).ceil() as usize).min(population)
+    }
+
+    /// Confidence reached after `samples` distinct shreds out of `population` came back valid
+    pub fn confidence(&self, population: usize, samples: usize) -> f64 {
+        let withheld = self.withheld(population);
+        if population == 0 || withheld == 0 {
+            return 0.0;
+        }
+
+        let mut miss = 1.0;
+        for i in 0..samples.min(population) {
+            if population - i <= withheld {
+                // every shred left is a withheld one
+                return 1.0;
+            }
+            miss *= (population - withheld - i) as f64 / (population - i) as f64;
+        }
+        1.0 - miss
+    }
+
+    /// Number of distinct samples needed to reach the target confidence
+    pub fn required_samples(&self, population: usize) -> usize {
+        (0..=population)
+            .find(|samples| self.confidence(population, *samples) >= self.target_confidence)
+            .unwrap_or(population)
+    }
+
+    /// Number of samples to take from a slot of `population` shreds
+    pub fn sample_count(&self, population: usize) -> usize {
+        self.required_samples(population)
+            .max(self.min_samples)
+            .min(population)
+    }
+
+    /// Picks indices without replacement across both the data and the coding index space
+    pub fn sample_indices(
+        &self,
+        num_data_shreds: usize,
+        num_coding_shreds: usize,
+    ) -> Vec<SampleIndex> {
+        let population = num_data_shreds + num_coding_shreds;
+        let mut rng = rand::thread_rng();
+        sample(&mut rng, population, self.sample_count(population))
+            .into_iter()
+            .map(|i| {
+                if i < num_data_shreds {
+                    SampleIndex {
+                        shred_type: ShredType::Data,
+                        index: i as u32,
+                    }
+                } else {
+                    SampleIndex {
+                        shred_type: ShredType::Code,
+                        index: (i - num_data_shreds) as u32,
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SamplingStrategy;
+    use solana_ledger::shred::ShredType;
+    use std::collections::HashSet;
+
+    #[test]
+    fn confidence_grows_with_samples() {
+        let strategy = SamplingStrategy::default();
+        assert_eq!(strategy.confidence(64, 0), 0.0);
+        assert!(strategy.confidence(64, 4) < strategy.confidence(64, 8));
+        // past the published shreds any further sample has to hit a withheld one
+        assert_eq!(strategy.confidence(64, 33), 1.0);
+    }
+
+    #[test]
+    fn required_samples_reach_target() {
+        let strategy = SamplingStrategy::default();
+        let required = strategy.required_samples(64);
+        assert!(strategy.confidence(64, required) >= strategy.target_confidence);
+        assert!(strategy.confidence(64, required - 1) < strategy.target_confidence);
+    }
+
+    #[test]
+    fn sample_indices_are_distinct_and_in_range() {
+        let strategy = SamplingStrategy::new(40, 0.99, 0.5);
+        let indices = strategy.sample_indices(32, 16);
+        assert_eq!(indices.len(), 40);
+        assert_eq!(indices.iter().collect::<HashSet<_>>().len(), indices.len());
+        assert!(indices.iter().all(|i| match i.shred_type {
+            ShredType::Data => i.index < 32,
+            ShredType::Code => i.index < 16,
+        }));
+    }
+}
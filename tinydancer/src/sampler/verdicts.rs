@@ -0,0 +1,353 @@
+//! Slot verdicts - one record per sampled slot in `VERDICT_CF`
+//!
+//! Keys are the big-endian slot so a range scan over the column family comes
+//! back in slot order.
+//!
+//! `VerdictStore` is how the sampler records verdicts, it also tells anyone
+//! subscribed (the rpc bridge) about every new verdict as it is recorded.
+use crate::sampler::{
+    archive::{sampled_at_key, SAMPLED_AT_CF},
+    SampleVerdict, SlotSample, Verdict,
+};
+use anyhow::anyhow;
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use serde::{Deserialize, Serialize};
+use solana_ledger::shred::ShredType;
+use solana_sdk::{
+    clock::Slot,
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    timing::timestamp,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+pub const VERDICT_CF: &str = "slot_verdicts";
+
+/// Verdicts buffered for subscribers that fall behind
+const VERDICT_UPDATES_CAPACITY: usize = 256;
+
+/// Widest slot range a single query may scan
+pub const MAX_VERDICT_RANGE: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotVerdict {
+    pub slot: Slot,
+    pub requested_data_indices: Vec<usize>,
+    pub requested_coding_indices: Vec<usize>,
+    pub received_data_indices: Vec<u32>,
+    pub received_coding_indices: Vec<u32>,
+    /// shreds that verified against the scheduled leader
+    pub valid: usize,
+    pub invalid: usize,
+    /// why each invalid shred was rejected
+    #[serde(default)]
+    pub rejected: Vec<RejectedShred>,
+    /// scheduled leader
+    pub leader: String,
+    /// leader the shred source claimed, if it named one
+    pub claimed_leader: Option<String>,
+    pub confidence: f64,
+    /// commitment the shreds were requested at
+    #[serde(default)]
+    pub commitment: Option<CommitmentLevel>,
+    /// unix timestamp in ms of when the slot was sampled
+    pub timestamp: u64,
+    pub verdict: Verdict,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectedShred {
+    pub shred_type: ShredType,
+    pub index: u32,
+    pub verdict: SampleVerdict,
+}
+
+impl SlotVerdict {
+    pub fn new(
+        sample: &SlotSample,
+        valid: usize,
+        rejected: Vec<RejectedShred>,
+        confidence: f64,
+        verdict: Verdict,
+    ) -> Self {
+        let received_indices = |shred_type: ShredType| {
+            let mut indices: Vec<u32> = sample
+                .shreds
+                .iter()
+                .flatten()
+                .filter(|s| s.shred_type() == shred_type)
+                .map(|s| s.index())
+                .collect();
+            indices.sort_unstable();
+            indices.dedup();
+            indices
+        };
+
+        Self {
+            slot: sample.slot,
+            requested_data_indices: sample.indices.clone(),
+            requested_coding_indices: sample.coding_indices.clone(),
+            received_data_indices: received_indices(ShredType::Data),
+            received_coding_indices: received_indices(ShredType::Code),
+            valid,
+            invalid: rejected.len(),
+            rejected,
+            leader: sample.leader.to_string(),
+            claimed_leader: sample.claimed_leader.map(|leader| leader.to_string()),
+            confidence,
+            commitment: Some(sample.commitment),
+            timestamp: timestamp(),
+            verdict,
+        }
+    }
+
+    /// Every sampled shred verified and the sample reached `target_confidence`
+    pub fn passed(&self, target_confidence: f64) -> bool {
+        self.verdict == Verdict::Valid && self.confidence >= target_confidence
+    }
+
+    /// True if the slot was sampled at `commitment` or a stronger one, verdicts
+    /// recorded before the commitment was kept only satisfy processed
+    pub fn satisfies(&self, commitment: CommitmentLevel) -> bool {
+        let sampled_at = self.commitment.map_or(0, commitment_rank);
+        sampled_at >= commitment_rank(commitment)
+    }
+}
+
+/// Orders commitment levels from processed to finalized
+fn commitment_rank(commitment: CommitmentLevel) -> u8 {
+    let config = CommitmentConfig { commitment };
+    match (config.is_finalized(), config.is_confirmed()) {
+        (true, _) => 2,
+        (_, true) => 1,
+        _ => 0,
+    }
+}
+
+pub fn verdict_key(slot: Slot) -> [u8; 8] {
+    slot.to_be_bytes()
+}
+
+/// Records the verdict along with its `SAMPLED_AT_CF` entry
+pub fn put_verdict(instance: &DB, verdict: &SlotVerdict) -> anyhow::Result<()> {
+    let cf = instance
+        .cf_handle(VERDICT_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", VERDICT_CF))?;
+    let sampled_at_cf = instance
+        .cf_handle(SAMPLED_AT_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", SAMPLED_AT_CF))?;
+
+    let mut batch = WriteBatch::default();
+    batch.put_cf(cf, verdict_key(verdict.slot), serde_json::to_vec(verdict)?);
+    batch.put_cf(
+        sampled_at_cf,
+        sampled_at_key(verdict.timestamp, verdict.slot),
+        b"",
+    );
+    instance.write(batch)?;
+    Ok(())
+}
+
+pub fn get_verdict(instance: &DB, slot: Slot) -> anyhow::Result<Option<SlotVerdict>> {
+    let cf = instance
+        .cf_handle(VERDICT_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", VERDICT_CF))?;
+    match instance.get_cf(cf, verdict_key(slot))? {
+        Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Verdicts of the sampled slots in `from..=to`, in slot order
+pub fn get_verdicts(instance: &DB, from: Slot, to: Slot) -> anyhow::Result<Vec<SlotVerdict>> {
+    if to < from {
+        return Err(anyhow!("invalid slot range {}..={}", from, to));
+    }
+    if to - from >= MAX_VERDICT_RANGE {
+        return Err(anyhow!(
+            "slot range {}..={} is wider than {} slots",
+            from,
+            to,
+            MAX_VERDICT_RANGE
+        ));
+    }
+
+    let cf = instance
+        .cf_handle(VERDICT_CF)
+        .ok_or_else(|| anyhow!("column family {} not found", VERDICT_CF))?;
+    let start = verdict_key(from);
+    let end = verdict_key(to);
+
+    let mut verdicts = vec![];
+    for item in instance.iterator_cf(cf, IteratorMode::From(&start, Direction::Forward)) {
+        let (key, value) = item?;
+        if key.as_ref() > end.as_slice() {
+            break;
+        }
+        verdicts.push(serde_json::from_slice(&value)?);
+    }
+    Ok(verdicts)
+}
+
+#[derive(Clone)]
+pub struct VerdictStore {
+    instance: Arc<DB>,
+    updates: broadcast::Sender<SlotVerdict>,
+}
+
+impl VerdictStore {
+    pub fn new(instance: Arc<DB>) -> Self {
+        let (updates, _) = broadcast::channel(VERDICT_UPDATES_CAPACITY);
+        Self { instance, updates }
+    }
+
+    pub fn record(&self, verdict: SlotVerdict) -> anyhow::Result<()> {
+        put_verdict(&self.instance, &verdict)?;
+        // nobody listening is fine
+        let _ = self.updates.send(verdict);
+        Ok(())
+    }
+
+    pub fn get(&self, slot: Slot) -> anyhow::Result<Option<SlotVerdict>> {
+        get_verdict(&self.instance, slot)
+    }
+
+    /// Every verdict recorded from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<SlotVerdict> {
+        self.updates.subscribe()
+    }
+
+    /// Verdict of `slot` sampled at `commitment` or a stronger one, waiting up to
+    /// `timeout` for the sampler to record it
+    pub async fn wait_for(
+        &self,
+        slot: Slot,
+        commitment: CommitmentLevel,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<SlotVerdict>> {
+        let satisfying = |verdict: &SlotVerdict| verdict.satisfies(commitment);
+        // subscribed before looking so a verdict recorded in between isn't missed
+        let mut updates = self.subscribe();
+        if let Some(verdict) = self.get(slot)?.filter(satisfying) {
+            return Ok(Some(verdict));
+        }
+
+        let recorded = async {
+            loop {
+                match updates.recv().await {
+                    Ok(verdict) if verdict.slot == slot && satisfying(&verdict) => {
+                        return Ok(Some(verdict))
+                    }
+                    Ok(_) => {}
+                    // the verdict may have been among the skipped ones
+                    Err(RecvError::Lagged(_)) => {
+                        if let Some(verdict) = self.get(slot)?.filter(satisfying) {
+                            return Ok(Some(verdict));
+                        }
+                    }
+                    Err(RecvError::Closed) => return Ok(None),
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, recorded).await {
+            Ok(verdict) => verdict,
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{open_temp_archive, test_verdict};
+
+    #[tokio::test]
+    async fn waiters_get_verdicts_as_they_are_recorded() {
+        let (instance, path) = open_temp_archive("verdicts");
+        let store = VerdictStore::new(Arc::new(instance));
+
+        let recorder = store.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            recorder.record(test_verdict(7)).unwrap();
+            recorder.record(test_verdict(8)).unwrap();
+        });
+
+        let waited = store
+            .wait_for(8, CommitmentLevel::Finalized, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(waited.map(|verdict| verdict.slot), Some(8));
+        assert!(store
+            .wait_for(9, CommitmentLevel::Confirmed, Duration::from_millis(20))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store.get(7).unwrap().unwrap().passed(0.5));
+
+        drop(store);
+        let _ = DB::destroy(&rocksdb::Options::default(), &path);
+    }
+
+    #[test]
+    fn range_queries_come_back_in_slot_order() {
+        let (instance, path) = open_temp_archive("verdict-range");
+        let recorded: Vec<SlotVerdict> = [300, 7, 256, 8].into_iter().map(test_verdict).collect();
+        for verdict in &recorded {
+            put_verdict(&instance, verdict).unwrap();
+        }
+
+        let slots = |from, to| {
+            get_verdicts(&instance, from, to)
+                .unwrap()
+                .into_iter()
+                .map(|verdict| verdict.slot)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(slots(0, 1000), vec![7, 8, 256, 300]);
+        assert_eq!(slots(8, 256), vec![8, 256]);
+        assert!(slots(9, 255).is_empty());
+        assert!(get_verdicts(&instance, 10, 9).is_err());
+        assert!(get_verdicts(&instance, 0, MAX_VERDICT_RANGE).is_err());
+        assert_eq!(
+            get_verdict(&instance, 256).unwrap().as_ref(),
+            Some(&recorded[2])
+        );
+        assert!(get_verdict(&instance, 9).unwrap().is_none());
+
+        drop(instance);
+        let _ = DB::destroy(&rocksdb::Options::default(), &path);
+    }
+
+    #[test]
+    fn weaker_commitments_dont_satisfy_stronger_ones() {
+        let confirmed = SlotVerdict {
+            commitment: Some(CommitmentLevel::Confirmed),
+            ..test_verdict(7)
+        };
+        assert!(confirmed.satisfies(CommitmentLevel::Processed));
+        assert!(confirmed.satisfies(CommitmentLevel::Confirmed));
+        assert!(!confirmed.satisfies(CommitmentLevel::Finalized));
+        assert!(test_verdict(7).satisfies(CommitmentLevel::Finalized));
+
+        let unknown = SlotVerdict {
+            commitment: None,
+            ..test_verdict(7)
+        };
+        assert!(!unknown.satisfies(CommitmentLevel::Confirmed));
+    }
+
+    #[test]
+    fn verdicts_recorded_before_rejections_were_kept_still_read() {
+        let mut value = serde_json::to_value(test_verdict(7)).unwrap();
+        let fields = value.as_object_mut().unwrap();
+        fields.remove("rejected");
+        fields.remove("commitment");
+
+        let read: SlotVerdict = serde_json::from_value(value).unwrap();
+        assert!(read.rejected.is_empty());
+        assert_eq!(read.commitment, None);
+    }
+}
@@ -0,0 +1,401 @@
+//! Service supervisor - runs every client service concurrently
+//!
+//! Each service runs in its own task. A service that fails, or exits while the
+//! client is still running, is started again from its config after a backoff,
+//! until it has failed `max_restarts` times in a row. Past that the failure is
+//! fatal: the shutdown token is cancelled and every other service stops too.
+//! Ctrl-C cancels the same token. Services that don't stop within
+//! `SHUTDOWN_GRACE` of the cancellation are aborted.
+//!
+//! The health of every service is tracked here and summed up into the shared
+//! `ClientStatus`.
+use crate::tinydancer::{ClientService, ClientStatus};
+use anyhow::anyhow;
+use prometheus::{opts, register_int_counter_vec, IntCounterVec};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+use tiny_logger::logs::{error, info, warn};
+use tokio::{
+    sync::watch,
+    task::{JoinError, JoinSet},
+    time::{Duration, Instant},
+};
+
+/// How long services get to stop once shutdown starts
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    static ref SERVICE_RESTARTS: IntCounterVec =
+    register_int_counter_vec!(opts!("tinydancer_service_restarts", "Number of times a client service was restarted after failing"), &["service"]).unwrap();
+}
+
+/// Error a service's `join` ends with
+#[derive(Debug, thiserror::Error)]
+pub enum ServiceError {
+    #[error("service task died: {0}")]
+    Join(#[from] JoinError),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Cancelled once, on Ctrl-C or when a service fails for good, every clone sees it
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    tx: Arc<watch::Sender<bool>>,
+    rx: watch::Receiver<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (tx, rx) = watch::channel(false);
+        Self {
+            tx: Arc::new(tx),
+            rx,
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn cancel(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Resolves once the token is cancelled
+    pub async fn cancelled(&self) {
+        let mut rx = self.rx.clone();
+        while !*rx.borrow_and_update() {
+            if rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RestartPolicy {
+    /// restarts in a row before a failure is fatal, 0 never restarts
+    pub max_restarts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// a service that ran this long before failing starts counting restarts from 0 again
+    pub reset_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 3,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            reset_after: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Delay before restart number `restart` (1 based)
+    fn delay(&self, restart: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(restart.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "state")]
+pub enum ServiceHealth {
+    Running,
+    Restarting { restarts: u32, error: String },
+    Failed { error: String },
+    Stopped,
+}
+
+type HealthMap = Arc<Mutex<BTreeMap<&'static str, ServiceHealth>>>;
+
+/// What the client status should read given the health of every service, `None` leaves
+/// it to the services
+fn summarize(
+    health: &BTreeMap<&'static str, ServiceHealth>,
+    max_restarts: u32,
+) -> Option<ClientStatus> {
+    if let Some((name, ServiceHealth::Failed { error })) = health
+        .iter()
+        .find(|(_, health)| matches!(health, ServiceHealth::Failed { .. }))
+    {
+        return Some(ClientStatus::Crashed(format!("{} failed: {}", name, error)));
+    }
+    let restarting: Vec<String> = health
+        .iter()
+        .filter_map(|(name, health)| match health {
+            ServiceHealth::Restarting { restarts, error } => Some(format!(
+                "{} restarting ({}/{}): {}",
+                name, restarts, max_restarts, error
+            )),
+            _ => None,
+        })
+        .collect();
+    if !restarting.is_empty() {
+        return Some(ClientStatus::Degraded(restarting.join(", ")));
+    }
+    None
+}
+
+pub struct Supervisor {
+    shutdown: Shutdown,
+    policy: RestartPolicy,
+    client_status: Arc<Mutex<ClientStatus>>,
+    health: HealthMap,
+    services: JoinSet<anyhow::Result<()>>,
+}
+
+impl Supervisor {
+    pub fn new(
+        shutdown: Shutdown,
+        policy: RestartPolicy,
+        client_status: Arc<Mutex<ClientStatus>>,
+    ) -> Self {
+        Self {
+            shutdown,
+            policy,
+            client_status,
+            health: HealthMap::default(),
+            services: JoinSet::new(),
+        }
+    }
+
+    /// Health of every supervised service
+    pub fn health(&self) -> BTreeMap<&'static str, ServiceHealth> {
+        self.health.lock().unwrap().clone()
+    }
+
+    /// Starts `S` from `config`, and again from a clone of it every time it fails
+    pub fn supervise<S, C>(&mut self, name: &'static str, config: C)
+    where
+        S: ClientService<C> + Send + 'static,
+        S::ServiceError: Send + 'static,
+        C: Clone + Send + 'static,
+    {
+        let shutdown = self.shutdown.clone();
+        let policy = self.policy;
+        let client_status = self.client_status.clone();
+        let health = self.health.clone();
+        let set_health = move |state: ServiceHealth| {
+            let mut health = health.lock().unwrap();
+            health.insert(name, state);
+            let mut client_status = client_status.lock().unwrap();
+            match summarize(&health, policy.max_restarts) {
+                Some(status) => *client_status = status,
+                None if matches!(&*client_status, ClientStatus::Degraded(_)) => {
+                    *client_status = ClientStatus::Active(String::from(
+                        "Monitoring Tinydancer: every service recovered",
+                    ))
+                }
+                None => {}
+            }
+        };
+
+        self.services.spawn(async move {
+            let mut restarts = 0;
+            loop {
+                set_health(ServiceHealth::Running);
+                let started = Instant::now();
+                let error = match S::new(config.clone()).join().await {
+                    _ if shutdown.is_cancelled() => {
+                        set_health(ServiceHealth::Stopped);
+                        return Ok(());
+                    }
+                    Ok(()) => String::from("exited unexpectedly"),
+                    Err(e) => e.to_string(),
+                };
+
+                if started.elapsed() >= policy.reset_after {
+                    restarts = 0;
+                }
+                if restarts >= policy.max_restarts {
+                    error!("{} failed for good: {}", name, error);
+                    set_health(ServiceHealth::Failed {
+                        error: error.clone(),
+                    });
+                    shutdown.cancel();
+                    return Err(anyhow!("{} failed: {}", name, error));
+                }
+
+                restarts += 1;
+                let delay = policy.delay(restarts);
+                warn!(
+                    "{} failed, restart {}/{} in {:?}: {}",
+                    name, restarts, policy.max_restarts, delay, error
+                );
+                SERVICE_RESTARTS.with_label_values(&[name]).inc();
+                set_health(ServiceHealth::Restarting { restarts, error });
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.cancelled() => {
+                        set_health(ServiceHealth::Stopped);
+                        return Ok(());
+                    }
+                }
+            }
+        });
+    }
+
+    /// Runs until every service stopped, errors if one of them failed for good
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        let shutdown = self.shutdown.clone();
+        let client_status = self.client_status.clone();
+        let ctrl_c = tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {
+                    info!("Received ctrl+c signal");
+                    *client_status.lock().unwrap() =
+                        ClientStatus::ShuttingDown(String::from("Shutting Down Gracefully..."));
+                    shutdown.cancel();
+                }
+                _ = shutdown.cancelled() => {}
+            }
+        });
+
+        let shutdown = self.shutdown.clone();
+        let grace = async {
+            shutdown.cancelled().await;
+            tokio::time::sleep(SHUTDOWN_GRACE).await;
+        };
+        tokio::pin!(grace);
+
+        let mut failure = None;
+        loop {
+            tokio::select! {
+                joined = self.services.join_next() => match joined {
+                    None => break,
+                    Some(Ok(Ok(()))) => {}
+                    Some(Ok(Err(e))) => {
+                        failure.get_or_insert(e);
+                    }
+                    Some(Err(e)) => {
+                        shutdown.cancel();
+                        failure.get_or_insert(anyhow!("supervisor task died: {}", e));
+                    }
+                },
+                _ = &mut grace => {
+                    warn!("services didn't stop within {:?}, aborting them", SHUTDOWN_GRACE);
+                    self.services.shutdown().await;
+                    break;
+                }
+            }
+        }
+        ctrl_c.abort();
+
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails `fail_times` times, then runs until shutdown
+    struct Flaky {
+        result: std::result::Result<(), ServiceError>,
+        shutdown: Shutdown,
+    }
+
+    #[derive(Clone)]
+    struct FlakyConfig {
+        starts: Arc<AtomicU32>,
+        fail_times: u32,
+        shutdown: Shutdown,
+    }
+
+    #[async_trait]
+    impl ClientService<FlakyConfig> for Flaky {
+        type ServiceError = ServiceError;
+
+        fn new(config: FlakyConfig) -> Self {
+            let start = config.starts.fetch_add(1, Ordering::SeqCst);
+            let result = if start < config.fail_times {
+                Err(anyhow!("start {} failed", start).into())
+            } else {
+                Ok(())
+            };
+            Self {
+                result,
+                shutdown: config.shutdown,
+            }
+        }
+
+        async fn join(self) -> std::result::Result<(), ServiceError> {
+            self.result?;
+            self.shutdown.cancelled().await;
+            Ok(())
+        }
+    }
+
+    fn policy(max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            reset_after: Duration::from_secs(60),
+        }
+    }
+
+    #[tokio::test]
+    async fn restarts_until_the_policy_gives_up() {
+        let shutdown = Shutdown::default();
+        let status = Arc::new(Mutex::new(ClientStatus::Initializing(String::new())));
+        let starts = Arc::new(AtomicU32::new(0));
+        let mut supervisor = Supervisor::new(shutdown.clone(), policy(2), status.clone());
+        supervisor.supervise::<Flaky, _>(
+            "flaky",
+            FlakyConfig {
+                starts: starts.clone(),
+                fail_times: u32::MAX,
+                shutdown: shutdown.clone(),
+            },
+        );
+
+        assert!(supervisor.run().await.is_err());
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+        assert!(shutdown.is_cancelled());
+        assert!(matches!(&*status.lock().unwrap(), ClientStatus::Crashed(_)));
+    }
+
+    #[tokio::test]
+    async fn stops_cleanly_once_cancelled() {
+        let shutdown = Shutdown::default();
+        let status = Arc::new(Mutex::new(ClientStatus::Initializing(String::new())));
+        let starts = Arc::new(AtomicU32::new(0));
+        let mut supervisor = Supervisor::new(shutdown.clone(), policy(5), status);
+        supervisor.supervise::<Flaky, _>(
+            "flaky",
+            FlakyConfig {
+                starts: starts.clone(),
+                fail_times: 1,
+                shutdown: shutdown.clone(),
+            },
+        );
+        shutdown.cancel();
+
+        assert!(supervisor.run().await.is_ok());
+    }
+
+    #[test]
+    fn restart_backoff_is_capped() {
+        let policy = RestartPolicy::default();
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(3), Duration::from_secs(4));
+        assert_eq!(policy.delay(20), policy.max_backoff);
+    }
+}
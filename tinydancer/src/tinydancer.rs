@@ -9,9 +9,18 @@ use std::{
 
 // use tokio::time::Duration;
 use crate::{
-    block_on,
-    rpc_wrapper::{TransactionService, TransactionServiceConfig},
-    sampler::{ArchiveConfig, SampleService, SampleServiceConfig, SHRED_CF},
+    rpc_wrapper::{BridgeConfig, TransactionService, TransactionServiceConfig},
+    sampler::{
+        archive::{migrate_legacy_keys, ARCHIVE_CFS},
+        leader_schedule::LeaderScheduleCache,
+        pipeline::PipelineConfig,
+        shred_source::{build_shred_sources, RpcProviders, ShredSourceKind},
+        slot_updates::{SamplingMode, SlotStream},
+        strategy::SamplingStrategy,
+        verdicts::VerdictStore,
+        ArchiveConfig, SampleService, SampleServiceConfig,
+    },
+    supervisor::{RestartPolicy, Shutdown, Supervisor},
     ui::{UiConfig, UiService},
 };
 use anyhow::anyhow;
@@ -23,7 +32,7 @@ use tiny_logger::logs::info;
 // use log::info;
 // use log4rs;
 use std::error::Error;
-use tokio::{runtime::Runtime, task::JoinError, try_join};
+use tokio::{task::JoinError, try_join};
 // use std::{thread, thread::JoinHandle, time::Duration};
 
 #[async_trait]
@@ -34,22 +43,32 @@ pub trait ClientService<T> {
     async fn join(self) -> std::result::Result<(), Self::ServiceError>;
 }
 
-pub struct TinyDancer {
-    sample_service: SampleService,
-    ui_service: Option<UiService>,
-    sample_qty: u64,
-    config: TinyDancerConfig,
-    transaction_service: TransactionService,
-}
+pub struct TinyDancer;
 
 #[derive(Clone)]
 pub struct TinyDancerConfig {
     pub rpc_endpoint: Cluster,
+    /// minimum amount of shreds sampled per slot
     pub sample_qty: usize,
+    pub target_confidence: f64,
+    pub withholding_fraction: f64,
     pub enable_ui_service: bool,
     pub archive_config: ArchiveConfig,
     pub tui_monitor: bool,
     pub log_path: String,
+    pub shred_sources: Vec<ShredSourceKind>,
+    pub gossip_entrypoint: Option<SocketAddr>,
+    /// Where the leader schedule is fetched from, defaults to `rpc_endpoint`
+    pub leader_schedule_endpoint: Option<String>,
+    pub sampling_mode: SamplingMode,
+    /// Rpc providers sampled alongside `rpc_endpoint`
+    pub rpc_endpoints: Vec<String>,
+    /// How many providers each sampled shred is requested from
+    pub provider_overlap: usize,
+    pub pipeline: PipelineConfig,
+    pub bridge: BridgeConfig,
+    /// how often a failed service is started again before the client gives up
+    pub restart_policy: RestartPolicy,
 }
 
 use solana_metrics::datapoint_info;
@@ -57,10 +76,11 @@ use std::ffi::OsString;
 use std::fs::read_dir;
 use std::io;
 use std::io::ErrorKind;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
 impl TinyDancer {
-    pub async fn start(config: TinyDancerConfig) -> Result<()> {
+    pub async fn start(config: TinyDancerConfig) -> anyhow::Result<()> {
         let status = ClientStatus::Initializing(String::from("Starting Up Tinydancer"));
 
         let client_status = Arc::new(Mutex::new(status));
@@ -70,10 +90,21 @@ impl TinyDancer {
             enable_ui_service,
             rpc_endpoint,
             sample_qty,
+            target_confidence,
+            withholding_fraction,
             tui_monitor,
             log_path,
             archive_config,
-        } = config.clone();
+            shred_sources,
+            gossip_entrypoint,
+            leader_schedule_endpoint,
+            sampling_mode,
+            rpc_endpoints,
+            provider_overlap,
+            pipeline,
+            bridge,
+            restart_policy,
+        } = config;
         std::env::set_var("RUST_LOG", "info");
         tiny_logger::setup_file_with_default(&log_path, "RUST_LOG");
 
@@ -83,50 +114,91 @@ impl TinyDancer {
         opts.create_missing_column_families(true);
 
         // setup db
-        let db = rocksdb::DB::open_cf(&opts, archive_config.clone().archive_path, vec![SHRED_CF])
-            .unwrap();
+        let db =
+            rocksdb::DB::open_cf(&opts, archive_config.clone().archive_path, ARCHIVE_CFS).unwrap();
         let db = Arc::new(db);
 
-        let sample_service_config = SampleServiceConfig {
-            cluster: rpc_endpoint.clone(),
-            archive_config,
-            instance: db.clone(),
-            status_sampler,
-            sample_qty,
-        };
-        let sample_service = SampleService::new(sample_service_config);
-
-        let transaction_service = TransactionService::new(TransactionServiceConfig {
-            cluster: rpc_endpoint.clone(),
-            db_instance: db.clone(),
-        });
-
-        let ui_service = if enable_ui_service || tui_monitor {
-            Some(UiService::new(UiConfig {
-                client_status,
-                enable_ui_service,
-                tui_monitor,
-            }))
-        } else {
-            None
-        };
-
-        // run
-        sample_service
-            .join()
-            .await
-            .expect("error in sample service thread");
-
-        transaction_service
-            .join()
-            .await
-            .expect("ERROR IN SIMPLE PAYMENT SERVICE");
-
-        if let Some(ui_service) = ui_service {
-            block_on!(async { ui_service.join().await }, "Ui Service Error");
+        match migrate_legacy_keys(&db) {
+            Ok(0) => {}
+            Ok(migrated) => info!(
+                "migrated {} shreds to the slot ordered archive keys",
+                migrated
+            ),
+            Err(e) => return Err(anyhow!("error migrating archive keys: {}", e)),
+        }
+
+        let rpc_providers = RpcProviders::new(
+            endpoint(rpc_endpoint.clone()),
+            rpc_endpoints,
+            provider_overlap,
+        );
+        let provider_reputations = rpc_providers.reputations.clone();
+        let leader_schedule = Arc::new(LeaderScheduleCache::new(
+            leader_schedule_endpoint,
+            &rpc_providers.endpoints,
+        ));
+        let shred_sources = build_shred_sources(
+            &shred_sources,
+            rpc_providers,
+            Some(db.clone()),
+            gossip_entrypoint,
+        )?;
+
+        let verdicts = VerdictStore::new(db.clone());
+        let slots = SlotStream::default();
+        let strategy = SamplingStrategy::new(sample_qty, target_confidence, withholding_fraction);
+
+        let shutdown = Shutdown::default();
+        let mut supervisor =
+            Supervisor::new(shutdown.clone(), restart_policy, client_status.clone());
+
+        supervisor.supervise::<SampleService, _>(
+            "sampler",
+            SampleServiceConfig {
+                cluster: rpc_endpoint.clone(),
+                archive_config,
+                instance: db.clone(),
+                verdicts: verdicts.clone(),
+                status_sampler,
+                strategy,
+                mode: sampling_mode,
+                shred_sources: shred_sources.clone(),
+                leader_schedule: leader_schedule.clone(),
+                pipeline,
+                slots: slots.clone(),
+                shutdown: shutdown.clone(),
+            },
+        );
+
+        supervisor.supervise::<TransactionService, _>(
+            "transactions",
+            TransactionServiceConfig {
+                cluster: rpc_endpoint.clone(),
+                db_instance: db.clone(),
+                leader_schedule,
+                provider_reputations,
+                verdicts,
+                slots,
+                shred_sources,
+                strategy,
+                bridge,
+                shutdown: shutdown.clone(),
+            },
+        );
+
+        if enable_ui_service || tui_monitor {
+            supervisor.supervise::<UiService, _>(
+                "ui",
+                UiConfig {
+                    client_status,
+                    enable_ui_service,
+                    tui_monitor,
+                    shutdown,
+                },
+            );
         }
 
-        Ok(())
+        supervisor.run().await
     }
 }
 
@@ -151,6 +223,8 @@ pub enum ClientStatus {
     Initializing(String),
     SearchingForRPCService(String),
     Active(String),
+    /// running, but some services are being restarted
+    Degraded(String),
     Crashed(String),
     ShuttingDown(String),
 }
@@ -1,4 +1,5 @@
 use crate::sampler::GetShredResponse;
+use crate::supervisor::Shutdown;
 use crate::tinydancer::{ClientService, ClientStatus, TinyDancer};
 use async_trait::async_trait;
 use crossterm::event::{KeyEvent, KeyEventKind, KeyEventState, KeyModifiers};
@@ -110,10 +111,12 @@ impl TabsState {
         }
     }
 }
+#[derive(Clone)]
 pub struct UiConfig {
     pub client_status: Arc<Mutex<ClientStatus>>,
     pub enable_ui_service: bool,
     pub tui_monitor: bool,
+    pub shutdown: Shutdown,
 }
 // main draw function
 pub fn draw<B: Backend>(f: &mut Frame<B>) {
@@ -208,78 +211,93 @@ fn draw_slot_list<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
 impl ClientService<UiConfig> for UiService {
     type ServiceError = ThreadJoinError;
     fn new(config: UiConfig) -> Self {
-        let ui_service_handle = std::thread::spawn(move || loop {
-            let mut threads = Vec::default();
+        let ui_service_handle = std::thread::spawn(move || {
+            while !config.shutdown.is_cancelled() {
+                let mut threads = Vec::default();
 
-            if config.enable_ui_service {
-                threads.push(std::thread::spawn(|| {
-                    info!("rendering ui");
-                    std::thread::sleep(std::time::Duration::from_secs(2));
-                }));
-            }
-
-            if config.tui_monitor {
-                let client_status = config.client_status.clone();
-                let mut spinner =
-                    Spinner::new(spinners::Dots, "Initializing Client...", SpinColor::Yellow);
+                if config.enable_ui_service {
+                    threads.push(std::thread::spawn(|| {
+                        info!("rendering ui");
+                        std::thread::sleep(std::time::Duration::from_secs(2));
+                    }));
+                }
 
-                threads.push(std::thread::spawn(move || loop {
-                    sleep(Duration::from_millis(100));
+                if config.tui_monitor {
+                    let client_status = config.client_status.clone();
+                    let shutdown = config.shutdown.clone();
+                    let mut spinner =
+                        Spinner::new(spinners::Dots, "Initializing Client...", SpinColor::Yellow);
 
-                    let status = client_status.lock().unwrap();
-                    match &*status {
-                        ClientStatus::Active(msg) => {
-                            spinner.update(spinners::Dots, msg.clone(), SpinColor::Green);
-                            // sleep(Duration::from_secs(100));
-                        }
-                        ClientStatus::Initializing(msg) => {
-                            spinner.update(spinners::Dots, msg.clone(), SpinColor::Yellow);
-                        }
-                        ClientStatus::Crashed(msg) => {
-                            spinner.update(spinners::Dots, msg.clone(), SpinColor::Red);
+                    threads.push(std::thread::spawn(move || loop {
+                        sleep(Duration::from_millis(100));
+                        if shutdown.is_cancelled() {
+                            disable_raw_mode();
+                            spinner.stop_with_message("Tinydancer stopped");
+                            break;
                         }
-                        ClientStatus::ShuttingDown(msg) => {
-                            spinner.update(spinners::Dots, msg.clone(), SpinColor::White);
-                            sleep(Duration::from_millis(500));
-                            std::process::exit(0);
+
+                        let status = client_status.lock().unwrap();
+                        match &*status {
+                            ClientStatus::Active(msg) => {
+                                spinner.update(spinners::Dots, msg.clone(), SpinColor::Green);
+                                // sleep(Duration::from_secs(100));
+                            }
+                            ClientStatus::Initializing(msg) => {
+                                spinner.update(spinners::Dots, msg.clone(), SpinColor::Yellow);
+                            }
+                            ClientStatus::Degraded(msg) => {
+                                spinner.update(spinners::Dots, msg.clone(), SpinColor::Yellow);
+                            }
+                            ClientStatus::Crashed(msg) => {
+                                spinner.update(spinners::Dots, msg.clone(), SpinColor::Red);
+                            }
+                            ClientStatus::ShuttingDown(msg) => {
+                                spinner.update(spinners::Dots, msg.clone(), SpinColor::White);
+                            }
+                            _ => {}
                         }
-                        _ => {}
-                    }
-                    Mutex::unlock(status);
-                    enable_raw_mode();
-                    if crossterm::event::poll(Duration::from_millis(100)).unwrap() {
-                        let ev = crossterm::event::read().unwrap();
+                        Mutex::unlock(status);
+                        enable_raw_mode();
+                        if crossterm::event::poll(Duration::from_millis(100)).unwrap() {
+                            let ev = crossterm::event::read().unwrap();
 
-                        if ev
-                            == Event::Key(KeyEvent {
-                                code: KeyCode::Char('c'),
-                                modifiers: KeyModifiers::CONTROL,
-                                kind: KeyEventKind::Press,
-                                state: KeyEventState::NONE,
-                            })
-                        {
-                            let mut status = client_status.lock().unwrap();
-                            *status = ClientStatus::ShuttingDown(String::from(
-                                "Shutting Down Gracefully...",
-                            ));
-                            Mutex::unlock(status);
-                            disable_raw_mode();
+                            if ev
+                                == Event::Key(KeyEvent {
+                                    code: KeyCode::Char('c'),
+                                    modifiers: KeyModifiers::CONTROL,
+                                    kind: KeyEventKind::Press,
+                                    state: KeyEventState::NONE,
+                                })
+                            {
+                                let mut status = client_status.lock().unwrap();
+                                *status = ClientStatus::ShuttingDown(String::from(
+                                    "Shutting Down Gracefully...",
+                                ));
+                                Mutex::unlock(status);
+                                // raw mode swallows the signal, stop the client ourselves
+                                shutdown.cancel();
+                            }
                         }
-                    }
-                }));
-            }
+                    }));
+                }
 
-            for handle in threads {
-                handle.join();
+                for handle in threads {
+                    handle.join();
+                }
             }
         });
 
         Self { ui_service_handle }
     }
     async fn join(self) -> std::result::Result<(), Self::ServiceError> {
-        match self.ui_service_handle.join() {
-            Ok(_) => Ok(()),
-            Err(error) => Err(ThreadJoinError { error }),
+        // the ui runs on plain threads, don't block the runtime waiting for them
+        let handle = self.ui_service_handle;
+        match tokio::task::spawn_blocking(move || handle.join()).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(error)) => Err(ThreadJoinError { error }),
+            Err(error) => Err(ThreadJoinError {
+                error: Box::new(error),
+            }),
         }
     }
 }
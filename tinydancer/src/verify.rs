@@ -0,0 +1,526 @@
+//! `tinydancer verify` - verifies a slot, a range of slots or new slots as the
+//! cluster produces them
+//!
+//! Slots the cluster skipped are looked up through `getBlocks` and reported as
+//! skipped rather than failed. Every other slot either passes (valid and at the
+//! target confidence) or counts as a failure, the command exits non-zero if any
+//! slot failed.
+use crate::sampler::{
+    leader_schedule::LeaderScheduleCache, rpc_client::JsonRpcClient, shred_source::ShredSource,
+    strategy::SamplingStrategy, verdicts::SlotVerdict, verify_slot_from_sources, Verdict,
+};
+use anyhow::Result;
+use colored::Colorize;
+use serde::Serialize;
+use solana_ledger::shred::ShredType;
+use solana_sdk::{clock::Slot, commitment_config::CommitmentLevel};
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    sync::Arc,
+    time::Duration,
+};
+use tiny_logger::logs::warn;
+
+/// How often `--follow` asks the cluster for new slots
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(400);
+
+const CSV_HEADER: &str = "slot,status,verdict,confidence,valid,invalid,leader,claimedLeader,commitment,receivedDataIndices,receivedCodingIndices,rejected,error";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum VerifyOutput {
+    Text,
+    /// one JSON object per line
+    Json,
+    Csv,
+}
+
+/// Outcome of verifying a single slot
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase", tag = "status")]
+pub enum SlotCheck {
+    Sampled(SlotVerdict),
+    /// the cluster produced no block for the slot
+    Skipped {
+        slot: Slot,
+    },
+    /// the slot couldn't be sampled at all
+    Failed {
+        slot: Slot,
+        error: String,
+    },
+}
+
+impl SlotCheck {
+    fn passed(&self, target_confidence: f64) -> bool {
+        match self {
+            SlotCheck::Sampled(verdict) => verdict.passed(target_confidence),
+            SlotCheck::Skipped { .. } => true,
+            SlotCheck::Failed { .. } => false,
+        }
+    }
+}
+
+pub struct SlotVerifier {
+    pub rpc_client: JsonRpcClient,
+    pub sources: Vec<Arc<dyn ShredSource>>,
+    pub strategy: SamplingStrategy,
+    pub leader_schedule: LeaderScheduleCache,
+    pub commitment: CommitmentLevel,
+    pub deep: bool,
+    pub output: VerifyOutput,
+    /// slots verified so far, skipped ones included
+    pub checked: usize,
+    pub failed: usize,
+}
+
+impl SlotVerifier {
+    /// Commitment slots and blocks are looked up at, `getBlocks` doesn't support processed
+    fn block_commitment(&self) -> CommitmentLevel {
+        match self.commitment {
+            CommitmentLevel::Processed => CommitmentLevel::Confirmed,
+            commitment => commitment,
+        }
+    }
+
+    /// Verifies every slot in `from..=to`
+    pub async fn verify_range(&mut self, from: Slot, to: Slot) -> Result<()> {
+        if self.output == VerifyOutput::Csv && self.checked == 0 {
+            println!("{}", CSV_HEADER);
+        }
+
+        let blocks: Option<HashSet<Slot>> = match self
+            .rpc_client
+            .call::<Vec<Slot>>(
+                "getBlocks",
+                serde_json::json!([from, to, { "commitment": self.block_commitment() }]),
+            )
+            .await
+        {
+            Ok(blocks) => Some(blocks.into_iter().collect()),
+            Err(e) => {
+                warn!(
+                    "couldn't tell skipped slots apart in {}..={}: {}",
+                    from, to, e
+                );
+                None
+            }
+        };
+
+        for slot in from..=to {
+            let check = match &blocks {
+                Some(blocks) if !blocks.contains(&slot) => SlotCheck::Skipped { slot },
+                _ => match verify_slot_from_sources(
+                    slot,
+                    &self.sources,
+                    &self.strategy,
+                    &self.leader_schedule,
+                    self.commitment,
+                    self.deep,
+                )
+                .await
+                {
+                    Ok(verdict) => SlotCheck::Sampled(verdict),
+                    Err(e) => SlotCheck::Failed {
+                        slot,
+                        error: e.to_string(),
+                    },
+                },
+            };
+
+            self.checked += 1;
+            if !check.passed(self.strategy.target_confidence) {
+                self.failed += 1;
+            }
+            self.print(&check)?;
+        }
+        Ok(())
+    }
+
+    /// Verifies new slots as they reach the commitment, from `from` (or the current slot)
+    /// until `to` if there is one
+    pub async fn follow(&mut self, from: Option<Slot>, to: Option<Slot>) -> Result<()> {
+        let mut next = match from {
+            Some(from) => from,
+            None => self.current_slot().await?,
+        };
+        while to.map_or(true, |to| next <= to) {
+            // a flaky rpc shouldn't end a long running follow, the tip is polled again
+            let tip = match self.current_slot().await {
+                Ok(tip) => tip,
+                Err(e) => {
+                    warn!("couldn't get the current slot: {}", e);
+                    tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+                    continue;
+                }
+            };
+            let end = to.map_or(tip, |to| to.min(tip));
+            if next <= end {
+                self.verify_range(next, end).await?;
+                next = end + 1;
+            }
+            tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    async fn current_slot(&self) -> Result<Slot> {
+        Ok(self
+            .rpc_client
+            .call(
+                "getSlot",
+                serde_json::json!([{ "commitment": self.block_commitment() }]),
+            )
+            .await?)
+    }
+
+    fn print(&self, check: &SlotCheck) -> Result<()> {
+        match self.output {
+            VerifyOutput::Text => print_text(check, self.strategy.target_confidence),
+            VerifyOutput::Json => println!("{}", serde_json::to_string(check)?),
+            VerifyOutput::Csv => println!("{}", csv_record(check)),
+        }
+        // records are consumed as they come when following the cluster
+        io::stdout().flush()?;
+        Ok(())
+    }
+}
+
+fn print_text(check: &SlotCheck, target_confidence: f64) {
+    let verdict = match check {
+        SlotCheck::Sampled(verdict) => verdict,
+        SlotCheck::Skipped { slot } => {
+            println!(
+                "\nSlot {} was skipped, the cluster produced no block for it",
+                slot.to_string().yellow()
+            );
+            return;
+        }
+        SlotCheck::Failed { slot, error } => {
+            println!(
+                "\nSlot {} could not be sampled: {}",
+                slot.to_string().yellow(),
+                error.to_string().red()
+            );
+            return;
+        }
+    };
+
+    let slot = verdict.slot.to_string().yellow();
+    match verdict.verdict {
+        Verdict::WrongLeader => println!(
+            "\nSlot {} is not {}, shreds were not signed by the scheduled leader ❌",
+            slot,
+            "Valid".to_string().red()
+        ),
+        Verdict::Unrecoverable => println!(
+            "\nSlot {} is not {}, its FEC sets could not be recovered ❌",
+            slot,
+            "Valid".to_string().red()
+        ),
+        Verdict::Valid if verdict.confidence < target_confidence => println!(
+            "\nSlot {} is {} but only reached {:.4} confidence (target {})",
+            slot,
+            "Valid".to_string().green(),
+            verdict.confidence,
+            target_confidence
+        ),
+        Verdict::Valid => println!(
+            "\nSlot {} is {} ✓ (confidence {:.4})",
+            slot,
+            "Valid".to_string().green(),
+            verdict.confidence
+        ),
+        Verdict::Invalid => {
+            println!("\nSlot {} is not {} ❌", slot, "Valid".to_string().red());
+            for rejected in &verdict.rejected {
+                println!(
+                    "  {} shred {} {:?}",
+                    shred_type_name(rejected.shred_type),
+                    rejected.index,
+                    rejected.verdict
+                );
+            }
+        }
+    }
+}
+
+fn shred_type_name(shred_type: ShredType) -> &'static str {
+    match shred_type {
+        ShredType::Data => "data",
+        ShredType::Code => "code",
+    }
+}
+
+/// Name a value serializes to, for the enums that serialize as plain strings
+fn serde_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| matches!(c, ',' | '"' | '\n')) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn join_indices(indices: &[u32]) -> String {
+    indices
+        .iter()
+        .map(|index| index.to_string())
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn csv_record(check: &SlotCheck) -> String {
+    let fields: Vec<String> = match check {
+        SlotCheck::Sampled(verdict) => vec![
+            verdict.slot.to_string(),
+            String::from("sampled"),
+            serde_name(&verdict.verdict),
+            format!("{:.4}", verdict.confidence),
+            verdict.valid.to_string(),
+            verdict.invalid.to_string(),
+            verdict.leader.clone(),
+            verdict.claimed_leader.clone().unwrap_or_default(),
+            verdict
+                .commitment
+                .map(|commitment| serde_name(&commitment))
+                .unwrap_or_default(),
+            join_indices(&verdict.received_data_indices),
+            join_indices(&verdict.received_coding_indices),
+            verdict
+                .rejected
+                .iter()
+                .map(|rejected| {
+                    format!(
+                        "{}:{}:{}",
+                        shred_type_name(rejected.shred_type),
+                        rejected.index,
+                        serde_name(&rejected.verdict)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(";"),
+            String::new(),
+        ],
+        SlotCheck::Skipped { slot } => {
+            let mut fields = vec![slot.to_string(), String::from("skipped")];
+            fields.resize(13, String::new());
+            fields
+        }
+        SlotCheck::Failed { slot, error } => {
+            let mut fields = vec![slot.to_string(), String::from("failed")];
+            fields.resize(12, String::new());
+            fields.push(error.clone());
+            fields
+        }
+    };
+    fields
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{
+        rpc_client::RetryConfig, shred_source::SourceShreds, test_verdict, verdicts::RejectedShred,
+        SampleVerdict,
+    };
+    use async_trait::async_trait;
+    use jsonrpsee::{
+        server::{ServerBuilder, ServerHandle},
+        RpcModule,
+    };
+
+    /// Source that never serves the slot
+    struct Unavailable;
+
+    #[async_trait]
+    impl ShredSource for Unavailable {
+        fn name(&self) -> String {
+            String::from("unavailable")
+        }
+
+        async fn get_shreds(
+            &self,
+            slot: Slot,
+            _indices: &[usize],
+            _commitment: CommitmentLevel,
+        ) -> anyhow::Result<SourceShreds> {
+            Err(anyhow::anyhow!("slot {} not available", slot))
+        }
+
+        async fn get_coding_shreds(
+            &self,
+            slot: Slot,
+            _indices: &[usize],
+            _commitment: CommitmentLevel,
+        ) -> anyhow::Result<SourceShreds> {
+            Err(anyhow::anyhow!("slot {} not available", slot))
+        }
+    }
+
+    /// Rpc at slot `tip` that produced a block for each of `blocks`
+    async fn cluster(tip: Slot, blocks: Vec<Slot>) -> (String, ServerHandle) {
+        let mut module = RpcModule::new(());
+        module
+            .register_method("getSlot", move |_, _| Ok(tip))
+            .unwrap();
+        module
+            .register_method("getBlocks", move |params, _| {
+                let (from, to, _): (Slot, Slot, serde_json::Value) = params.parse()?;
+                Ok(blocks
+                    .iter()
+                    .copied()
+                    .filter(|slot| (from..=to).contains(slot))
+                    .collect::<Vec<_>>())
+            })
+            .unwrap();
+        let server = ServerBuilder::default().build("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", server.local_addr().unwrap());
+        (endpoint, server.start(module).unwrap())
+    }
+
+    fn slot_verifier(endpoint: String) -> SlotVerifier {
+        let no_retries = RetryConfig {
+            max_retries: 0,
+            ..RetryConfig::default()
+        };
+        SlotVerifier {
+            rpc_client: JsonRpcClient::new_with_retry(endpoint, no_retries),
+            sources: vec![Arc::new(Unavailable)],
+            strategy: SamplingStrategy::default(),
+            leader_schedule: LeaderScheduleCache::new(None, &[]),
+            commitment: CommitmentLevel::Confirmed,
+            deep: false,
+            output: VerifyOutput::Json,
+            checked: 0,
+            failed: 0,
+        }
+    }
+
+    fn sampled(slot: Slot) -> SlotVerdict {
+        SlotVerdict {
+            requested_data_indices: vec![0, 1],
+            requested_coding_indices: vec![0],
+            received_data_indices: vec![0, 1],
+            invalid: 1,
+            rejected: vec![RejectedShred {
+                shred_type: ShredType::Data,
+                index: 1,
+                verdict: SampleVerdict::BadSignature,
+            }],
+            leader: String::from("leader"),
+            confidence: 0.5,
+            commitment: Some(CommitmentLevel::Confirmed),
+            timestamp: 0,
+            verdict: Verdict::Invalid,
+            ..test_verdict(slot)
+        }
+    }
+
+    #[tokio::test]
+    async fn skipped_slots_pass_and_unsampled_ones_fail() {
+        let (endpoint, _server) = cluster(10, vec![6]).await;
+        let mut verifier = slot_verifier(endpoint);
+        verifier.verify_range(5, 7).await.unwrap();
+        assert_eq!((verifier.checked, verifier.failed), (3, 1));
+    }
+
+    #[tokio::test]
+    async fn unknown_blocks_are_all_sampled() {
+        // nothing listens on the discard port, skipped slots can't be told apart
+        let mut verifier = slot_verifier(String::from("http://127.0.0.1:9"));
+        verifier.verify_range(5, 7).await.unwrap();
+        assert_eq!((verifier.checked, verifier.failed), (3, 3));
+    }
+
+    #[tokio::test]
+    async fn follow_stops_past_the_end_slot() {
+        let (endpoint, _server) = cluster(10, vec![]).await;
+        let mut verifier = slot_verifier(endpoint);
+        verifier.follow(Some(8), Some(9)).await.unwrap();
+        assert_eq!((verifier.checked, verifier.failed), (2, 0));
+
+        // without a start slot following begins at the tip
+        let mut verifier = slot_verifier(verifier.rpc_client.endpoint().to_string());
+        verifier.follow(None, Some(10)).await.unwrap();
+        assert_eq!(verifier.checked, 1);
+    }
+
+    #[tokio::test]
+    async fn follow_keeps_polling_when_the_tip_is_unavailable() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let polls = Arc::new(AtomicUsize::new(0));
+        let mut module = RpcModule::new(());
+        let counter = polls.clone();
+        module
+            .register_method("getSlot", move |_, _| {
+                match counter.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(jsonrpsee::core::Error::Custom(String::from("unavailable"))),
+                    _ => Ok(10u64),
+                }
+            })
+            .unwrap();
+        module
+            .register_method("getBlocks", |_, _| Ok(vec![9u64]))
+            .unwrap();
+        let server = ServerBuilder::default().build("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", server.local_addr().unwrap());
+        let _server = server.start(module).unwrap();
+
+        let mut verifier = slot_verifier(endpoint);
+        verifier.follow(Some(9), Some(9)).await.unwrap();
+        assert_eq!(verifier.checked, 1);
+        assert_eq!(polls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn json_records_are_tagged_with_the_status() {
+        assert_eq!(
+            serde_json::to_value(SlotCheck::Skipped { slot: 5 }).unwrap(),
+            serde_json::json!({ "status": "skipped", "slot": 5 })
+        );
+        let record = serde_json::to_value(SlotCheck::Sampled(sampled(5))).unwrap();
+        assert_eq!(record["status"], "sampled");
+        assert_eq!(record["verdict"], "invalid");
+    }
+
+    #[test]
+    fn csv_records_fill_every_column() {
+        let columns = CSV_HEADER.split(',').count();
+        assert_eq!(
+            csv_record(&SlotCheck::Skipped { slot: 5 }),
+            format!("5,skipped{}", ",".repeat(columns - 2))
+        );
+        assert_eq!(
+            csv_record(&SlotCheck::Failed {
+                slot: 5,
+                error: String::from("timed out, \"twice\""),
+            }),
+            format!(
+                "5,failed{},\"timed out, \"\"twice\"\"\"",
+                ",".repeat(columns - 3)
+            )
+        );
+        assert_eq!(
+            csv_record(&SlotCheck::Sampled(sampled(5))),
+            "5,sampled,invalid,0.5000,1,1,leader,,confirmed,0;1,,data:1:badSignature,"
+        );
+        assert_eq!(
+            csv_record(&SlotCheck::Sampled(sampled(5)))
+                .split(',')
+                .count(),
+            columns
+        );
+    }
+}